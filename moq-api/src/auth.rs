@@ -0,0 +1,169 @@
+use std::{io, path};
+
+use axum::http::{header, HeaderMap};
+
+/// The bearer tokens allowed to modify origins, each with a name used in the audit log.
+///
+/// The file contains one `<name> <token> [<scope>...]` entry per line, ignoring empty lines and comments starting with `#`.
+/// Each scope is either a namespace prefix, or an origin URL prefix if it contains `://`.
+/// A token with namespace scopes can only modify matching namespaces, and a token with URL scopes can only register
+/// matching origins and nodes. A token without any scopes can modify anything.
+pub struct Tokens {
+	clients: Vec<(String, Client)>,
+}
+
+/// A client authenticated with a bearer token, and what it's allowed to modify.
+#[derive(Debug, Default, PartialEq)]
+pub struct Client {
+	pub name: String,
+
+	// Namespace prefixes, or any namespace if empty.
+	namespaces: Vec<String>,
+
+	// Origin URL prefixes, or any URL if empty.
+	origins: Vec<String>,
+}
+
+impl Client {
+	/// Returns true if the client can modify origins for this namespace.
+	pub fn allows_namespace(&self, namespace: &str) -> bool {
+		self.namespaces.is_empty()
+			|| self
+				.namespaces
+				.iter()
+				.any(|prefix| namespace.starts_with(prefix.as_str()))
+	}
+
+	/// Returns true if the client can register an origin or node with this URL.
+	pub fn allows_url(&self, url: &url::Url) -> bool {
+		self.origins.is_empty()
+			|| self
+				.origins
+				.iter()
+				.any(|prefix| url.as_str().starts_with(prefix.as_str()))
+	}
+}
+
+impl Tokens {
+	pub async fn load(path: &path::Path) -> io::Result<Self> {
+		let contents = tokio::fs::read_to_string(path).await?;
+		Self::parse(&contents)
+	}
+
+	fn parse(contents: &str) -> io::Result<Self> {
+		let mut clients = Vec::new();
+
+		for (index, line) in contents.lines().enumerate() {
+			let line = line.trim();
+			if line.is_empty() || line.starts_with('#') {
+				continue;
+			}
+
+			let mut fields = line.split_whitespace();
+			let (name, token) = match (fields.next(), fields.next()) {
+				(Some(name), Some(token)) => (name, token),
+				_ => {
+					return Err(io::Error::new(
+						io::ErrorKind::InvalidData,
+						format!("expected <name> <token> [<scope>...] on line {}", index + 1),
+					))
+				}
+			};
+
+			let mut client = Client {
+				name: name.to_string(),
+				..Default::default()
+			};
+
+			for scope in fields {
+				match scope.contains("://") {
+					true => client.origins.push(scope.to_string()),
+					false => client.namespaces.push(scope.to_string()),
+				}
+			}
+
+			if client.namespaces.is_empty() && client.origins.is_empty() {
+				log::warn!("token can modify any origin: name={}", name);
+			}
+
+			clients.push((token.to_string(), client));
+		}
+
+		if clients.is_empty() {
+			return Err(io::Error::new(io::ErrorKind::InvalidData, "no tokens found"));
+		}
+
+		Ok(Self { clients })
+	}
+
+	/// Return the client if the request has a valid `Authorization: Bearer <token>` header.
+	pub fn authenticate(&self, headers: &HeaderMap) -> Option<&Client> {
+		let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+		let token = value.strip_prefix("Bearer ")?.trim();
+
+		// Check every token so the response time doesn't reveal which one was close.
+		let mut found = None;
+		for (expected, client) in &self.clients {
+			if constant_eq(token.as_bytes(), expected.as_bytes()) {
+				found = Some(client);
+			}
+		}
+
+		found
+	}
+}
+
+// Compare two values in constant time, given they're the same length.
+fn constant_eq(a: &[u8], b: &[u8]) -> bool {
+	if a.len() != b.len() {
+		return false;
+	}
+
+	a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn authenticate() {
+		let tokens = Tokens::parse("# relays\nrelay1 secret1\n\nrelay2  secret2\n").unwrap();
+		let name = |headers: &HeaderMap| tokens.authenticate(headers).map(|client| client.name.clone());
+
+		let mut headers = HeaderMap::new();
+		assert_eq!(name(&headers), None);
+
+		headers.insert(header::AUTHORIZATION, "Bearer secret2".parse().unwrap());
+		assert_eq!(name(&headers).as_deref(), Some("relay2"));
+
+		headers.insert(header::AUTHORIZATION, "Bearer secret".parse().unwrap());
+		assert_eq!(name(&headers), None);
+
+		headers.insert(header::AUTHORIZATION, "secret1".parse().unwrap());
+		assert_eq!(name(&headers), None);
+
+		assert!(Tokens::parse("relay1\n").is_err());
+		assert!(Tokens::parse("# empty\n").is_err());
+	}
+
+	#[test]
+	fn scopes() {
+		let tokens = Tokens::parse("relay1 secret1 live/ vod/ https://relay1.example.com/\nadmin secret2\n").unwrap();
+
+		let mut headers = HeaderMap::new();
+		headers.insert(header::AUTHORIZATION, "Bearer secret1".parse().unwrap());
+		let relay = tokens.authenticate(&headers).unwrap();
+
+		assert!(relay.allows_namespace("live/demo"));
+		assert!(relay.allows_namespace("vod/demo"));
+		assert!(!relay.allows_namespace("other/demo"));
+		assert!(relay.allows_url(&"https://relay1.example.com/".parse().unwrap()));
+		assert!(!relay.allows_url(&"https://relay2.example.com/".parse().unwrap()));
+
+		headers.insert(header::AUTHORIZATION, "Bearer secret2".parse().unwrap());
+		let admin = tokens.authenticate(&headers).unwrap();
+		assert!(admin.allows_namespace("other/demo"));
+		assert!(admin.allows_url(&"https://relay2.example.com/".parse().unwrap()));
+	}
+}
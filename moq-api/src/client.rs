@@ -1,5 +1,7 @@
+use std::sync::{Arc, RwLock};
+
 use url::Url;
-use crate::{ApiError, Origin};
+use crate::{ApiError, Lease, Node, NodeEntry, Origin, OriginList, OriginStatus, Routing, Watch, LEASE_HEADER};
 
 #[derive(Clone)]
 pub struct Client {
@@ -7,109 +9,289 @@ pub struct Client {
     url: Url,
 
     client: reqwest::Client,
+
+    // Sent as a bearer token when modifying origins, shared by every clone
+    token: Arc<RwLock<Option<String>>>,
+
+    // Applied to every origin returned by get_origin, shared by every clone
+    routing: Arc<RwLock<Routing>>,
 }
 
 impl Client {
     pub fn new(url: Url) -> Self {
         let client = reqwest::Client::new();
-        Self { url, client }
+        Self {
+            url,
+            client,
+            token: Default::default(),
+            routing: Default::default(),
+        }
     }
 
-    pub async fn get_origin(&self, namespace: &str) -> Result<Option<Origin>, ApiError> {
-        // Step 1: Create a mutable local copy of the URL
-        let mut working_url = self.url.clone();
-        let original_path = working_url.path().to_string();
-        let do_edge = original_path.contains("/do_edge/");
-        let do_regex = original_path.contains("/do_regex/");
-        let mut edge_value = String::new();
-        let mut regex_values = (String::new(), String::new(), String::new());
-
-        println!("Original path: {}", original_path);
-
-        if do_edge {
-            // Extract value after /do_edge/
-            if let Some(edge_pos) = original_path.find("/do_edge/") {
-                edge_value = original_path[edge_pos + 9..].to_string();
-                println!("Captured do_edge value: {}", edge_value);
-            }
-            // Strip the /do_edge/ part from the path for the URL construction
-            let stripped_path = original_path.replace("/do_edge/", "");
-            working_url.set_path(&stripped_path);
-        } else if do_regex {
-            // Extract values for regex replacement and strip the /do_regex/ part
-            if let Some(regex_pos) = original_path.find("/do_regex/") {
-                let regex_parts: Vec<&str> = original_path[regex_pos + 10..].split('/').collect();
-                if regex_parts.len() == 3 {
-                    regex_values = (
-                        regex_parts[0].to_string(),  // e.g., "ohio"
-                        regex_parts[1].to_string(),  // e.g., "origin"
-                        regex_parts[2].to_string(),  // e.g., "regional"
-                    );
-                    println!("Captured do_regex values: {:?}", regex_values);
-                }
-                // Strip the /do_regex/ part from the path for the URL construction
-                let stripped_path = original_path.replace(&format!("/do_regex/{}/{}/{}", regex_values.0, regex_values.1, regex_values.2), "");
-                working_url.set_path(&stripped_path);
-            }
+    /// Authenticate with this bearer token when registering, refreshing or deleting origins.
+    pub fn with_token(mut self, token: String) -> Self {
+        self.token = Arc::new(RwLock::new(Some(token)));
+        self
+    }
+
+    /// Apply this routing policy to every origin returned by [Self::get_origin].
+    pub fn with_routing(mut self, routing: Routing) -> Self {
+        self.routing = Arc::new(RwLock::new(routing));
+        self
+    }
+
+    /// Replace the bearer token used by this client and every clone, applying to any new requests.
+    pub fn set_token(&self, token: Option<String>) {
+        *self.token.write().unwrap() = token;
+    }
+
+    /// Replace the routing policy used by this client and every clone, applying to any new requests.
+    pub fn set_routing(&self, routing: Routing) {
+        *self.routing.write().unwrap() = routing;
+    }
+
+    // Add the bearer token to a request, if configured.
+    fn auth(&self, req: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match &*self.token.read().unwrap() {
+            Some(token) => req.bearer_auth(token),
+            None => req,
         }
+    }
+
+    /// Return the best origin for the namespace, after applying the routing policy.
+    ///
+    /// The server prefers candidates in the region of the routing policy, if any.
+    pub async fn get_origin(&self, namespace: &str) -> Result<Option<Origin>, ApiError> {
+        let url = self.region(self.url.join("origin/")?.join(namespace)?);
 
-        // Step 2: Construct the URL as usual using the local mutable copy
-        let url = working_url.join("origin/")?.join(namespace)?;
-        println!("Constructed URL: {}", url);
         let resp = self.client.get(url).send().await?;
         if resp.status() == reqwest::StatusCode::NOT_FOUND {
             return Ok(None);
         }
 
-        // Step 3: Apply the do_edge or do_regex logic to the JSON response
-        let mut origin: Origin = resp.json().await?;
-        println!("Original URL in response: {}", origin.url);
-
-        if do_edge {
-            origin.url = Url::parse(&format!("https://{}", edge_value))?;
-            println!("Modified URL with do_edge: {}", origin.url);
-        } else if do_regex {
-            // Only modify the URL if the 'contains' value is not found
-            if !origin.url.as_str().contains(&regex_values.0) {
-                if origin.url.as_str().contains(&regex_values.1) {
-                    let new_url = origin.url.as_str().replace(&regex_values.1, &regex_values.2);
-                    origin.url = Url::parse(&new_url)?;
-                    println!("Modified URL with do_regex: {}", origin.url);
-                } else {
-                    println!("No replacement needed, URL remains: {}", origin.url);
-                }
-            } else {
-                println!("'Contains' value found in URL, no modification applied: {}", origin.url);
-            }
-        }
+        let origin: Origin = resp.error_for_status()?.json().await?;
+        let origin = self.routing.read().unwrap().apply(origin)?;
 
         Ok(Some(origin))
     }
 
-    pub async fn set_origin(&self, namespace: &str, origin: Origin) -> Result<(), ApiError> {
+    /// Return every candidate for the namespace from most to least preferred, after applying the routing policy.
+    pub async fn get_candidates(&self, namespace: &str) -> Result<Vec<Origin>, ApiError> {
+        let url = self.region(self.url.join("candidates/")?.join(namespace)?);
+
+        let resp = self.client.get(url).send().await?;
+        if resp.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(Vec::new());
+        }
+
+        let candidates: Vec<Origin> = resp.error_for_status()?.json().await?;
+        let routing = self.routing.read().unwrap();
+        candidates
+            .into_iter()
+            .map(|origin| routing.apply(origin))
+            .collect()
+    }
+
+    // Ask the server to prefer candidates in our region.
+    fn region(&self, mut url: Url) -> Url {
+        if let Some(region) = &self.routing.read().unwrap().region {
+            url.query_pairs_mut().append_pair("region", region);
+        }
+
+        url
+    }
+
+    /// Register the origin as the owner of the namespace, returning a lease that must be used to refresh or delete it.
+    ///
+    /// Registering the same URL again updates the region, capacity and load, returning a new lease that replaces the previous one.
+    /// Returns [ApiError::Claimed] if the namespace is owned by a different URL.
+    pub async fn set_origin(&self, namespace: &str, origin: Origin) -> Result<Lease, ApiError> {
         let url = self.url.join("origin/")?.join(namespace)?;
 
-        let resp = self.client.post(url).json(&origin).send().await?;
-        resp.error_for_status()?;
+        let resp = self.auth(self.client.post(url)).json(&origin).send().await?;
+        if resp.status() == reqwest::StatusCode::CONFLICT {
+            return Err(ApiError::Claimed);
+        }
 
-        Ok(())
+        let lease = resp.error_for_status()?.json().await?;
+
+        Ok(lease)
     }
 
-    pub async fn delete_origin(&self, namespace: &str) -> Result<(), ApiError> {
+    /// Register the origin as a replica of the namespace, using the lease of the owner to authorize it.
+    ///
+    /// Returns a separate lease that must be used to refresh or delete the replica.
+    /// Fails with a 412 Precondition Failed if the owner lease doesn't match, or a 404 Not Found if there's no owner.
+    pub async fn add_replica(&self, namespace: &str, origin: Origin, owner: &Lease) -> Result<Lease, ApiError> {
         let url = self.url.join("origin/")?.join(namespace)?;
 
-        let resp = self.client.delete(url).send().await?;
+        let resp = self
+            .auth(self.client.post(url))
+            .header(LEASE_HEADER, &owner.token)
+            .json(&origin)
+            .send()
+            .await?;
+        let lease = resp.error_for_status()?.json().await?;
+
+        Ok(lease)
+    }
+
+    /// Remove the origin using the lease returned by [Self::set_origin].
+    ///
+    /// Returns false if the origin doesn't exist, for example because it expired,
+    /// or [ApiError::Claimed] if the URL was registered again since.
+    pub async fn delete_origin(&self, namespace: &str, lease: &Lease) -> Result<bool, ApiError> {
+        let url = self.url.join("origin/")?.join(namespace)?;
+
+        let resp = self.auth(self.client.delete(url)).header(LEASE_HEADER, &lease.token).send().await?;
+        if resp.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+
+        if resp.status() == reqwest::StatusCode::CONFLICT {
+            return Err(ApiError::Claimed);
+        }
+
         resp.error_for_status()?;
 
-        Ok(())
+        Ok(true)
     }
 
-    pub async fn patch_origin(&self, namespace: &str, origin: Origin) -> Result<(), ApiError> {
+    /// Reset the expiration of the origin and update its load, using the lease returned by [Self::set_origin].
+    ///
+    /// Returns false if the origin doesn't exist, for example because it expired,
+    /// or [ApiError::Claimed] if the URL was registered again since.
+    pub async fn patch_origin(&self, namespace: &str, lease: &Lease, status: &OriginStatus) -> Result<bool, ApiError> {
         let url = self.url.join("origin/")?.join(namespace)?;
 
-        let resp = self.client.patch(url).json(&origin).send().await?;
+        let resp = self
+            .auth(self.client.patch(url))
+            .header(LEASE_HEADER, &lease.token)
+            .json(status)
+            .send()
+            .await?;
+        if resp.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+
+        if resp.status() == reqwest::StatusCode::CONFLICT {
+            return Err(ApiError::Claimed);
+        }
+
+        resp.error_for_status()?;
+
+        Ok(true)
+    }
+
+    /// List the active origins with a namespace starting with `prefix`, sorted by namespace.
+    ///
+    /// Provide the `next` value of the previous page as `after` to paginate.
+    /// The server returns 100 origins by default and at most 1000.
+    pub async fn list_origins(
+        &self,
+        prefix: &str,
+        after: Option<&str>,
+        limit: Option<usize>,
+    ) -> Result<OriginList, ApiError> {
+        let mut url = self.url.join("origin")?;
+        {
+            let mut query = url.query_pairs_mut();
+            query.append_pair("prefix", prefix);
+
+            if let Some(after) = after {
+                query.append_pair("after", after);
+            }
+
+            if let Some(limit) = limit {
+                query.append_pair("limit", &limit.to_string());
+            }
+        }
+
+        let resp = self.client.get(url).send().await?;
+        let list = resp.error_for_status()?.json().await?;
+
+        Ok(list)
+    }
+
+    /// Stream every change to the candidates of any namespace, resuming after the `after` cursor if provided.
+    ///
+    /// Returns [ApiError::Expired] if the cursor is too old to resume from,
+    /// in which case the caller should assume anything could have changed and watch again without a cursor.
+    /// The routing policy is not applied to the origins in each event.
+    pub async fn watch(&self, after: Option<&str>) -> Result<Watch, ApiError> {
+        let mut url = self.url.join("events")?;
+        if let Some(after) = after {
+            url.query_pairs_mut().append_pair("after", after);
+        }
+
+        let resp = self
+            .client
+            .get(url)
+            .header(reqwest::header::ACCEPT, "text/event-stream")
+            .send()
+            .await?;
+
+        if resp.status() == reqwest::StatusCode::GONE {
+            return Err(ApiError::Expired);
+        }
+
+        Ok(Watch::new(resp.error_for_status()?))
+    }
+
+    /// Register the node or update its status, which must be repeated at least every 30 seconds.
+    pub async fn set_node(&self, node: &Node) -> Result<(), ApiError> {
+        let url = self.url.join("node")?;
+
+        let resp = self.auth(self.client.put(url)).json(node).send().await?;
         resp.error_for_status()?;
 
         Ok(())
     }
+
+    /// Remove the node, so it's no longer selected for new viewers.
+    ///
+    /// Returns false if the node doesn't exist, for example because it expired.
+    pub async fn delete_node(&self, node: &Url) -> Result<bool, ApiError> {
+        let mut url = self.url.join("node")?;
+        url.query_pairs_mut().append_pair("url", node.as_str());
+
+        let resp = self.auth(self.client.delete(url)).send().await?;
+        if resp.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+
+        resp.error_for_status()?;
+
+        Ok(true)
+    }
+
+    /// List every registered node, sorted by URL.
+    pub async fn list_nodes(&self) -> Result<Vec<NodeEntry>, ApiError> {
+        let url = self.url.join("node")?;
+
+        let resp = self.client.get(url).send().await?;
+        let nodes = resp.error_for_status()?.json().await?;
+
+        Ok(nodes)
+    }
+
+    /// Return up to `count` nodes for a new viewer in `region`, from most to least preferred.
+    ///
+    /// Nodes that are full are never returned, so the result is empty if every node is full.
+    pub async fn select_nodes(&self, region: Option<&str>, count: usize) -> Result<Vec<Node>, ApiError> {
+        let mut url = self.url.join("node/select")?;
+        {
+            let mut query = url.query_pairs_mut();
+            query.append_pair("count", &count.to_string());
+
+            if let Some(region) = region {
+                query.append_pair("region", region);
+            }
+        }
+
+        let resp = self.client.get(url).send().await?;
+        let nodes = resp.error_for_status()?.json().await?;
+
+        Ok(nodes)
+    }
 }
@@ -16,4 +16,17 @@ pub enum ApiError {
 
 	#[error("io error: {0}")]
 	Io(#[from] std::io::Error),
+
+	#[error("json error: {0}")]
+	Json(#[from] serde_json::Error),
+
+	/// The cursor is too old to resume from, so the caller needs to fetch the current state again.
+	#[error("cursor expired")]
+	Expired,
+
+	/// The namespace is owned by another origin, or our lease was replaced by registering the same URL again.
+	///
+	/// The caller should stop refreshing instead of registering again, otherwise it would fence off whoever claimed it.
+	#[error("claimed by another relay")]
+	Claimed,
 }
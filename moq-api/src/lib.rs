@@ -1,7 +1,11 @@
 mod client;
 mod error;
 mod model;
+mod routing;
+mod watch;
 
 pub use client::*;
 pub use error::*;
 pub use model::*;
+pub use routing::*;
+pub use watch::*;
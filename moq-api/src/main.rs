@@ -1,6 +1,8 @@
 use clap::Parser;
 
+mod auth;
 mod server;
+mod store;
 use moq_api::ApiError;
 use server::{Server, ServerConfig};
 
@@ -1,8 +1,337 @@
+use std::cmp::Ordering;
+
 use serde::{Deserialize, Serialize};
 
 use url::Url;
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Clone)]
+/// A relay serving a namespace, one of possibly several candidates such as the origin and any regional replicas.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
 pub struct Origin {
 	pub url: Url,
+
+	/// The region of the relay, used to prefer candidates near the subscriber.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub region: Option<String>,
+
+	/// The maximum load the relay will accept, in the same units as `load`.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub capacity: Option<u64>,
+
+	/// The current load of the relay, such as the number of sessions.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub load: Option<u64>,
+}
+
+impl Origin {
+	/// An origin without any region or load metadata.
+	pub fn new(url: Url) -> Self {
+		Self {
+			url,
+			region: None,
+			capacity: None,
+			load: None,
+		}
+	}
+
+	/// True if the relay reported a capacity and its load has reached it.
+	pub fn is_full(&self) -> bool {
+		Usage::new(self.load, self.capacity).is_full()
+	}
+}
+
+/// Sort the candidates for a namespace from most to least preferred for a subscriber in `region`.
+///
+/// Candidates with spare capacity come first, then those in the same region, then the least utilized.
+/// Otherwise the order is preserved, which is the order they were registered.
+pub fn rank(candidates: &mut [Origin], region: Option<&str>) {
+	candidates.sort_by(|a, b| {
+		let usage = |origin: &Origin| Usage::new(origin.load, origin.capacity);
+		let local = |origin: &Origin| region.is_some() && origin.region.as_deref() == region;
+
+		prefer(usage(a), local(a), usage(b), local(b))
+	});
+}
+
+/// A relay registered with moq-api, kept alive by sending a heartbeat.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct Node {
+	/// The URL used to connect to the relay.
+	pub url: Url,
+
+	/// The region of the relay, used to prefer nodes near the viewer.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub region: Option<String>,
+
+	/// The maximum number of sessions the relay will accept.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub capacity: Option<u64>,
+
+	/// The current number of sessions.
+	#[serde(default)]
+	pub sessions: u64,
+
+	/// The measured bandwidth received from publishers, in bytes per second.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub ingress: Option<u64>,
+
+	/// The measured bandwidth sent to subscribers, in bytes per second.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub egress: Option<u64>,
+}
+
+impl Node {
+	/// A node without any region, capacity or load.
+	pub fn new(url: Url) -> Self {
+		Self {
+			url,
+			region: None,
+			capacity: None,
+			sessions: 0,
+			ingress: None,
+			egress: None,
+		}
+	}
+
+	/// True if the relay reported a capacity and its sessions have reached it.
+	pub fn is_full(&self) -> bool {
+		Usage::new(Some(self.sessions), self.capacity).is_full()
+	}
+}
+
+/// An active node, returned when listing nodes.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct NodeEntry {
+	#[serde(flatten)]
+	pub node: Node,
+
+	/// The number of seconds until the node expires, unless it sends another heartbeat.
+	pub ttl: u64,
+}
+
+/// Return up to `count` nodes for a new viewer in `region`, from most to least preferred.
+///
+/// Nodes that are full are never selected. Otherwise, nodes in the same region come first, then the least utilized.
+pub fn select(nodes: &[Node], region: Option<&str>, count: usize) -> Vec<Node> {
+	let mut nodes: Vec<Node> = nodes.iter().filter(|node| !node.is_full()).cloned().collect();
+
+	nodes.sort_by(|a, b| {
+		let usage = |node: &Node| Usage::new(Some(node.sessions), node.capacity);
+		let local = |node: &Node| region.is_some() && node.region.as_deref() == region;
+
+		prefer(usage(a), local(a), usage(b), local(b))
+	});
+
+	nodes.truncate(count);
+	nodes
+}
+
+// The load and capacity of a relay, either of which may be unknown.
+struct Usage {
+	load: Option<u64>,
+	capacity: Option<u64>,
+}
+
+impl Usage {
+	fn new(load: Option<u64>, capacity: Option<u64>) -> Self {
+		Self { load, capacity }
+	}
+
+	fn is_full(&self) -> bool {
+		matches!((self.load, self.capacity), (Some(load), Some(capacity)) if load >= capacity)
+	}
+
+	// The fraction of the capacity in use, or zero if unknown.
+	fn utilization(&self) -> f64 {
+		match (self.load, self.capacity) {
+			(Some(load), Some(capacity)) if capacity > 0 => load as f64 / capacity as f64,
+			(Some(_), Some(_)) => 1.0,
+			_ => 0.0,
+		}
+	}
+}
+
+// Relays with spare capacity come first, then those in the same region, then the least utilized.
+fn prefer(a: Usage, a_local: bool, b: Usage, b_local: bool) -> Ordering {
+	a.is_full()
+		.cmp(&b.is_full())
+		.then_with(|| b_local.cmp(&a_local))
+		.then_with(|| a.utilization().total_cmp(&b.utilization()))
+}
+
+/// Returned when registering an origin, and required to refresh or delete it.
+///
+/// This ensures only the relay that registered the origin can modify it.
+/// Registering the same URL again issues a new lease, so a stale relay can't refresh or delete an origin that was claimed since.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct Lease {
+	pub token: String,
+}
+
+/// The HTTP header used to present a [Lease] when refreshing or deleting an origin.
+pub const LEASE_HEADER: &str = "moq-lease";
+
+/// Optionally sent when refreshing an origin, to update its metadata without registering it again.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug, Default)]
+pub struct OriginStatus {
+	/// The current load of the relay, replacing the previous value if provided.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub load: Option<u64>,
+}
+
+/// An active origin, returned when listing origins.
+///
+/// A namespace with multiple candidates has an entry for each of them.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct OriginEntry {
+	pub namespace: String,
+
+	#[serde(flatten)]
+	pub origin: Origin,
+
+	/// The number of seconds until the origin expires, unless it's refreshed.
+	pub ttl: u64,
+}
+
+/// A page of origins, sorted by namespace.
+///
+/// Every candidate for a namespace is returned on the same page.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug, Default)]
+pub struct OriginList {
+	pub origins: Vec<OriginEntry>,
+
+	/// Provide this as `after` to fetch the next page, or None if this is the last page.
+	pub next: Option<String>,
+}
+
+/// A change to one of the candidates of a namespace, streamed by `GET /events`.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct OriginEvent {
+	/// Provide this as `after` to resume the stream after this event.
+	pub cursor: String,
+
+	pub namespace: String,
+	pub kind: OriginEventKind,
+
+	/// The candidate after the change, or before it was deleted.
+	pub origin: Origin,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum OriginEventKind {
+	/// The candidate was registered, or registered again with new metadata.
+	Set,
+
+	/// The candidate was refreshed, possibly with a new load.
+	Refresh,
+
+	/// The candidate was deleted by the relay that registered it.
+	Delete,
+
+	/// The candidate expired because it wasn't refreshed.
+	Expire,
+}
+
+impl OriginEventKind {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			Self::Set => "set",
+			Self::Refresh => "refresh",
+			Self::Delete => "delete",
+			Self::Expire => "expire",
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn candidate(url: &str, region: &str, load: u64, capacity: u64) -> Origin {
+		Origin {
+			url: url.parse().unwrap(),
+			region: Some(region.to_string()),
+			capacity: Some(capacity),
+			load: Some(load),
+		}
+	}
+
+	#[test]
+	fn ranked() {
+		let ohio = || candidate("https://ohio.example.com", "us-east", 50, 100);
+		let tokyo = || candidate("https://tokyo.example.com", "ap-northeast", 80, 100);
+		let seoul = || candidate("https://seoul.example.com", "ap-northeast", 20, 100);
+		let full = || candidate("https://full.example.com", "ap-northeast", 100, 100);
+
+		let urls = |candidates: &[Origin]| {
+			candidates
+				.iter()
+				.map(|c| c.url.host_str().unwrap().to_string())
+				.collect::<Vec<_>>()
+		};
+
+		let mut candidates = vec![ohio(), tokyo(), full(), seoul()];
+		rank(&mut candidates, Some("ap-northeast"));
+		assert_eq!(
+			urls(&candidates),
+			[
+				"seoul.example.com",
+				"tokyo.example.com",
+				"ohio.example.com",
+				"full.example.com"
+			]
+		);
+
+		// Without a region, only the load is considered.
+		rank(&mut candidates, None);
+		assert_eq!(
+			urls(&candidates),
+			[
+				"seoul.example.com",
+				"ohio.example.com",
+				"tokyo.example.com",
+				"full.example.com"
+			]
+		);
+
+		// Candidates without any metadata keep their registration order.
+		let mut candidates = vec![
+			Origin::new("https://a.example.com".parse().unwrap()),
+			Origin::new("https://b.example.com".parse().unwrap()),
+		];
+		rank(&mut candidates, Some("us-east"));
+		assert_eq!(urls(&candidates), ["a.example.com", "b.example.com"]);
+	}
+
+	#[test]
+	fn selected() {
+		let node = |url: &str, region: &str, sessions: u64| Node {
+			region: Some(region.to_string()),
+			capacity: Some(100),
+			sessions,
+			..Node::new(url.parse().unwrap())
+		};
+
+		let nodes = [
+			node("https://ohio.example.com", "us-east", 10),
+			node("https://tokyo.example.com", "ap-northeast", 90),
+			node("https://seoul.example.com", "ap-northeast", 100),
+			node("https://virginia.example.com", "us-east", 50),
+		];
+
+		let hosts = |nodes: Vec<Node>| {
+			nodes
+				.iter()
+				.map(|n| n.url.host_str().unwrap().to_string())
+				.collect::<Vec<_>>()
+		};
+
+		// Full nodes are never selected, even in the same region.
+		assert_eq!(
+			hosts(select(&nodes, Some("ap-northeast"), 2)),
+			["tokyo.example.com", "ohio.example.com"]
+		);
+		assert_eq!(hosts(select(&nodes, None, 1)), ["ohio.example.com"]);
+		assert_eq!(select(&nodes, None, 10).len(), 3);
+	}
 }
@@ -0,0 +1,124 @@
+use regex::Regex;
+use url::Url;
+
+use crate::{ApiError, Origin};
+
+/// Decides which URL to connect to for an origin returned by moq-api.
+///
+/// The policy is applied in order:
+/// - If `edge` is set, it's always used instead of the origin.
+/// - If the origin is in `region`, or its host contains `region`, it's used as-is.
+/// - Otherwise, each of the `rewrites` is applied to the origin URL.
+#[derive(Clone, Debug, Default)]
+pub struct Routing {
+	/// Connect to this URL for every origin, for example a regional edge relay.
+	pub edge: Option<Url>,
+
+	/// The region of the subscriber, used to prefer nearby candidates.
+	///
+	/// Origins in this region, or with a host containing it, are not rewritten.
+	pub region: Option<String>,
+
+	/// Rewrite the URL of any origins outside of our region, applied in order.
+	pub rewrites: Vec<Rewrite>,
+}
+
+impl Routing {
+	/// Apply the policy to an origin, returning the origin to connect to.
+	pub fn apply(&self, mut origin: Origin) -> Result<Origin, ApiError> {
+		if let Some(edge) = &self.edge {
+			log::debug!("routing to edge: origin={} edge={}", origin.url, edge);
+			origin.url = edge.clone();
+			return Ok(origin);
+		}
+
+		if let Some(region) = &self.region {
+			let local = origin.region.as_ref() == Some(region)
+				|| origin.url.host_str().is_some_and(|host| host.contains(region.as_str()));
+			if local {
+				log::debug!("routing within region: origin={} region={}", origin.url, region);
+				return Ok(origin);
+			}
+		}
+
+		for rewrite in &self.rewrites {
+			let url = rewrite.apply(origin.url.as_str());
+			if url != origin.url.as_str() {
+				log::debug!("rewrote origin: from={} to={} rule={:?}", origin.url, url, rewrite);
+				origin.url = Url::parse(&url)?;
+			}
+		}
+
+		Ok(origin)
+	}
+}
+
+/// Replace every match of a regular expression in the origin URL.
+///
+/// The replacement can refer to capture groups, such as `$1` or `${name}`.
+#[derive(Clone, Debug)]
+pub struct Rewrite {
+	pattern: Regex,
+	replace: String,
+}
+
+impl Rewrite {
+	pub fn new(pattern: &str, replace: &str) -> Result<Self, regex::Error> {
+		Ok(Self {
+			pattern: Regex::new(pattern)?,
+			replace: replace.to_string(),
+		})
+	}
+
+	fn apply(&self, url: &str) -> String {
+		self.pattern.replace_all(url, self.replace.as_str()).into_owned()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn origin(url: &str) -> Origin {
+		Origin::new(url.parse().unwrap())
+	}
+
+	#[test]
+	fn edge() {
+		let routing = Routing {
+			edge: Some("https://edge.example.com".parse().unwrap()),
+			rewrites: vec![Rewrite::new("origin", "regional").unwrap()],
+			..Default::default()
+		};
+
+		let routed = routing.apply(origin("https://origin.ohio.example.com")).unwrap();
+		assert_eq!(routed, origin("https://edge.example.com"));
+	}
+
+	#[test]
+	fn rewrite() {
+		let routing = Routing {
+			region: Some("ohio".to_string()),
+			rewrites: vec![Rewrite::new(r"^https://origin\.(\w+)\.", "https://regional.$1.").unwrap()],
+			..Default::default()
+		};
+
+		// Origins in our region are used as-is.
+		let routed = routing.apply(origin("https://origin.ohio.example.com")).unwrap();
+		assert_eq!(routed, origin("https://origin.ohio.example.com"));
+
+		let routed = routing.apply(origin("https://origin.oregon.example.com")).unwrap();
+		assert_eq!(routed, origin("https://regional.oregon.example.com"));
+
+		// The reported region takes precedence over the host.
+		let tagged = Origin {
+			region: Some("ohio".to_string()),
+			..origin("https://origin.east.example.com")
+		};
+		assert_eq!(routing.apply(tagged.clone()).unwrap(), tagged);
+
+		// No matching rules, so nothing changes.
+		let routed = routing.apply(origin("https://relay.example.com")).unwrap();
+		assert_eq!(routed, origin("https://relay.example.com"));
+	}
+}
@@ -1,30 +1,109 @@
-use std::net;
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::{io, net, path, time};
 
 use axum::{
-	extract::{Path, State},
-	http::StatusCode,
-	response::{IntoResponse, Response},
+	extract::{Path, Query, State},
+	http::{HeaderMap, StatusCode},
+	response::{
+		sse::{Event, KeepAlive, Sse},
+		IntoResponse, Response,
+	},
 	routing::get,
 	Json, Router,
 };
 
 use clap::Parser;
+use futures::Stream;
 
-use redis::{aio::ConnectionManager, AsyncCommands};
+use moq_api::{ApiError, Lease, Node, NodeEntry, Origin, OriginList, OriginStatus, LEASE_HEADER};
+use serde::Deserialize;
 
-use moq_api::{ApiError, Origin};
+use crate::auth::Tokens;
+use crate::store::{FileStore, MemoryStore, RedisStore, Store, StoreError};
+
+// Origins expire after 10 minutes; the origin needs to keep refreshing it.
+const ORIGIN_TTL: time::Duration = time::Duration::from_secs(600);
+
+// Nodes expire after 30 seconds; the relay needs to keep sending a heartbeat.
+const NODE_TTL: time::Duration = time::Duration::from_secs(30);
+
+// How often to remove expired origins, so subscribers are notified promptly.
+const EXPIRE_INTERVAL: time::Duration = time::Duration::from_secs(5);
+
+// The default and maximum number of origins returned when listing.
+const LIST_LIMIT: usize = 100;
+const LIST_LIMIT_MAX: usize = 1000;
 
 /// Runs a HTTP API to create/get origins for broadcasts.
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
+#[command(group(clap::ArgGroup::new("store").required(true).args(["redis", "file", "memory"])))]
 pub struct ServerConfig {
 	/// Listen for HTTP requests on the given address
 	#[arg(long, default_value = "[::]:80")]
 	pub bind: net::SocketAddr,
 
-	/// Connect to the given redis instance
+	/// Store origins in the given redis instance, which can be shared by multiple servers.
+	#[arg(long)]
+	pub redis: Option<url::Url>,
+
+	/// Store origins in a JSON file at the given path, which survives a restart.
 	#[arg(long)]
-	pub redis: url::Url,
+	pub file: Option<path::PathBuf>,
+
+	/// Store origins in memory, which is lost on restart.
+	#[arg(long)]
+	pub memory: bool,
+
+	/// Require a bearer token to register, refresh or delete origins, loaded from the given file.
+	///
+	/// The file contains one `<name> <token> [<scope>...]` entry per line; the name identifies the client in the audit log.
+	/// Each scope restricts the token to a namespace prefix, or an origin URL prefix if it contains `://`.
+	/// If not provided, anybody that can reach the server can modify any origin.
+	#[arg(long)]
+	pub tokens: Option<path::PathBuf>,
+}
+
+#[derive(Clone)]
+struct AppState {
+	store: Arc<dyn Store>,
+	tokens: Option<Arc<Tokens>>,
+}
+
+impl AppState {
+	// Return the name of the authenticated client used for the audit log, logging any rejections.
+	// The client must be allowed to modify the namespace and URL, if provided.
+	fn authenticate(
+		&self,
+		action: &str,
+		namespace: Option<&str>,
+		url: Option<&url::Url>,
+		headers: &HeaderMap,
+	) -> Result<String, AppError> {
+		let tokens = match &self.tokens {
+			Some(tokens) => tokens,
+			None => return Ok("anonymous".to_string()),
+		};
+
+		let client = match tokens.authenticate(headers) {
+			Some(client) => client,
+			None => {
+				log::warn!(target: AUDIT, "{} unauthorized: namespace={:?} url={:?}", action, namespace, url.map(|url| url.as_str()));
+				return Err(AppError::Unauthorized);
+			}
+		};
+
+		let allowed = namespace.is_none_or(|namespace| client.allows_namespace(namespace))
+			&& url.is_none_or(|url| client.allows_url(url));
+
+		if !allowed {
+			log::warn!(target: AUDIT, "{} forbidden: client={} namespace={:?} url={:?}", action, client.name, namespace, url.map(|url| url.as_str()));
+			return Err(AppError::Forbidden);
+		}
+
+		Ok(client.name.clone())
+	}
 }
 
 pub struct Server {
@@ -37,13 +116,29 @@ impl Server {
 	}
 
 	pub async fn run(self) -> Result<(), ApiError> {
-		log::info!("connecting to redis: url={}", self.config.redis);
-
-		// Create the redis client.
-		let redis = redis::Client::open(self.config.redis)?;
-		let redis = redis.get_connection_manager().await?;
+		let store = self.store().await?;
+
+		let tokens = match &self.config.tokens {
+			Some(path) => {
+				let tokens = Tokens::load(path)
+					.await
+					.map_err(|err| io::Error::other(format!("failed to load {}: {}", path.display(), err)))?;
+				Some(Arc::new(tokens))
+			}
+			None => {
+				log::warn!("no --tokens provided, so any client can modify origins");
+				None
+			}
+		};
+
+		tokio::spawn(Self::expire(store.clone()));
 
 		let app = Router::new()
+			.route("/origin", get(list_origins))
+			.route("/candidates/*namespace", get(get_candidates))
+			.route("/events", get(stream_events))
+			.route("/node", get(list_nodes).put(set_node).delete(delete_node))
+			.route("/node/select", get(select_nodes))
 			.route(
 				"/origin/*namespace",
 				get(get_origin)
@@ -51,7 +146,7 @@ impl Server {
 					.delete(delete_origin)
 					.patch(patch_origin),
 			)
-			.with_state(redis);
+			.with_state(AppState { store, tokens });
 
 		log::info!("serving requests: bind={}", self.config.bind);
 
@@ -60,124 +155,300 @@ impl Server {
 
 		Ok(())
 	}
+
+	// Periodically remove expired origins, which publishes an event for each.
+	async fn expire(store: Arc<dyn Store>) {
+		let mut interval = tokio::time::interval(EXPIRE_INTERVAL);
+
+		loop {
+			interval.tick().await;
+
+			if let Err(err) = store.expire().await {
+				log::warn!("failed to expire origins: {}", err);
+			}
+		}
+	}
+
+	async fn store(&self) -> Result<Arc<dyn Store>, ApiError> {
+		if let Some(url) = &self.config.redis {
+			log::info!("connecting to redis: url={}", url);
+			return Ok(Arc::new(RedisStore::connect(url.clone()).await?));
+		}
+
+		if let Some(path) = &self.config.file {
+			log::info!("using file store: path={}", path.display());
+			let store = FileStore::open(path.clone())
+				.await
+				.map_err(|err| io::Error::other(format!("failed to open {}: {}", path.display(), err)))?;
+			return Ok(Arc::new(store));
+		}
+
+		log::info!("using memory store");
+		Ok(Arc::new(MemoryStore::new()))
+	}
+}
+
+#[derive(Deserialize)]
+struct OriginQuery {
+	/// Prefer candidates in the region of the subscriber.
+	region: Option<String>,
 }
 
+// Return the best candidate for the subscriber.
 async fn get_origin(
 	Path(namespace): Path<String>,
-	State(mut redis): State<ConnectionManager>,
+	State(state): State<AppState>,
+	Query(query): Query<OriginQuery>,
 ) -> Result<Json<Origin>, AppError> {
-	let key = origin_key(&namespace);
-
-	let payload: Option<String> = redis.get(&key).await?;
-	let payload = payload.ok_or(AppError::NotFound)?;
-	let origin: Origin = serde_json::from_str(&payload)?;
+	let mut candidates = state.store.get(&namespace).await?;
+	moq_api::rank(&mut candidates, query.region.as_deref());
 
+	let origin = candidates.into_iter().next().ok_or(StoreError::NotFound)?;
 	Ok(Json(origin))
 }
 
-async fn set_origin(
-	State(mut redis): State<ConnectionManager>,
+// Return every candidate, from most to least preferred for the subscriber.
+async fn get_candidates(
 	Path(namespace): Path<String>,
-	Json(origin): Json<Origin>,
-) -> Result<(), AppError> {
-	// TODO validate origin
+	State(state): State<AppState>,
+	Query(query): Query<OriginQuery>,
+) -> Result<Json<Vec<Origin>>, AppError> {
+	let mut candidates = state.store.get(&namespace).await?;
+	if candidates.is_empty() {
+		return Err(StoreError::NotFound.into());
+	}
 
-	let key = origin_key(&namespace);
+	moq_api::rank(&mut candidates, query.region.as_deref());
+	Ok(Json(candidates))
+}
 
-	// Convert the input back to JSON after validating it add adding any fields (TODO)
-	let payload = serde_json::to_string(&origin)?;
+async fn set_origin(
+	State(state): State<AppState>,
+	Path(namespace): Path<String>,
+	headers: HeaderMap,
+	Json(origin): Json<Origin>,
+) -> Result<Json<Lease>, AppError> {
+	let client = state.authenticate("set", Some(&namespace), Some(&origin.url), &headers)?;
 
-	// Attempt to get the current value for the key
-	let current: Option<String> = redis::cmd("GET").arg(&key).query_async(&mut redis).await?;
+	// A replica presents the lease of the owner, otherwise only the owner can register.
+	// Registering the same URL again requires its current lease, whether or not tokens are used.
+	let owner = headers.get(LEASE_HEADER).and_then(|value| value.to_str().ok());
 
-	if let Some(current) = &current {
-		if current.eq(&payload) {
-			// The value is the same, so we're done.
-			return Ok(());
-		} else {
-			return Err(AppError::Duplicate);
+	// TODO validate origin
+	match state.store.set(&namespace, &origin, owner, ORIGIN_TTL).await {
+		Ok(lease) => {
+			log::info!(target: AUDIT, "set origin: client={} namespace={} url={}", client, namespace, origin.url);
+			Ok(Json(lease))
+		}
+		Err(err) => {
+			log::info!(target: AUDIT, "set failed: client={} namespace={} url={} error={}", client, namespace, origin.url, err);
+			Err(err.into())
 		}
 	}
-
-	let res: Option<String> = redis::cmd("SET")
-		.arg(key)
-		.arg(payload)
-		.arg("NX")
-		.arg("EX")
-		.arg(600) // Set the key to expire in 10 minutes; the origin needs to keep refreshing it.
-		.query_async(&mut redis)
-		.await?;
-
-	if res.is_none() {
-		return Err(AppError::Duplicate);
-	}
-
-	Ok(())
 }
 
 async fn delete_origin(
 	Path(namespace): Path<String>,
-	State(mut redis): State<ConnectionManager>,
+	State(state): State<AppState>,
+	headers: HeaderMap,
 ) -> Result<(), AppError> {
-	let key = origin_key(&namespace);
-	match redis.del(key).await? {
-		0 => Err(AppError::NotFound),
-		_ => Ok(()),
+	let client = state.authenticate("delete", Some(&namespace), None, &headers)?;
+
+	match state.store.delete(&namespace, lease(&headers)?).await {
+		Ok(()) => {
+			log::info!(target: AUDIT, "deleted origin: client={} namespace={}", client, namespace);
+			Ok(())
+		}
+		Err(err) => {
+			log::info!(target: AUDIT, "delete failed: client={} namespace={} error={}", client, namespace, err);
+			Err(err.into())
+		}
 	}
 }
 
-// Update the expiration deadline.
+// Update the expiration deadline, and the load if a body is provided.
 async fn patch_origin(
 	Path(namespace): Path<String>,
-	State(mut redis): State<ConnectionManager>,
-	Json(origin): Json<Origin>,
+	State(state): State<AppState>,
+	headers: HeaderMap,
+	status: Option<Json<OriginStatus>>,
 ) -> Result<(), AppError> {
-	let key = origin_key(&namespace);
+	let client = state.authenticate("refresh", Some(&namespace), None, &headers)?;
+
+	// Reset the timeout to 10 minutes, as long as the lease matches.
+	// Successful refreshes aren't audited since they happen constantly.
+	let status = status.map(|Json(status)| status).unwrap_or_default();
+	if let Err(err) = state
+		.store
+		.refresh(&namespace, lease(&headers)?, &status, ORIGIN_TTL)
+		.await
+	{
+		log::info!(target: AUDIT, "refresh failed: client={} namespace={} error={}", client, namespace, err);
+		return Err(err.into());
+	}
 
-	// Make sure the contents haven't changed
-	// TODO make a LUA script to do this all in one operation.
-	let payload: Option<String> = redis.get(&key).await?;
-	let payload = payload.ok_or(AppError::NotFound)?;
-	let expected: Origin = serde_json::from_str(&payload)?;
+	Ok(())
+}
 
-	if expected != origin {
-		return Err(AppError::Duplicate);
-	}
+#[derive(Deserialize)]
+struct ListQuery {
+	/// Only return namespaces starting with this prefix.
+	#[serde(default)]
+	prefix: String,
+
+	/// Only return namespaces sorted after this one, used for pagination.
+	after: Option<String>,
+
+	/// The maximum number of origins to return.
+	limit: Option<usize>,
+}
+
+async fn list_origins(
+	State(state): State<AppState>,
+	Query(query): Query<ListQuery>,
+) -> Result<Json<OriginList>, AppError> {
+	let limit = query.limit.unwrap_or(LIST_LIMIT).clamp(1, LIST_LIMIT_MAX);
+	let list = state.store.list(&query.prefix, query.after.as_deref(), limit).await?;
+	Ok(Json(list))
+}
+
+#[derive(Deserialize)]
+struct EventsQuery {
+	/// Resume after the event with this cursor, otherwise only new events are streamed.
+	after: Option<String>,
+}
+
+// Stream every change to the candidates as server-sent events.
+async fn stream_events(
+	State(state): State<AppState>,
+	Query(query): Query<EventsQuery>,
+	headers: HeaderMap,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, AppError> {
+	// EventSource reconnects using the Last-Event-ID header instead.
+	let after = query.after.or_else(|| {
+		headers
+			.get("last-event-id")
+			.and_then(|value| value.to_str().ok())
+			.map(String::from)
+	});
+
+	let subscription = state.store.events().subscribe(after.as_deref())?;
+
+	let stream = futures::stream::unfold(subscription, |mut subscription| async move {
+		// End the stream if the subscriber fell behind; it can resume from the last cursor it received.
+		let event = subscription.next().await.ok()?;
+		let sse = Event::default()
+			.id(&event.cursor)
+			.event(event.kind.as_str())
+			.json_data(&event)
+			.ok()?;
+
+		Some((Ok(sse), subscription))
+	});
+
+	Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
 
-	// Reset the timeout to 10 minutes.
-	match redis.expire(key, 600).await? {
-		0 => Err(AppError::NotFound),
-		_ => Ok(()),
+// Register the node or update its status, used as a heartbeat.
+async fn set_node(State(state): State<AppState>, headers: HeaderMap, Json(node): Json<Node>) -> Result<(), AppError> {
+	let client = state.authenticate("heartbeat", None, Some(&node.url), &headers)?;
+
+	// Heartbeats aren't audited since they happen constantly, only new nodes.
+	if state.store.set_node(&node, &client, NODE_TTL).await? {
+		log::info!(target: AUDIT, "registered node: client={} url={} region={:?}", client, node.url, node.region);
 	}
+
+	Ok(())
+}
+
+#[derive(Deserialize)]
+struct NodeQuery {
+	url: url::Url,
+}
+
+async fn delete_node(
+	State(state): State<AppState>,
+	Query(query): Query<NodeQuery>,
+	headers: HeaderMap,
+) -> Result<(), AppError> {
+	let client = state.authenticate("delete node", None, Some(&query.url), &headers)?;
+
+	state.store.delete_node(&query.url, &client).await?;
+	log::info!(target: AUDIT, "deleted node: client={} url={}", client, query.url);
+
+	Ok(())
 }
 
-fn origin_key(namespace: &str) -> String {
-	format!("origin.{}", namespace)
+async fn list_nodes(State(state): State<AppState>) -> Result<Json<Vec<NodeEntry>>, AppError> {
+	Ok(Json(state.store.nodes().await?))
 }
 
+#[derive(Deserialize)]
+struct SelectQuery {
+	/// Prefer nodes in the region of the viewer.
+	region: Option<String>,
+
+	/// The maximum number of nodes to return [default: 1]
+	count: Option<usize>,
+}
+
+// Return the best nodes for a new viewer, excluding any that are full.
+async fn select_nodes(
+	State(state): State<AppState>,
+	Query(query): Query<SelectQuery>,
+) -> Result<Json<Vec<Node>>, AppError> {
+	let nodes: Vec<Node> = state.store.nodes().await?.into_iter().map(|entry| entry.node).collect();
+	let count = query.count.unwrap_or(1).clamp(1, LIST_LIMIT_MAX);
+
+	Ok(Json(moq_api::select(&nodes, query.region.as_deref(), count)))
+}
+
+// Return the lease token provided when the origin was registered.
+fn lease(headers: &HeaderMap) -> Result<&str, StoreError> {
+	headers
+		.get(LEASE_HEADER)
+		.and_then(|value| value.to_str().ok())
+		.ok_or(StoreError::Lease)
+}
+
+// The log target for changes to origins, so they can be filtered with RUST_LOG.
+const AUDIT: &str = "moq_api::audit";
+
 #[derive(thiserror::Error, Debug)]
 enum AppError {
-	#[error("redis error")]
-	Redis(#[from] redis::RedisError),
-
-	#[error("json error")]
-	Json(#[from] serde_json::Error),
+	#[error(transparent)]
+	Store(#[from] StoreError),
 
-	#[error("not found")]
-	NotFound,
+	#[error("unauthorized")]
+	Unauthorized,
 
-	#[error("duplicate ID")]
-	Duplicate,
+	#[error("forbidden")]
+	Forbidden,
 }
 
 // Tell axum how to convert `AppError` into a response.
 impl IntoResponse for AppError {
 	fn into_response(self) -> Response {
 		match self {
-			AppError::Redis(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("redis error: {}", e)).into_response(),
-			AppError::Json(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("json error: {}", e)).into_response(),
-			AppError::NotFound => StatusCode::NOT_FOUND.into_response(),
-			AppError::Duplicate => StatusCode::CONFLICT.into_response(),
+			AppError::Store(StoreError::Redis(e)) => {
+				(StatusCode::INTERNAL_SERVER_ERROR, format!("redis error: {}", e)).into_response()
+			}
+			AppError::Store(StoreError::Json(e)) => {
+				(StatusCode::INTERNAL_SERVER_ERROR, format!("json error: {}", e)).into_response()
+			}
+			AppError::Store(StoreError::Io(e)) => {
+				(StatusCode::INTERNAL_SERVER_ERROR, format!("io error: {}", e)).into_response()
+			}
+			AppError::Store(StoreError::NotFound) => StatusCode::NOT_FOUND.into_response(),
+			AppError::Store(StoreError::Duplicate) => StatusCode::CONFLICT.into_response(),
+			AppError::Store(StoreError::Lease) => StatusCode::PRECONDITION_FAILED.into_response(),
+			AppError::Store(StoreError::Fenced) => (StatusCode::CONFLICT, "lease replaced").into_response(),
+			AppError::Store(StoreError::Cursor) => (StatusCode::GONE, "cursor expired").into_response(),
+			AppError::Store(StoreError::Owner) => {
+				(StatusCode::FORBIDDEN, "node registered by another client").into_response()
+			}
+			AppError::Unauthorized => StatusCode::UNAUTHORIZED.into_response(),
+			AppError::Forbidden => StatusCode::FORBIDDEN.into_response(),
 		}
 	}
 }
@@ -0,0 +1,184 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use tokio::sync::broadcast;
+
+use moq_api::{Origin, OriginEvent, OriginEventKind};
+
+use super::StoreError;
+
+// The number of recent events kept in memory, so subscribers can resume from a cursor.
+const RECENT: usize = 1024;
+
+/// Fans out changes to every subscriber, keeping recent events so a subscriber can resume from a cursor.
+pub struct Events {
+	state: Mutex<EventsState>,
+
+	// None tells subscribers that events were missed.
+	live: broadcast::Sender<Option<OriginEvent>>,
+
+	// Included in each cursor we assign, so cursors from before a restart are rejected instead of misinterpreted.
+	epoch: String,
+}
+
+struct EventsState {
+	recent: VecDeque<OriginEvent>,
+	sequence: u64,
+}
+
+impl Events {
+	pub fn new() -> Self {
+		let (live, _) = broadcast::channel(RECENT);
+
+		Self {
+			state: Mutex::new(EventsState {
+				recent: VecDeque::with_capacity(RECENT),
+				sequence: 0,
+			}),
+			live,
+			epoch: hex::encode(rand::random::<[u8; 4]>()),
+		}
+	}
+
+	/// Record a change made by this server, assigning the next cursor.
+	pub fn publish(&self, kind: OriginEventKind, namespace: &str, origin: Origin) {
+		let mut state = self.state.lock().unwrap();
+		state.sequence += 1;
+
+		let event = OriginEvent {
+			cursor: format!("{}-{}", self.epoch, state.sequence),
+			namespace: namespace.to_string(),
+			kind,
+			origin,
+		};
+
+		Self::send(&mut state, &self.live, event);
+	}
+
+	/// Record a change with a cursor assigned elsewhere, such as by a shared store.
+	pub fn push(&self, event: OriginEvent) {
+		let mut state = self.state.lock().unwrap();
+		Self::send(&mut state, &self.live, event);
+	}
+
+	/// Tell every subscriber that events may have been missed, for example because the shared stream was trimmed.
+	///
+	/// The recent events are forgotten and subscribers get [StoreError::Cursor], so they fetch the current state again.
+	pub fn gap(&self) {
+		let mut state = self.state.lock().unwrap();
+		state.recent.clear();
+
+		// An error just means there are no subscribers.
+		self.live.send(None).ok();
+	}
+
+	// Called while holding the lock, so subscribers never miss or duplicate an event.
+	fn send(state: &mut EventsState, live: &broadcast::Sender<Option<OriginEvent>>, event: OriginEvent) {
+		if state.recent.len() == RECENT {
+			state.recent.pop_front();
+		}
+
+		state.recent.push_back(event.clone());
+
+		// An error just means there are no subscribers.
+		live.send(Some(event)).ok();
+	}
+
+	/// Subscribe to any events after the cursor, or only new events if None.
+	///
+	/// Returns [StoreError::Cursor] if the cursor is unknown, for example because it's too old.
+	pub fn subscribe(&self, after: Option<&str>) -> Result<Subscription, StoreError> {
+		let state = self.state.lock().unwrap();
+
+		let backlog = match after {
+			Some(after) => {
+				let index = state
+					.recent
+					.iter()
+					.position(|event| event.cursor == after)
+					.ok_or(StoreError::Cursor)?;
+				state.recent.iter().skip(index + 1).cloned().collect()
+			}
+			None => VecDeque::new(),
+		};
+
+		Ok(Subscription {
+			backlog,
+			live: self.live.subscribe(),
+		})
+	}
+}
+
+/// The events after a cursor, followed by any new events.
+pub struct Subscription {
+	backlog: VecDeque<OriginEvent>,
+	live: broadcast::Receiver<Option<OriginEvent>>,
+}
+
+impl Subscription {
+	/// Wait for the next event.
+	///
+	/// Returns [StoreError::Cursor] if the subscriber fell too far behind or events were otherwise missed.
+	pub async fn next(&mut self) -> Result<OriginEvent, StoreError> {
+		if let Some(event) = self.backlog.pop_front() {
+			return Ok(event);
+		}
+
+		match self.live.recv().await {
+			Ok(Some(event)) => Ok(event),
+			Ok(None) | Err(broadcast::error::RecvError::Lagged(_)) => Err(StoreError::Cursor),
+			// The sender is owned by the store, which lives as long as the server.
+			Err(broadcast::error::RecvError::Closed) => std::future::pending().await,
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn origin() -> Origin {
+		Origin::new("https://a.example.com".parse().unwrap())
+	}
+
+	#[tokio::test]
+	async fn resume() {
+		let events = Events::new();
+		let mut live = events.subscribe(None).unwrap();
+
+		events.publish(OriginEventKind::Set, "live/a", origin());
+		events.publish(OriginEventKind::Refresh, "live/a", origin());
+		events.publish(OriginEventKind::Delete, "live/a", origin());
+
+		let first = live.next().await.unwrap();
+		assert_eq!(first.kind, OriginEventKind::Set);
+
+		// Resuming after the first event replays the rest.
+		let mut resumed = events.subscribe(Some(&first.cursor)).unwrap();
+		assert_eq!(resumed.next().await.unwrap().kind, OriginEventKind::Refresh);
+		assert_eq!(resumed.next().await.unwrap().kind, OriginEventKind::Delete);
+
+		events.publish(OriginEventKind::Set, "live/b", origin());
+		assert_eq!(resumed.next().await.unwrap().namespace, "live/b");
+
+		assert!(matches!(events.subscribe(Some("unknown")), Err(StoreError::Cursor)));
+	}
+
+	#[tokio::test]
+	async fn gap() {
+		let events = Events::new();
+		let mut live = events.subscribe(None).unwrap();
+
+		events.publish(OriginEventKind::Set, "live/a", origin());
+		let first = live.next().await.unwrap();
+
+		// Subscribers are told they missed events, and can't resume from before the gap.
+		events.gap();
+		assert!(matches!(live.next().await, Err(StoreError::Cursor)));
+		assert!(matches!(events.subscribe(Some(&first.cursor)), Err(StoreError::Cursor)));
+
+		let mut live = events.subscribe(None).unwrap();
+		events.publish(OriginEventKind::Delete, "live/a", origin());
+		assert_eq!(live.next().await.unwrap().kind, OriginEventKind::Delete);
+	}
+}
@@ -0,0 +1,186 @@
+use std::{io, path, time};
+
+use tokio::sync::Mutex;
+
+use moq_api::{Lease, Node, NodeEntry, Origin, OriginEventKind, OriginList, OriginStatus};
+use url::Url;
+
+use super::{Events, Nodes, Origins, Store, StoreError};
+
+/// Stores the candidate origins in a JSON file, so they survive a restart of a single API server.
+///
+/// This is a plain JSON file rather than an embedded database like SQLite, so it doesn't need a native dependency.
+/// The file is read on startup and rewritten after every change, so it's not intended for a large number of origins.
+pub struct FileStore {
+	path: path::PathBuf,
+	origins: Mutex<Origins>,
+	nodes: std::sync::Mutex<Nodes>,
+	events: Events,
+}
+
+impl FileStore {
+	/// Load the origins from the file, which is created on the first change if it doesn't exist.
+	pub async fn open(path: path::PathBuf) -> Result<Self, StoreError> {
+		let origins = match tokio::fs::read(&path).await {
+			Ok(contents) => serde_json::from_slice(&contents)?,
+			Err(err) if err.kind() == io::ErrorKind::NotFound => Origins::default(),
+			Err(err) => return Err(err.into()),
+		};
+
+		Ok(Self {
+			path,
+			origins: Mutex::new(origins),
+			nodes: Default::default(),
+			events: Events::new(),
+		})
+	}
+
+	// Write to a temporary file and rename it, so the file is never partially written.
+	async fn save(&self, origins: &Origins) -> Result<(), StoreError> {
+		let contents = serde_json::to_vec(origins)?;
+
+		let mut temp = self.path.clone().into_os_string();
+		temp.push(".tmp");
+
+		tokio::fs::write(&temp, contents).await?;
+		tokio::fs::rename(&temp, &self.path).await?;
+
+		Ok(())
+	}
+
+	// Apply a change to a copy of the origins, which only replaces them once the file is saved.
+	async fn update<T>(&self, change: impl FnOnce(&mut Origins) -> Result<T, StoreError>) -> Result<T, StoreError> {
+		let mut origins = self.origins.lock().await;
+
+		// Expired candidates are gone whether or not the change is saved.
+		origins.expire();
+		origins.publish_expired(&self.events);
+
+		let mut updated = origins.clone();
+		let res = change(&mut updated)?;
+		self.save(&updated).await?;
+
+		updated.publish_expired(&self.events);
+		*origins = updated;
+
+		Ok(res)
+	}
+}
+
+#[async_trait::async_trait]
+impl Store for FileStore {
+	async fn get(&self, namespace: &str) -> Result<Vec<Origin>, StoreError> {
+		Ok(self.origins.lock().await.get(namespace))
+	}
+
+	async fn set(
+		&self,
+		namespace: &str,
+		origin: &Origin,
+		owner: Option<&str>,
+		ttl: time::Duration,
+	) -> Result<Lease, StoreError> {
+		let lease = self
+			.update(|origins| origins.set(namespace, origin, owner, ttl))
+			.await?;
+		self.events.publish(OriginEventKind::Set, namespace, origin.clone());
+
+		Ok(lease)
+	}
+
+	async fn refresh(
+		&self,
+		namespace: &str,
+		lease: &str,
+		status: &OriginStatus,
+		ttl: time::Duration,
+	) -> Result<(), StoreError> {
+		let origin = self
+			.update(|origins| origins.refresh(namespace, lease, status, ttl))
+			.await?;
+		self.events.publish(OriginEventKind::Refresh, namespace, origin);
+
+		Ok(())
+	}
+
+	async fn delete(&self, namespace: &str, lease: &str) -> Result<(), StoreError> {
+		let origin = self.update(|origins| origins.delete(namespace, lease)).await?;
+		self.events.publish(OriginEventKind::Delete, namespace, origin);
+
+		Ok(())
+	}
+
+	async fn list(&self, prefix: &str, after: Option<&str>, limit: usize) -> Result<OriginList, StoreError> {
+		Ok(self.origins.lock().await.list(prefix, after, limit))
+	}
+
+	// Expired candidates are ignored when loading, so there's no need to save the file.
+	async fn expire(&self) -> Result<(), StoreError> {
+		let mut origins = self.origins.lock().await;
+		origins.expire();
+		origins.publish_expired(&self.events);
+
+		Ok(())
+	}
+
+	fn events(&self) -> &Events {
+		&self.events
+	}
+
+	// Nodes are only kept in memory, since they're quickly registered again after a restart.
+	async fn set_node(&self, node: &Node, client: &str, ttl: time::Duration) -> Result<bool, StoreError> {
+		self.nodes.lock().unwrap().set(node, client, ttl)
+	}
+
+	async fn delete_node(&self, url: &Url, client: &str) -> Result<(), StoreError> {
+		self.nodes.lock().unwrap().delete(url, client)
+	}
+
+	async fn nodes(&self) -> Result<Vec<NodeEntry>, StoreError> {
+		Ok(self.nodes.lock().unwrap().list())
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[tokio::test]
+	async fn persist() {
+		let path = std::env::temp_dir().join(format!("moq-api-{}.json", std::process::id()));
+		let ttl = time::Duration::from_secs(600);
+		let origin = Origin::new("https://a.example.com".parse().unwrap());
+
+		let store = FileStore::open(path.clone()).await.unwrap();
+		let lease = store.set("live", &origin, None, ttl).await.unwrap();
+		let other = store.set("other", &origin, None, ttl).await.unwrap();
+		store.delete("other", &other.token).await.unwrap();
+		drop(store);
+
+		let store = FileStore::open(path.clone()).await.unwrap();
+		assert_eq!(store.get("live").await.unwrap(), [origin]);
+		assert_eq!(store.get("other").await.unwrap(), []);
+		store
+			.refresh("live", &lease.token, &OriginStatus::default(), ttl)
+			.await
+			.unwrap();
+
+		std::fs::remove_file(path).unwrap();
+	}
+
+	#[tokio::test]
+	async fn unsaved() {
+		// The directory doesn't exist, so every change fails to save.
+		let path = std::env::temp_dir()
+			.join(format!("moq-api-missing-{}", std::process::id()))
+			.join("origins.json");
+		let origin = Origin::new("https://a.example.com".parse().unwrap());
+
+		let store = FileStore::open(path).await.unwrap();
+		let res = store.set("live", &origin, None, time::Duration::from_secs(600)).await;
+		assert!(matches!(res, Err(StoreError::Io(_))));
+
+		// The change isn't visible, since it was never saved.
+		assert_eq!(store.get("live").await.unwrap(), []);
+	}
+}
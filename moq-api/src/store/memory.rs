@@ -0,0 +1,511 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time;
+
+use serde::{Deserialize, Serialize};
+
+use moq_api::{Lease, Node, NodeEntry, Origin, OriginEntry, OriginEventKind, OriginList, OriginStatus};
+use url::Url;
+
+use super::{Events, Nodes, Store, StoreError};
+
+/// Stores origins in memory, intended for development, tests and single node deployments.
+///
+/// Everything is lost when the server restarts.
+pub struct MemoryStore {
+	origins: Mutex<Origins>,
+	nodes: Mutex<Nodes>,
+	events: Events,
+}
+
+impl MemoryStore {
+	pub fn new() -> Self {
+		Self {
+			origins: Default::default(),
+			nodes: Default::default(),
+			events: Events::new(),
+		}
+	}
+}
+
+#[async_trait::async_trait]
+impl Store for MemoryStore {
+	async fn get(&self, namespace: &str) -> Result<Vec<Origin>, StoreError> {
+		Ok(self.origins.lock().unwrap().get(namespace))
+	}
+
+	async fn set(
+		&self,
+		namespace: &str,
+		origin: &Origin,
+		owner: Option<&str>,
+		ttl: time::Duration,
+	) -> Result<Lease, StoreError> {
+		let mut origins = self.origins.lock().unwrap();
+		let res = origins.set(namespace, origin, owner, ttl);
+		origins.publish_expired(&self.events);
+
+		let lease = res?;
+		self.events.publish(OriginEventKind::Set, namespace, origin.clone());
+
+		Ok(lease)
+	}
+
+	async fn refresh(
+		&self,
+		namespace: &str,
+		lease: &str,
+		status: &OriginStatus,
+		ttl: time::Duration,
+	) -> Result<(), StoreError> {
+		let mut origins = self.origins.lock().unwrap();
+		let res = origins.refresh(namespace, lease, status, ttl);
+		origins.publish_expired(&self.events);
+
+		let origin = res?;
+		self.events.publish(OriginEventKind::Refresh, namespace, origin);
+
+		Ok(())
+	}
+
+	async fn delete(&self, namespace: &str, lease: &str) -> Result<(), StoreError> {
+		let mut origins = self.origins.lock().unwrap();
+		let res = origins.delete(namespace, lease);
+		origins.publish_expired(&self.events);
+
+		let origin = res?;
+		self.events.publish(OriginEventKind::Delete, namespace, origin);
+
+		Ok(())
+	}
+
+	async fn list(&self, prefix: &str, after: Option<&str>, limit: usize) -> Result<OriginList, StoreError> {
+		Ok(self.origins.lock().unwrap().list(prefix, after, limit))
+	}
+
+	async fn expire(&self) -> Result<(), StoreError> {
+		let mut origins = self.origins.lock().unwrap();
+		origins.expire();
+		origins.publish_expired(&self.events);
+
+		Ok(())
+	}
+
+	fn events(&self) -> &Events {
+		&self.events
+	}
+
+	async fn set_node(&self, node: &Node, client: &str, ttl: time::Duration) -> Result<bool, StoreError> {
+		self.nodes.lock().unwrap().set(node, client, ttl)
+	}
+
+	async fn delete_node(&self, url: &Url, client: &str) -> Result<(), StoreError> {
+		self.nodes.lock().unwrap().delete(url, client)
+	}
+
+	async fn nodes(&self) -> Result<Vec<NodeEntry>, StoreError> {
+		Ok(self.nodes.lock().unwrap().list())
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Entry {
+	origin: Origin,
+	lease: String,
+	expires: time::SystemTime,
+
+	// The most recently replaced leases, newest first.
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	fenced: Vec<String>,
+}
+
+/// The candidates of each namespace along with when they expire, shared by the in-process stores.
+#[derive(Serialize, Deserialize, Default, Clone)]
+#[serde(transparent)]
+pub(super) struct Origins {
+	entries: HashMap<String, Vec<Entry>>,
+
+	// Candidates removed by [Self::expire] that haven't been published yet.
+	#[serde(skip)]
+	expired: Vec<(String, Origin)>,
+}
+
+impl Origins {
+	pub fn get(&self, namespace: &str) -> Vec<Origin> {
+		let now = time::SystemTime::now();
+
+		self.entries
+			.get(namespace)
+			.into_iter()
+			.flatten()
+			.filter(|entry| entry.expires > now)
+			.map(|entry| entry.origin.clone())
+			.collect()
+	}
+
+	pub fn set(
+		&mut self,
+		namespace: &str,
+		origin: &Origin,
+		owner: Option<&str>,
+		ttl: time::Duration,
+	) -> Result<Lease, StoreError> {
+		self.expire();
+
+		let expires = time::SystemTime::now() + ttl;
+		let lease = super::lease();
+
+		let candidates = self.entries.get_mut(namespace);
+		let first = candidates
+			.as_ref()
+			.and_then(|candidates| candidates.first())
+			.map(|entry| entry.lease.clone());
+
+		if let Some(current) =
+			candidates.and_then(|candidates| candidates.iter_mut().find(|entry| entry.origin.url == origin.url))
+		{
+			// Registering the same URL again requires its current lease, or the owner's lease for a replica.
+			match owner {
+				None => return Err(StoreError::Duplicate),
+				Some(owner) if owner == current.lease || Some(owner) == first.as_deref() => {}
+				Some(owner) if current.fenced.iter().any(|fenced| fenced == owner) => return Err(StoreError::Fenced),
+				Some(_) => return Err(StoreError::Lease),
+			}
+
+			// Claimed again, so issue a new lease to fence off whoever held the previous one.
+			let previous = std::mem::replace(&mut current.lease, lease.token.clone());
+			current.fenced.insert(0, previous);
+			current.fenced.truncate(super::FENCED_MAX);
+			current.origin = origin.clone();
+			current.expires = expires;
+
+			return Ok(lease);
+		}
+
+		// Candidates are kept in the order they were registered, so the first is the owner.
+		match (
+			self.entries.get(namespace).and_then(|candidates| candidates.first()),
+			owner,
+		) {
+			(None, None) => {}
+			(None, Some(_)) => return Err(StoreError::NotFound),
+			(Some(_), None) => return Err(StoreError::Duplicate),
+			(Some(first), Some(owner)) if first.lease != owner => return Err(StoreError::Lease),
+			(Some(_), Some(_)) => {}
+		}
+
+		self.entries.entry(namespace.to_string()).or_default().push(Entry {
+			origin: origin.clone(),
+			lease: lease.token.clone(),
+			expires,
+			fenced: Vec::new(),
+		});
+
+		Ok(lease)
+	}
+
+	pub fn refresh(
+		&mut self,
+		namespace: &str,
+		lease: &str,
+		status: &OriginStatus,
+		ttl: time::Duration,
+	) -> Result<Origin, StoreError> {
+		self.expire();
+
+		let entry = match self
+			.entries
+			.get_mut(namespace)
+			.and_then(|candidates| candidates.iter_mut().find(|entry| entry.lease == lease))
+		{
+			Some(entry) => entry,
+			None => return Err(self.missing(namespace, lease)),
+		};
+
+		entry.expires = time::SystemTime::now() + ttl;
+		if let Some(load) = status.load {
+			entry.origin.load = Some(load);
+		}
+
+		Ok(entry.origin.clone())
+	}
+
+	pub fn delete(&mut self, namespace: &str, lease: &str) -> Result<Origin, StoreError> {
+		self.expire();
+
+		let index = self
+			.entries
+			.get(namespace)
+			.and_then(|candidates| candidates.iter().position(|entry| entry.lease == lease));
+		let index = match index {
+			Some(index) => index,
+			None => return Err(self.missing(namespace, lease)),
+		};
+
+		let candidates = self.entries.get_mut(namespace).unwrap();
+		let entry = candidates.remove(index);
+		if candidates.is_empty() {
+			self.entries.remove(namespace);
+		}
+
+		Ok(entry.origin)
+	}
+
+	pub fn list(&self, prefix: &str, after: Option<&str>, limit: usize) -> OriginList {
+		let now = time::SystemTime::now();
+
+		let mut namespaces: Vec<String> = self
+			.entries
+			.iter()
+			.filter(|(namespace, candidates)| {
+				namespace.starts_with(prefix) && candidates.iter().any(|entry| entry.expires > now)
+			})
+			.map(|(namespace, _)| namespace.clone())
+			.collect();
+		namespaces.sort();
+
+		let (page, next) = super::paginate(&namespaces, after, limit);
+
+		let origins = page
+			.iter()
+			.flat_map(|namespace| {
+				self.entries[namespace]
+					.iter()
+					.filter(move |entry| entry.expires > now)
+					.map(move |entry| OriginEntry {
+						namespace: namespace.clone(),
+						origin: entry.origin.clone(),
+						ttl: entry.expires.duration_since(now).unwrap_or_default().as_secs(),
+					})
+			})
+			.collect();
+
+		OriginList { origins, next }
+	}
+
+	// The error for a lease that doesn't match any candidate, depending on whether it was replaced.
+	fn missing(&self, namespace: &str, lease: &str) -> StoreError {
+		let fenced = self
+			.entries
+			.get(namespace)
+			.into_iter()
+			.flatten()
+			.any(|entry| entry.fenced.iter().any(|fenced| fenced == lease));
+
+		match fenced {
+			true => StoreError::Fenced,
+			false => StoreError::NotFound,
+		}
+	}
+
+	// Remove any expired candidates, and any namespaces without candidates.
+	pub fn expire(&mut self) {
+		let now = time::SystemTime::now();
+		let expired = &mut self.expired;
+
+		self.entries.retain(|namespace, candidates| {
+			candidates.retain(|entry| {
+				if entry.expires > now {
+					return true;
+				}
+
+				expired.push((namespace.clone(), entry.origin.clone()));
+				false
+			});
+
+			!candidates.is_empty()
+		});
+	}
+
+	/// Publish an event for each candidate that expired since the last call.
+	pub fn publish_expired(&mut self, events: &Events) {
+		for (namespace, origin) in self.expired.drain(..) {
+			events.publish(OriginEventKind::Expire, &namespace, origin);
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn origin(url: &str) -> Origin {
+		Origin::new(url.parse().unwrap())
+	}
+
+	#[tokio::test]
+	async fn origins() {
+		let store = MemoryStore::new();
+		let ttl = time::Duration::from_secs(600);
+		let a = origin("https://a.example.com");
+		let b = origin("https://b.example.com");
+
+		assert_eq!(store.get("live").await.unwrap(), []);
+		let stale = store.set("live", &a, None, ttl).await.unwrap();
+
+		// The same URL can't be registered again without proving it holds the lease.
+		assert!(matches!(
+			store.set("live", &a, None, ttl).await,
+			Err(StoreError::Duplicate)
+		));
+		assert!(matches!(
+			store.set("live", &a, Some("wrong"), ttl).await,
+			Err(StoreError::Lease)
+		));
+
+		let lease = store.set("live", &a, Some(&stale.token), ttl).await.unwrap();
+		assert_ne!(lease, stale);
+		assert_eq!(store.get("live").await.unwrap(), vec![a.clone()]);
+
+		// Claiming the same URL again fences off the previous lease.
+		assert!(matches!(
+			store.set("live", &a, Some(&stale.token), ttl).await,
+			Err(StoreError::Fenced)
+		));
+		let status = OriginStatus::default();
+		assert!(matches!(
+			store.refresh("live", &stale.token, &status, ttl).await,
+			Err(StoreError::Fenced)
+		));
+		assert!(matches!(
+			store.delete("live", &stale.token).await,
+			Err(StoreError::Fenced)
+		));
+		assert_eq!(store.get("live").await.unwrap(), vec![a.clone()]);
+
+		// A different URL can only be added as a replica using the lease of the owner.
+		assert!(matches!(
+			store.set("live", &b, None, ttl).await,
+			Err(StoreError::Duplicate)
+		));
+		assert!(matches!(
+			store.set("live", &b, Some(&stale.token), ttl).await,
+			Err(StoreError::Lease)
+		));
+		assert!(matches!(
+			store.set("other", &b, Some(&lease.token), ttl).await,
+			Err(StoreError::NotFound)
+		));
+
+		// A replica is another candidate with its own lease.
+		let replica = store.set("live", &b, Some(&lease.token), ttl).await.unwrap();
+		assert_ne!(replica, lease);
+		assert_eq!(store.get("live").await.unwrap(), [a.clone(), b.clone()]);
+
+		// Setting the same URL again updates the metadata.
+		let loaded = Origin {
+			load: Some(10),
+			..a.clone()
+		};
+		let lease = store.set("live", &loaded, Some(&lease.token), ttl).await.unwrap();
+		assert_eq!(store.get("live").await.unwrap(), [loaded, b.clone()]);
+
+		let status = OriginStatus { load: Some(20) };
+		store.refresh("live", &lease.token, &status, ttl).await.unwrap();
+		assert_eq!(store.get("live").await.unwrap()[0].load, Some(20));
+
+		assert!(matches!(
+			store.refresh("live", "wrong", &status, ttl).await,
+			Err(StoreError::NotFound)
+		));
+		assert!(matches!(
+			store.refresh("other", &lease.token, &status, ttl).await,
+			Err(StoreError::NotFound)
+		));
+
+		assert!(matches!(store.delete("live", "wrong").await, Err(StoreError::NotFound)));
+		store.delete("live", &lease.token).await.unwrap();
+		assert!(matches!(
+			store.delete("live", &lease.token).await,
+			Err(StoreError::NotFound)
+		));
+		assert_eq!(store.get("live").await.unwrap(), [b]);
+
+		// The replica is the owner once the original is gone.
+		let c = origin("https://c.example.com");
+		assert!(matches!(
+			store.set("live", &c, Some(&lease.token), ttl).await,
+			Err(StoreError::Lease)
+		));
+		let other = store.set("live", &c, Some(&replica.token), ttl).await.unwrap();
+
+		store.delete("live", &replica.token).await.unwrap();
+		store.delete("live", &other.token).await.unwrap();
+		assert_eq!(store.get("live").await.unwrap(), []);
+	}
+
+	#[tokio::test]
+	async fn list() {
+		let store = MemoryStore::new();
+		let ttl = time::Duration::from_secs(600);
+		let a = origin("https://a.example.com");
+
+		let mut leases = HashMap::new();
+		for namespace in ["live/c", "live/a", "vod/a", "live/b"] {
+			leases.insert(namespace, store.set(namespace, &a, None, ttl).await.unwrap());
+		}
+
+		// Every candidate for a namespace is on the same page.
+		let b = origin("https://b.example.com");
+		store
+			.set("live/b", &b, Some(&leases["live/b"].token), ttl)
+			.await
+			.unwrap();
+
+		let page = store.list("live/", None, 2).await.unwrap();
+		let namespaces: Vec<_> = page.origins.iter().map(|entry| entry.namespace.as_str()).collect();
+		assert_eq!(namespaces, ["live/a", "live/b", "live/b"]);
+		assert_eq!(page.origins[2].origin, b);
+		assert_eq!(page.origins[0].origin, a);
+		assert!(page.origins[0].ttl > 590);
+		assert_eq!(page.next.as_deref(), Some("live/b"));
+
+		let page = store.list("live/", page.next.as_deref(), 2).await.unwrap();
+		assert_eq!(page.origins.len(), 1);
+		assert_eq!(page.origins[0].namespace, "live/c");
+		assert_eq!(page.next, None);
+
+		assert_eq!(store.list("", None, 10).await.unwrap().origins.len(), 5);
+	}
+
+	#[tokio::test]
+	async fn expires() {
+		let store = MemoryStore::new();
+		let a = origin("https://a.example.com");
+
+		let lease = store.set("live", &a, None, time::Duration::ZERO).await.unwrap();
+		assert_eq!(store.get("live").await.unwrap(), []);
+
+		// Expired candidates are reported once they're removed, either by another change or periodically.
+		let mut events = store.events().subscribe(None).unwrap();
+		assert!(matches!(
+			store
+				.refresh("live", &lease.token, &OriginStatus::default(), time::Duration::ZERO)
+				.await,
+			Err(StoreError::NotFound)
+		));
+
+		let event = events.next().await.unwrap();
+		assert_eq!(event.kind, OriginEventKind::Expire);
+		assert_eq!((event.namespace.as_str(), &event.origin), ("live", &a));
+
+		store.set("other", &a, None, time::Duration::ZERO).await.unwrap();
+		assert_eq!(events.next().await.unwrap().kind, OriginEventKind::Set);
+		store.expire().await.unwrap();
+		store.expire().await.unwrap();
+		let event = events.next().await.unwrap();
+		assert_eq!(
+			(event.kind, event.namespace.as_str()),
+			(OriginEventKind::Expire, "other")
+		);
+
+		// An expired origin is registered again with a new lease.
+		let replaced = store
+			.set("live", &a, None, time::Duration::from_secs(600))
+			.await
+			.unwrap();
+		assert_ne!(replaced, lease);
+		assert_eq!(store.get("live").await.unwrap(), [a]);
+		assert_eq!(events.next().await.unwrap().kind, OriginEventKind::Set);
+	}
+}
@@ -0,0 +1,137 @@
+mod events;
+mod file;
+mod memory;
+mod nodes;
+mod redis;
+
+pub use self::redis::*;
+pub use events::*;
+pub use file::*;
+pub use memory::*;
+use nodes::Nodes;
+
+use std::time;
+
+use moq_api::{Lease, Node, NodeEntry, Origin, OriginList, OriginStatus};
+use url::Url;
+
+/// Persists the candidate origins of each namespace, expiring them unless refreshed.
+///
+/// Each candidate is identified by its URL and has its own lease and expiration.
+#[async_trait::async_trait]
+pub trait Store: Send + Sync {
+	/// Return every candidate for the namespace that hasn't expired, in the order they were registered.
+	async fn get(&self, namespace: &str) -> Result<Vec<Origin>, StoreError>;
+
+	/// Atomically add the origin as a candidate for the namespace, expiring after `ttl`.
+	///
+	/// The first candidate is the owner of the namespace. Any other URL is a [StoreError::Duplicate],
+	/// unless it's a replica presenting the `owner` lease, which must be the lease of the oldest candidate.
+	///
+	/// Returns a new lease that must be used to refresh or delete the candidate.
+	/// Setting an origin with the same URL again requires presenting its current lease, or the owner's lease for a replica,
+	/// otherwise it's a [StoreError::Duplicate], or [StoreError::Fenced] for a replaced lease.
+	/// It updates the metadata and resets the expiration, but returns a new lease so the previous one can no longer be used.
+	async fn set(
+		&self,
+		namespace: &str,
+		origin: &Origin,
+		owner: Option<&str>,
+		ttl: time::Duration,
+	) -> Result<Lease, StoreError>;
+
+	/// Atomically reset the expiration of the candidate with this lease to `ttl`, updating the load if provided.
+	///
+	/// Returns [StoreError::Fenced] if the lease was replaced by registering the same URL again,
+	/// so the caller knows to stop instead of registering again.
+	async fn refresh(
+		&self,
+		namespace: &str,
+		lease: &str,
+		status: &OriginStatus,
+		ttl: time::Duration,
+	) -> Result<(), StoreError>;
+
+	/// Atomically remove the candidate with this lease, or return [StoreError::Fenced] if the lease was replaced.
+	async fn delete(&self, namespace: &str, lease: &str) -> Result<(), StoreError>;
+
+	/// Return the candidates of up to `limit` namespaces sorted by namespace, starting with `prefix` and sorted after `after`.
+	async fn list(&self, prefix: &str, after: Option<&str>, limit: usize) -> Result<OriginList, StoreError>;
+
+	/// Remove every expired candidate, publishing an event for each.
+	///
+	/// Expired candidates are never returned, but they're only removed and reported when their namespace changes,
+	/// so this is called periodically to report them promptly.
+	async fn expire(&self) -> Result<(), StoreError>;
+
+	/// Every change made by [Self::set], [Self::refresh], [Self::delete] and [Self::expire], including those made by other servers.
+	fn events(&self) -> &Events;
+
+	/// Register the node or update its status, expiring after `ttl` unless it sends another heartbeat.
+	///
+	/// Returns true if the node wasn't already registered, or [StoreError::Owner] if it was registered by another client.
+	async fn set_node(&self, node: &Node, client: &str, ttl: time::Duration) -> Result<bool, StoreError>;
+
+	/// Remove the node with this URL, only allowed for the client that registered it.
+	async fn delete_node(&self, url: &Url, client: &str) -> Result<(), StoreError>;
+
+	/// Return every node that hasn't expired, sorted by URL.
+	async fn nodes(&self) -> Result<Vec<NodeEntry>, StoreError>;
+}
+
+// Return a page of namespaces from a sorted list, along with the cursor for the next page.
+fn paginate<'a>(sorted: &'a [String], after: Option<&str>, limit: usize) -> (&'a [String], Option<String>) {
+	// Skip past any namespaces up to and including the cursor.
+	let start = match after {
+		Some(after) => sorted.partition_point(|namespace| namespace.as_str() <= after),
+		None => 0,
+	};
+
+	let sorted = &sorted[start..];
+	let limit = limit.max(1);
+
+	match sorted.len() > limit {
+		true => (&sorted[..limit], Some(sorted[limit - 1].clone())),
+		false => (sorted, None),
+	}
+}
+
+// The number of replaced leases remembered for each candidate, so their holders are told they were fenced.
+const FENCED_MAX: usize = 4;
+
+// Generate a random lease token.
+fn lease() -> Lease {
+	Lease {
+		token: hex::encode(rand::random::<[u8; 16]>()),
+	}
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum StoreError {
+	#[error("redis error: {0}")]
+	Redis(#[from] ::redis::RedisError),
+
+	#[error("json error: {0}")]
+	Json(#[from] serde_json::Error),
+
+	#[error("io error: {0}")]
+	Io(#[from] std::io::Error),
+
+	#[error("not found")]
+	NotFound,
+
+	#[error("duplicate ID")]
+	Duplicate,
+
+	#[error("lease mismatch")]
+	Lease,
+
+	#[error("lease replaced")]
+	Fenced,
+
+	#[error("cursor expired")]
+	Cursor,
+
+	#[error("node registered by another client")]
+	Owner,
+}
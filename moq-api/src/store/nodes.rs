@@ -0,0 +1,113 @@
+use std::collections::BTreeMap;
+use std::time;
+
+use moq_api::{Node, NodeEntry};
+use url::Url;
+
+use super::StoreError;
+
+/// The registered relays along with when they expire, shared by the in-process stores.
+///
+/// Nodes aren't persisted, since they send a heartbeat far more often than the server restarts.
+#[derive(Default)]
+pub(super) struct Nodes {
+	entries: BTreeMap<Url, Entry>,
+}
+
+struct Entry {
+	node: Node,
+
+	// The client that registered the node, the only one allowed to update or remove it until it expires.
+	client: String,
+	expires: time::SystemTime,
+}
+
+impl Nodes {
+	pub fn set(&mut self, node: &Node, client: &str, ttl: time::Duration) -> Result<bool, StoreError> {
+		self.expire();
+
+		if let Some(entry) = self.entries.get(&node.url) {
+			if entry.client != client {
+				return Err(StoreError::Owner);
+			}
+		}
+
+		let entry = Entry {
+			node: node.clone(),
+			client: client.to_string(),
+			expires: time::SystemTime::now() + ttl,
+		};
+
+		Ok(self.entries.insert(node.url.clone(), entry).is_none())
+	}
+
+	pub fn delete(&mut self, url: &Url, client: &str) -> Result<(), StoreError> {
+		self.expire();
+
+		match self.entries.get(url) {
+			None => Err(StoreError::NotFound),
+			Some(entry) if entry.client != client => Err(StoreError::Owner),
+			Some(_) => {
+				self.entries.remove(url);
+				Ok(())
+			}
+		}
+	}
+
+	pub fn list(&self) -> Vec<NodeEntry> {
+		let now = time::SystemTime::now();
+
+		self.entries
+			.values()
+			.filter_map(|entry| {
+				let ttl = entry.expires.duration_since(now).ok()?;
+				Some(NodeEntry {
+					node: entry.node.clone(),
+					ttl: ttl.as_secs(),
+				})
+			})
+			.collect()
+	}
+
+	fn expire(&mut self) {
+		let now = time::SystemTime::now();
+		self.entries.retain(|_, entry| entry.expires > now);
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn heartbeat() {
+		let mut nodes = Nodes::default();
+		let ttl = time::Duration::from_secs(30);
+		let mut node = Node::new("https://b.example.com".parse().unwrap());
+
+		assert!(nodes.set(&node, "relay", ttl).unwrap());
+		node.sessions = 10;
+		assert!(!nodes.set(&node, "relay", ttl).unwrap());
+		assert!(nodes
+			.set(&Node::new("https://a.example.com".parse().unwrap()), "other", ttl)
+			.unwrap());
+
+		// Sorted by URL, with the latest heartbeat.
+		let list = nodes.list();
+		assert_eq!(list.len(), 2);
+		assert_eq!(list[0].node.url.as_str(), "https://a.example.com/");
+		assert_eq!(list[1].node.sessions, 10);
+
+		// Only the client that registered a node can update or remove it.
+		assert!(matches!(nodes.set(&node, "other", ttl), Err(StoreError::Owner)));
+		assert!(matches!(nodes.delete(&node.url, "other"), Err(StoreError::Owner)));
+
+		nodes.delete(&node.url, "relay").unwrap();
+		assert!(matches!(nodes.delete(&node.url, "relay"), Err(StoreError::NotFound)));
+
+		// Expired nodes aren't listed, and can be registered by another client.
+		nodes.set(&node, "relay", time::Duration::ZERO).unwrap();
+		assert_eq!(nodes.list().len(), 1);
+		assert!(nodes.set(&node, "other", ttl).unwrap());
+	}
+}
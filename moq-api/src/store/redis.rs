@@ -0,0 +1,751 @@
+use std::sync::Arc;
+use std::time;
+
+use redis::{
+	aio::ConnectionManager,
+	streams::{StreamId, StreamRangeReply, StreamReadOptions, StreamReadReply},
+	AsyncCommands, Script, ScriptInvocation,
+};
+use serde::Deserialize;
+
+use moq_api::{Lease, Node, NodeEntry, Origin, OriginEntry, OriginEvent, OriginEventKind, OriginList, OriginStatus};
+use url::Url;
+
+use super::{Events, Store, StoreError};
+
+// Functions, keys and the current time shared by every script.
+// Each candidate is a field in the hash of its namespace, keyed by URL, containing the origin, lease and expiration.
+// Every namespace with a hash is a member of a sorted set, so they can be listed in order without scanning,
+// and every candidate is in another sorted set scored by its expiration, so expired candidates can be found and reported.
+const PRELUDE: &str = r"
+-- Every script is passed the same keys, where KEYS[1] is the hash of the namespace if any.
+local EVENTS, INDEX, EXPIRY = KEYS[2], KEYS[3], KEYS[4]
+-- The hash outlives its last candidate, so the expiration is reported before the hash disappears.
+local GRACE = 60
+-- Identify a candidate in the expiry queue.
+local function member(namespace, url)
+	return cjson.encode({ namespace, url })
+end
+-- Append to the stream of events, trimming it to roughly 10,000 entries.
+local function publish(kind, namespace, origin)
+	redis.call('XADD', EVENTS, 'MAXLEN', '~', 10000, '*', 'namespace', namespace, 'kind', kind, 'origin', origin)
+end
+-- Convert an origin stored as a string by an older version into a candidate, keeping its expiration.
+-- It has no lease, so it can't be refreshed or deleted, but registering the same URL again replaces it.
+local function migrate(key, namespace, now)
+	if redis.call('TYPE', key).ok ~= 'string' then
+		return
+	end
+	local origin = redis.call('GET', key)
+	local ttl = redis.call('TTL', key)
+	redis.call('DEL', key)
+	if ttl == -1 then
+		ttl = 600
+	elseif ttl <= 0 then
+		return
+	end
+	local url = cjson.decode(origin).url
+	local entry = { origin = origin, lease = '', expires = now + ttl, created = now }
+	redis.call('HSET', key, url, cjson.encode(entry))
+	redis.call('EXPIRE', key, ttl + GRACE)
+	redis.call('ZADD', INDEX, 0, namespace)
+	redis.call('ZADD', EXPIRY, entry.expires, member(namespace, url))
+end
+-- Remove any expired candidates, publishing an event for each, and expire the hash after the last one.
+-- The namespace is removed from the index once it has no candidates.
+local function prune(key, namespace, now)
+	local latest = 0
+	local fields = redis.call('HGETALL', key)
+	for i = 1, #fields, 2 do
+		local entry = cjson.decode(fields[i + 1])
+		if entry.expires <= now then
+			redis.call('HDEL', key, fields[i])
+			redis.call('ZREM', EXPIRY, member(namespace, fields[i]))
+			publish('expire', namespace, entry.origin)
+		elseif entry.expires > latest then
+			latest = entry.expires
+		end
+	end
+	if latest > 0 then
+		redis.call('EXPIRE', key, latest - now + GRACE)
+	else
+		redis.call('ZREM', INDEX, namespace)
+	end
+end
+-- Return -1 if the lease was replaced by registering the same URL again, otherwise 0 since it doesn't exist.
+local function missing(key, lease)
+	local fields = redis.call('HGETALL', key)
+	for i = 2, #fields, 2 do
+		for _, fenced in ipairs(cjson.decode(fields[i]).fenced or {}) do
+			if fenced == lease then
+				return -1
+			end
+		end
+	end
+	return 0
+end
+local now = tonumber(redis.call('TIME')[1])
+";
+
+// The scripts below modify a single namespace: KEYS[1] is the hash of candidates and ARGV[1] is the namespace.
+
+// Return every field of the hash, after migrating it if needed.
+const GET: &str = r"
+migrate(KEYS[1], ARGV[1], now)
+return redis.call('HGETALL', KEYS[1])
+";
+
+// Add or update the candidate with this URL, replacing any previous lease so it can no longer be used.
+// Returns {1, lease} on success, or {0, ''} if there's no owner, {-1, ''} if the namespace or URL is owned,
+// {-2, ''} if the lease doesn't match and {-3, ''} if the lease was replaced.
+const SET: &str = r"
+migrate(KEYS[1], ARGV[1], now)
+prune(KEYS[1], ARGV[1], now)
+local created = now
+local fenced = {}
+
+-- The oldest candidate owns the namespace.
+local owner = nil
+local fields = redis.call('HGETALL', KEYS[1])
+for i = 1, #fields, 2 do
+	local entry = cjson.decode(fields[i + 1])
+	if owner == nil or entry.created < owner.created or (entry.created == owner.created and fields[i] < owner.url) then
+		owner = { url = fields[i], lease = entry.lease, created = entry.created }
+	end
+end
+
+local current = redis.call('HGET', KEYS[1], ARGV[2])
+if current then
+	-- Registering the same URL again requires its current lease, or the owner's lease for a replica.
+	local previous = cjson.decode(current)
+	if ARGV[6] == '' then
+		return { -1, '' }
+	elseif ARGV[6] ~= previous.lease and ARGV[6] ~= owner.lease then
+		for _, replaced in ipairs(previous.fenced or {}) do
+			if replaced == ARGV[6] then
+				return { -3, '' }
+			end
+		end
+		return { -2, '' }
+	end
+
+	-- Remember the replaced lease, so its holder is told it was fenced.
+	created = previous.created
+	fenced = previous.fenced or {}
+	if previous.lease ~= '' then
+		table.insert(fenced, 1, previous.lease)
+	end
+	while #fenced > tonumber(ARGV[7]) do
+		table.remove(fenced)
+	end
+else
+	-- Any other URL must be a replica presenting the owner's lease.
+	if owner == nil then
+		if ARGV[6] ~= '' then
+			return { 0, '' }
+		end
+	elseif ARGV[6] == '' then
+		return { -1, '' }
+	elseif owner.lease == '' or owner.lease ~= ARGV[6] then
+		return { -2, '' }
+	end
+end
+local entry = { origin = ARGV[3], lease = ARGV[4], expires = now + tonumber(ARGV[5]), created = created, fenced = fenced }
+redis.call('HSET', KEYS[1], ARGV[2], cjson.encode(entry))
+redis.call('ZADD', INDEX, 0, ARGV[1])
+redis.call('ZADD', EXPIRY, entry.expires, member(ARGV[1], ARGV[2]))
+prune(KEYS[1], ARGV[1], now)
+publish('set', ARGV[1], ARGV[3])
+return { 1, ARGV[4] }
+";
+
+// Reset the expiration of the candidate with this lease and update the load if not empty.
+// Returns 0 if not found, or -1 if the lease was replaced.
+const REFRESH: &str = r"
+migrate(KEYS[1], ARGV[1], now)
+local fields = redis.call('HGETALL', KEYS[1])
+for i = 1, #fields, 2 do
+	local entry = cjson.decode(fields[i + 1])
+	if entry.lease ~= '' and entry.lease == ARGV[2] and entry.expires > now then
+		entry.expires = now + tonumber(ARGV[3])
+		if ARGV[4] ~= '' then
+			local origin = cjson.decode(entry.origin)
+			origin.load = tonumber(ARGV[4])
+			entry.origin = cjson.encode(origin)
+		end
+		redis.call('HSET', KEYS[1], fields[i], cjson.encode(entry))
+		redis.call('ZADD', EXPIRY, entry.expires, member(ARGV[1], fields[i]))
+		prune(KEYS[1], ARGV[1], now)
+		publish('refresh', ARGV[1], entry.origin)
+		return 1
+	end
+end
+return missing(KEYS[1], ARGV[2])
+";
+
+// Remove the candidate with this lease, returning 0 if not found or -1 if the lease was replaced.
+const DELETE: &str = r"
+migrate(KEYS[1], ARGV[1], now)
+local fields = redis.call('HGETALL', KEYS[1])
+for i = 1, #fields, 2 do
+	local entry = cjson.decode(fields[i + 1])
+	if entry.lease ~= '' and entry.lease == ARGV[2] and entry.expires > now then
+		redis.call('HDEL', KEYS[1], fields[i])
+		redis.call('ZREM', EXPIRY, member(ARGV[1], fields[i]))
+		prune(KEYS[1], ARGV[1], now)
+		publish('delete', ARGV[1], entry.origin)
+		return 1
+	end
+end
+return missing(KEYS[1], ARGV[2])
+";
+
+// Page through the index in order, returning {more, {namespace, fields, ...}} for up to ARGV[3] namespaces.
+// KEYS[1] isn't used, ARGV[1] is the prefix, ARGV[2] is the namespace to start after (or empty) and ARGV[4] is the key prefix.
+// Namespaces whose hash expired are removed from the index as they're found.
+const LIST: &str = r"
+local prefix = ARGV[1]
+local limit = tonumber(ARGV[3])
+local min = '[' .. prefix
+if ARGV[2] ~= '' and ARGV[2] >= prefix then
+	min = '(' .. ARGV[2]
+end
+local page = {}
+local count = 0
+while true do
+	local namespaces = redis.call('ZRANGEBYLEX', INDEX, min, '+', 'LIMIT', 0, 100)
+	if #namespaces == 0 then
+		return { 0, page }
+	end
+	for _, namespace in ipairs(namespaces) do
+		if string.sub(namespace, 1, #prefix) ~= prefix then
+			return { 0, page }
+		end
+		local key = ARGV[4] .. namespace
+		migrate(key, namespace, now)
+		prune(key, namespace, now)
+		local fields = redis.call('HGETALL', key)
+		if #fields > 0 then
+			if count == limit then
+				return { 1, page }
+			end
+			count = count + 1
+			table.insert(page, namespace)
+			table.insert(page, fields)
+		end
+		min = '(' .. namespace
+	end
+end
+";
+
+// Prune up to ARGV[2] namespaces with a candidate that's due to expire, which publishes an event for each.
+// KEYS[1] isn't used and ARGV[1] is the key prefix. Returns the number of candidates that were due.
+const SWEEP: &str = r"
+local due = redis.call('ZRANGEBYSCORE', EXPIRY, '-inf', now, 'LIMIT', 0, tonumber(ARGV[2]))
+for _, candidate in ipairs(due) do
+	local namespace = cjson.decode(candidate)[1]
+	prune(ARGV[1] .. namespace, namespace, now)
+	-- Also remove candidates that are already gone, for example because the hash was deleted.
+	redis.call('ZREM', EXPIRY, candidate)
+end
+return #due
+";
+
+// The scripts below modify the hash of nodes in KEYS[1], where each field is keyed by URL and contains the node,
+// the client that registered it and the expiration. Fields in a hash can't expire, so expired nodes are removed by the scripts.
+
+// Register or update the node in ARGV[2] with URL ARGV[1] for the client in ARGV[3], expiring after ARGV[4] seconds.
+// Returns 1 if added, 0 if updated, or -1 if it's registered by another client.
+const SET_NODE: &str = r"
+local current = redis.call('HGET', KEYS[1], ARGV[1])
+local added = 1
+if current then
+	local previous = cjson.decode(current)
+	if previous.expires > now then
+		if previous.client ~= ARGV[3] then
+			return -1
+		end
+		added = 0
+	end
+end
+local entry = { node = ARGV[2], client = ARGV[3], expires = now + tonumber(ARGV[4]) }
+redis.call('HSET', KEYS[1], ARGV[1], cjson.encode(entry))
+return added
+";
+
+// Remove the node with URL ARGV[1] registered by the client in ARGV[2].
+// Returns 1 if removed, 0 if not found, or -1 if it's registered by another client.
+const DELETE_NODE: &str = r"
+local current = redis.call('HGET', KEYS[1], ARGV[1])
+if not current then
+	return 0
+end
+local entry = cjson.decode(current)
+if entry.expires <= now then
+	redis.call('HDEL', KEYS[1], ARGV[1])
+	return 0
+elseif entry.client ~= ARGV[2] then
+	return -1
+end
+redis.call('HDEL', KEYS[1], ARGV[1])
+return 1
+";
+
+// Return {node, ttl, ...} for every node that hasn't expired, removing the rest.
+const NODES: &str = r"
+local nodes = {}
+local fields = redis.call('HGETALL', KEYS[1])
+for i = 1, #fields, 2 do
+	local entry = cjson.decode(fields[i + 1])
+	if entry.expires <= now then
+		redis.call('HDEL', KEYS[1], fields[i])
+	else
+		table.insert(nodes, entry.node)
+		table.insert(nodes, entry.expires - now)
+	end
+end
+return nodes
+";
+
+// A candidate stored as a field in the hash, encoded by the scripts above.
+#[derive(Deserialize)]
+struct Candidate {
+	// The origin encoded as JSON, so the scripts don't need to understand it.
+	origin: String,
+	expires: u64,
+	created: u64,
+}
+
+/// Stores the candidate origins in Redis, so they can be shared by multiple API servers.
+///
+/// Each operation is a Lua script so it's atomic, even when multiple servers are racing.
+/// Changes are appended to a stream, which every server tails to notify its own subscribers.
+pub struct RedisStore {
+	redis: ConnectionManager,
+	get: Script,
+	set: Script,
+	refresh: Script,
+	delete: Script,
+	list: Script,
+	sweep: Script,
+	set_node: Script,
+	delete_node: Script,
+	nodes: Script,
+	events: Arc<Events>,
+}
+
+impl RedisStore {
+	pub async fn connect(url: url::Url) -> Result<Self, redis::RedisError> {
+		let client = redis::Client::open(url)?;
+		let mut redis = client.get_connection_manager().await?;
+		Self::backfill(&mut redis).await?;
+
+		// Reading the stream blocks, so it needs a separate connection.
+		let mut tail = client.get_connection_manager().await?;
+		let latest: StreamRangeReply = tail.xrevrange_count(EVENTS_KEY, "+", "-", 1).await?;
+		let latest = latest
+			.ids
+			.first()
+			.map(|id| id.id.clone())
+			.unwrap_or_else(|| "0-0".to_string());
+
+		let events = Arc::new(Events::new());
+		tokio::spawn(Self::tail(tail, latest, events.clone()));
+
+		Ok(Self {
+			redis,
+			get: script(GET),
+			set: script(SET),
+			refresh: script(REFRESH),
+			delete: script(DELETE),
+			list: script(LIST),
+			sweep: script(SWEEP),
+			set_node: script(SET_NODE),
+			delete_node: script(DELETE_NODE),
+			nodes: script(NODES),
+			events,
+		})
+	}
+
+	// Build the index of namespaces the first time a server starts, since older versions didn't maintain it.
+	// This is the only time the keyspace is scanned; any origins created later by older versions are indexed when accessed.
+	async fn backfill(redis: &mut ConnectionManager) -> Result<(), redis::RedisError> {
+		if redis.exists(INDEX_KEY).await? {
+			return Ok(());
+		}
+
+		let mut namespaces = Vec::new();
+		let mut iter = redis
+			.scan_match::<_, String>(format!("{}*", escape(ORIGIN_PREFIX)))
+			.await?;
+		while let Some(key) = iter.next_item().await {
+			namespaces.push((0, key[ORIGIN_PREFIX.len()..].to_string()));
+		}
+		drop(iter);
+
+		if !namespaces.is_empty() {
+			log::info!("indexing existing origins: count={}", namespaces.len());
+			let _: i64 = redis.zadd_multiple(INDEX_KEY, &namespaces).await?;
+		}
+
+		Ok(())
+	}
+
+	// Return the fields of the hash of candidates, migrating an origin stored by an older version.
+	async fn fields(&self, namespace: &str) -> Result<Vec<(String, String)>, StoreError> {
+		let fields = invoke(&self.get, &origin_key(namespace))
+			.arg(namespace)
+			.invoke_async(&mut self.redis.clone())
+			.await?;
+
+		Ok(fields)
+	}
+
+	// Forward every event appended to the stream after `latest`, including those from other servers.
+	async fn tail(mut redis: ConnectionManager, mut latest: String, events: Arc<Events>) {
+		let options = StreamReadOptions::default().block(TAIL_BLOCK_MS).count(TAIL_BATCH);
+
+		// Set after an error or a full batch, when we may have fallen far enough behind for the stream to be trimmed.
+		let mut behind = false;
+
+		loop {
+			if behind {
+				match Self::trimmed(&mut redis, &latest).await {
+					Ok(true) => {
+						log::warn!("missed events, subscribers will need to resync: after={}", latest);
+						events.gap();
+					}
+					Ok(false) => {}
+					Err(err) => {
+						log::warn!("failed to check for missed events: {}", err);
+						tokio::time::sleep(time::Duration::from_secs(1)).await;
+						continue;
+					}
+				}
+			}
+
+			let reply: Option<StreamReadReply> = match redis.xread_options(&[EVENTS_KEY], &[&latest], &options).await {
+				Ok(reply) => reply,
+				Err(err) => {
+					// The connection manager reconnects, so try again shortly.
+					log::warn!("failed to read events: {}", err);
+					tokio::time::sleep(time::Duration::from_secs(1)).await;
+					behind = true;
+					continue;
+				}
+			};
+
+			let ids: Vec<StreamId> = reply
+				.into_iter()
+				.flat_map(|reply| reply.keys)
+				.flat_map(|key| key.ids)
+				.collect();
+			behind = ids.len() >= TAIL_BATCH;
+
+			for id in ids {
+				latest.clone_from(&id.id);
+
+				match event(&id) {
+					Some(event) => events.push(event),
+					None => log::warn!("invalid event: id={}", id.id),
+				}
+			}
+		}
+	}
+
+	// True if events after `latest` may have been trimmed from the stream before we could read them.
+	// This is conservative, since it can't tell whether the oldest remaining event immediately follows `latest`.
+	async fn trimmed(redis: &mut ConnectionManager, latest: &str) -> Result<bool, redis::RedisError> {
+		let oldest: StreamRangeReply = redis.xrange_count(EVENTS_KEY, "-", "+", 1).await?;
+		Ok(oldest
+			.ids
+			.first()
+			.is_some_and(|oldest| stream_id(&oldest.id) > stream_id(latest)))
+	}
+}
+
+#[async_trait::async_trait]
+impl Store for RedisStore {
+	async fn get(&self, namespace: &str) -> Result<Vec<Origin>, StoreError> {
+		let fields = self.fields(namespace).await?;
+		let candidates = candidates(fields, now())?;
+
+		Ok(candidates.into_iter().map(|(origin, _)| origin).collect())
+	}
+
+	async fn set(
+		&self,
+		namespace: &str,
+		origin: &Origin,
+		owner: Option<&str>,
+		ttl: time::Duration,
+	) -> Result<Lease, StoreError> {
+		// Convert the input back to JSON after validating it add adding any fields (TODO)
+		let payload = serde_json::to_string(origin)?;
+		let lease = super::lease();
+
+		let (res, token): (i64, String) = invoke(&self.set, &origin_key(namespace))
+			.arg(namespace)
+			.arg(origin.url.as_str())
+			.arg(payload)
+			.arg(lease.token)
+			.arg(ttl.as_secs())
+			.arg(owner.unwrap_or_default())
+			.arg(super::FENCED_MAX)
+			.invoke_async(&mut self.redis.clone())
+			.await?;
+
+		match (res, token) {
+			(1, token) => Ok(Lease { token }),
+			(-1, _) => Err(StoreError::Duplicate),
+			(-2, _) => Err(StoreError::Lease),
+			(-3, _) => Err(StoreError::Fenced),
+			_ => Err(StoreError::NotFound),
+		}
+	}
+
+	async fn refresh(
+		&self,
+		namespace: &str,
+		lease: &str,
+		status: &OriginStatus,
+		ttl: time::Duration,
+	) -> Result<(), StoreError> {
+		let load = status.load.map(|load| load.to_string()).unwrap_or_default();
+
+		let res: i64 = invoke(&self.refresh, &origin_key(namespace))
+			.arg(namespace)
+			.arg(lease)
+			.arg(ttl.as_secs())
+			.arg(load)
+			.invoke_async(&mut self.redis.clone())
+			.await?;
+
+		result(res)
+	}
+
+	async fn delete(&self, namespace: &str, lease: &str) -> Result<(), StoreError> {
+		let res: i64 = invoke(&self.delete, &origin_key(namespace))
+			.arg(namespace)
+			.arg(lease)
+			.invoke_async(&mut self.redis.clone())
+			.await?;
+
+		result(res)
+	}
+
+	async fn list(&self, prefix: &str, after: Option<&str>, limit: usize) -> Result<OriginList, StoreError> {
+		let (more, page): (i64, Vec<(String, Vec<(String, String)>)>) = invoke(&self.list, INDEX_KEY)
+			.arg(prefix)
+			.arg(after.unwrap_or_default())
+			.arg(limit.max(1))
+			.arg(ORIGIN_PREFIX)
+			.invoke_async(&mut self.redis.clone())
+			.await?;
+
+		let next = match more {
+			0 => None,
+			_ => page.last().map(|(namespace, _)| namespace.clone()),
+		};
+
+		let now = now();
+		let mut origins = Vec::with_capacity(page.len());
+
+		for (namespace, fields) in page {
+			for (origin, expires) in candidates(fields, now)? {
+				origins.push(OriginEntry {
+					namespace: namespace.clone(),
+					origin,
+					ttl: expires.saturating_sub(now),
+				});
+			}
+		}
+
+		Ok(OriginList { origins, next })
+	}
+
+	async fn expire(&self) -> Result<(), StoreError> {
+		loop {
+			let due: usize = invoke(&self.sweep, INDEX_KEY)
+				.arg(ORIGIN_PREFIX)
+				.arg(SWEEP_BATCH)
+				.invoke_async(&mut self.redis.clone())
+				.await?;
+
+			if due < SWEEP_BATCH {
+				return Ok(());
+			}
+		}
+	}
+
+	fn events(&self) -> &Events {
+		&self.events
+	}
+
+	async fn set_node(&self, node: &Node, client: &str, ttl: time::Duration) -> Result<bool, StoreError> {
+		let res: i64 = invoke(&self.set_node, NODES_KEY)
+			.arg(node.url.as_str())
+			.arg(serde_json::to_string(node)?)
+			.arg(client)
+			.arg(ttl.as_secs())
+			.invoke_async(&mut self.redis.clone())
+			.await?;
+
+		match res {
+			-1 => Err(StoreError::Owner),
+			res => Ok(res > 0),
+		}
+	}
+
+	async fn delete_node(&self, url: &Url, client: &str) -> Result<(), StoreError> {
+		let res: i64 = invoke(&self.delete_node, NODES_KEY)
+			.arg(url.as_str())
+			.arg(client)
+			.invoke_async(&mut self.redis.clone())
+			.await?;
+
+		match res {
+			0 => Err(StoreError::NotFound),
+			-1 => Err(StoreError::Owner),
+			_ => Ok(()),
+		}
+	}
+
+	async fn nodes(&self) -> Result<Vec<NodeEntry>, StoreError> {
+		let fields: Vec<(String, u64)> = invoke(&self.nodes, NODES_KEY)
+			.invoke_async(&mut self.redis.clone())
+			.await?;
+
+		let mut nodes = fields
+			.into_iter()
+			.map(|(node, ttl)| {
+				Ok(NodeEntry {
+					node: serde_json::from_str(&node)?,
+					ttl,
+				})
+			})
+			.collect::<Result<Vec<_>, StoreError>>()?;
+
+		nodes.sort_by(|a, b| a.node.url.cmp(&b.node.url));
+
+		Ok(nodes)
+	}
+}
+
+// Decode an entry in the stream of events, using its ID as the cursor.
+fn event(id: &StreamId) -> Option<OriginEvent> {
+	let kind = match id.get::<String>("kind")?.as_str() {
+		"set" => OriginEventKind::Set,
+		"refresh" => OriginEventKind::Refresh,
+		"delete" => OriginEventKind::Delete,
+		"expire" => OriginEventKind::Expire,
+		_ => return None,
+	};
+
+	let origin = serde_json::from_str(&id.get::<String>("origin")?).ok()?;
+
+	Some(OriginEvent {
+		cursor: id.id.clone(),
+		namespace: id.get("namespace")?,
+		kind,
+		origin,
+	})
+}
+
+// Decode the fields of a hash, returning each unexpired candidate and when it expires, in the order they were registered.
+fn candidates(fields: Vec<(String, String)>, now: u64) -> Result<Vec<(Origin, u64)>, StoreError> {
+	let mut candidates = Vec::with_capacity(fields.len());
+	for (_, value) in fields {
+		let candidate: Candidate = serde_json::from_str(&value)?;
+		if candidate.expires <= now {
+			continue;
+		}
+
+		let origin: Origin = serde_json::from_str(&candidate.origin)?;
+		candidates.push((candidate.created, origin, candidate.expires));
+	}
+
+	// Hashes aren't ordered, so sort by registration time and then URL.
+	candidates.sort_by(|a, b| (a.0, a.1.url.as_str()).cmp(&(b.0, b.1.url.as_str())));
+
+	Ok(candidates
+		.into_iter()
+		.map(|(_, origin, expires)| (origin, expires))
+		.collect())
+}
+
+// Parse the ID of an entry in a stream, which is a timestamp and sequence number, so they can be compared.
+fn stream_id(id: &str) -> (u64, u64) {
+	let (ms, seq) = id.split_once('-').unwrap_or((id, "0"));
+	(ms.parse().unwrap_or(0), seq.parse().unwrap_or(0))
+}
+
+// The current time in seconds since the Unix epoch, comparable with the expiration set by the scripts.
+fn now() -> u64 {
+	time::SystemTime::now()
+		.duration_since(time::UNIX_EPOCH)
+		.unwrap_or_default()
+		.as_secs()
+}
+
+// Each script uses the functions and the current time defined in the prelude.
+fn script(code: &str) -> Script {
+	Script::new(&format!("{}{}", PRELUDE, code))
+}
+
+// Invoke the script with the keys used by the prelude, where `key` is the hash of the namespace if any.
+fn invoke<'a>(script: &'a Script, key: &str) -> ScriptInvocation<'a> {
+	let mut invocation = script.prepare_invoke();
+	invocation.key(key).key(EVENTS_KEY).key(INDEX_KEY).key(EXPIRY_KEY);
+	invocation
+}
+
+// Convert the result of the refresh and delete scripts.
+fn result(res: i64) -> Result<(), StoreError> {
+	match res {
+		0 => Err(StoreError::NotFound),
+		-1 => Err(StoreError::Fenced),
+		_ => Ok(()),
+	}
+}
+
+// The same prefix as older versions, which stored each origin as a string; they're migrated by the scripts.
+const ORIGIN_PREFIX: &str = "origin.";
+
+// The stream of changes, which doesn't share the prefix so it's never mistaken for an origin.
+const EVENTS_KEY: &str = "origin-events";
+
+// A sorted set of every namespace with candidates, all with the same score so they're sorted lexicographically.
+const INDEX_KEY: &str = "origin-index";
+
+// A sorted set of every candidate scored by its expiration, so expired candidates are found and reported.
+const EXPIRY_KEY: &str = "origin-expiry";
+
+// The maximum number of expired candidates removed by each script.
+const SWEEP_BATCH: usize = 100;
+
+// A hash of every node, which isn't under the origin prefix so it's never mistaken for an origin.
+// Older versions used "nodes"; it's abandoned since every relay registers again within a heartbeat.
+const NODES_KEY: &str = "origin-nodes";
+
+// How long to block waiting for new events, before checking the connection again.
+const TAIL_BLOCK_MS: usize = 10_000;
+
+// The maximum number of events read at once.
+const TAIL_BATCH: usize = 100;
+
+fn origin_key(namespace: &str) -> String {
+	format!("{}{}", ORIGIN_PREFIX, namespace)
+}
+
+// Escape any characters with a special meaning in a SCAN pattern.
+fn escape(key: &str) -> String {
+	let mut escaped = String::with_capacity(key.len());
+	for c in key.chars() {
+		if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+			escaped.push('\\');
+		}
+		escaped.push(c);
+	}
+	escaped
+}
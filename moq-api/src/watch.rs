@@ -0,0 +1,90 @@
+use crate::{ApiError, OriginEvent};
+
+/// A stream of changes to the candidates, returned by [crate::Client::watch].
+pub struct Watch {
+	resp: reqwest::Response,
+
+	// Any data received after the last complete event.
+	buffer: Vec<u8>,
+
+	// The cursor of the last event returned.
+	cursor: Option<String>,
+}
+
+impl Watch {
+	pub(crate) fn new(resp: reqwest::Response) -> Self {
+		Self {
+			resp,
+			buffer: Vec::new(),
+			cursor: None,
+		}
+	}
+
+	/// Wait for the next event, returning None if the server closed the stream.
+	pub async fn next(&mut self) -> Result<Option<OriginEvent>, ApiError> {
+		loop {
+			// Each event ends with a blank line.
+			while let Some(end) = self.buffer.windows(2).position(|window| window == b"\n\n") {
+				let block: Vec<u8> = self.buffer.drain(..end + 2).collect();
+
+				if let Some(event) = parse(&block)? {
+					self.cursor = Some(event.cursor.clone());
+					return Ok(Some(event));
+				}
+			}
+
+			match self.resp.chunk().await? {
+				Some(chunk) => self.buffer.extend_from_slice(&chunk),
+				None => return Ok(None),
+			}
+		}
+	}
+
+	/// The cursor of the last event, used to resume with [crate::Client::watch] after reconnecting.
+	pub fn cursor(&self) -> Option<&str> {
+		self.cursor.as_deref()
+	}
+}
+
+// Decode a server-sent event, returning None for keep-alive comments.
+// The data contains the entire event, so the `id` and `event` fields are ignored.
+fn parse(block: &[u8]) -> Result<Option<OriginEvent>, ApiError> {
+	let text = String::from_utf8_lossy(block);
+
+	let mut data = String::new();
+	for line in text.lines() {
+		if let Some(value) = line.strip_prefix("data:") {
+			if !data.is_empty() {
+				data.push('\n');
+			}
+
+			data.push_str(value.strip_prefix(' ').unwrap_or(value));
+		}
+	}
+
+	if data.is_empty() {
+		return Ok(None);
+	}
+
+	Ok(Some(serde_json::from_str(&data)?))
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::OriginEventKind;
+
+	#[test]
+	fn decode() {
+		assert_eq!(parse(b":\n\n").unwrap(), None);
+
+		let block = b"event: set\ndata: {\"cursor\":\"1-0\",\"namespace\":\"live\",\"kind\":\"set\",\"origin\":{\"url\":\"https://a.example.com/\"}}\nid: 1-0\n\n";
+		let event = parse(block).unwrap().unwrap();
+		assert_eq!(event.cursor, "1-0");
+		assert_eq!(event.namespace, "live");
+		assert_eq!(event.kind, OriginEventKind::Set);
+		assert_eq!(event.origin.url.as_str(), "https://a.example.com/");
+
+		assert!(parse(b"data: {\n\n").is_err());
+	}
+}
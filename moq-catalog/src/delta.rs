@@ -0,0 +1,194 @@
+//! Delta updates, which describe changes to the previous catalog as a JSON Patch (RFC 6902).
+//!
+//! A publisher sends a full catalog first, then a delta whenever a track is added, removed or changed.
+//! https://www.ietf.org/archive/id/draft-ietf-moq-catalogformat-01.html#name-delta-updates
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::Root;
+
+/// A change to a catalog, encoded as a JSON array of patch operations.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(transparent)]
+pub struct Delta {
+	patch: json_patch::Patch,
+}
+
+impl Delta {
+	/// Check that the delta applies cleanly to `base` and produces a valid catalog.
+	pub fn validate(&self, base: &Root) -> Result<(), DeltaError> {
+		base.apply([self]).map(|_| ())
+	}
+
+	/// True if the delta doesn't change anything.
+	pub fn is_empty(&self) -> bool {
+		self.patch.0.is_empty()
+	}
+}
+
+/// A catalog object, which is either a full catalog or a delta to the previous one.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum Update {
+	// A delta is an array, so it's tried first to avoid parsing it as a catalog.
+	Delta(Delta),
+	Full(Root),
+}
+
+impl Update {
+	/// Return the catalog after this update, applying a delta to the current catalog.
+	pub fn apply(self, current: Option<&Root>) -> Result<Root, DeltaError> {
+		match self {
+			Self::Full(root) => Ok(root),
+			Self::Delta(delta) => current.ok_or(DeltaError::Missing)?.apply([&delta]),
+		}
+	}
+}
+
+#[derive(Error, Debug)]
+pub enum DeltaError {
+	#[error("catalog doesn't support delta updates")]
+	Unsupported,
+
+	#[error("no catalog to apply the delta to")]
+	Missing,
+
+	#[error("delta {index} doesn't apply: {source}")]
+	Patch {
+		index: usize,
+		source: json_patch::PatchError,
+	},
+
+	#[error("delta {index} produced an invalid catalog: {source}")]
+	Invalid { index: usize, source: serde_json::Error },
+
+	#[error("json error: {0}")]
+	Json(#[from] serde_json::Error),
+}
+
+impl Root {
+	/// Compute the delta that changes this catalog into `next`.
+	///
+	/// This catalog must support delta updates, otherwise subscribers will reject the delta.
+	pub fn delta(&self, next: &Root) -> Result<Delta, DeltaError> {
+		if !self.streaming_delta_updates {
+			return Err(DeltaError::Unsupported);
+		}
+
+		let prev = serde_json::to_value(self)?;
+		let next = serde_json::to_value(next)?;
+
+		Ok(Delta {
+			patch: json_patch::diff(&prev, &next),
+		})
+	}
+
+	/// Apply each delta in order, returning the resulting catalog.
+	///
+	/// Every delta must apply cleanly and produce a valid catalog that still supports delta updates.
+	pub fn apply<'a, I>(&self, deltas: I) -> Result<Root, DeltaError>
+	where
+		I: IntoIterator<Item = &'a Delta>,
+	{
+		let mut root = self.clone();
+		let mut doc = serde_json::to_value(self)?;
+
+		for (index, delta) in deltas.into_iter().enumerate() {
+			if !root.streaming_delta_updates {
+				return Err(DeltaError::Unsupported);
+			}
+
+			json_patch::patch(&mut doc, &delta.patch).map_err(|source| DeltaError::Patch { index, source })?;
+			root = Root::deserialize(&doc).map_err(|source| DeltaError::Invalid { index, source })?;
+		}
+
+		Ok(root)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::{CommonTrackFields, SelectionParam, Track};
+
+	fn track(name: &str, bitrate: u32) -> Track {
+		Track {
+			name: name.to_string(),
+			selection_params: SelectionParam {
+				codec: Some("avc1.64001f".to_string()),
+				bitrate: Some(bitrate),
+				..Default::default()
+			},
+			..Default::default()
+		}
+	}
+
+	fn catalog(tracks: Vec<Track>) -> Root {
+		Root {
+			version: 1,
+			streaming_format: 1,
+			streaming_format_version: "0.2".to_string(),
+			streaming_delta_updates: true,
+			common_track_fields: CommonTrackFields::default(),
+			tracks,
+		}
+	}
+
+	#[test]
+	fn renditions() {
+		let base = catalog(vec![track("720p", 3_000_000)]);
+		let added = catalog(vec![track("720p", 3_000_000), track("1080p", 6_000_000)]);
+		let removed = catalog(vec![track("1080p", 6_000_000)]);
+
+		let add = base.delta(&added).unwrap();
+		let remove = added.delta(&removed).unwrap();
+		assert!(base.delta(&base).unwrap().is_empty());
+
+		add.validate(&base).unwrap();
+		remove.validate(&added).unwrap();
+		assert_eq!(base.apply([&add, &remove]).unwrap(), removed);
+
+		// The second delta only applies after the first.
+		assert!(matches!(
+			base.apply([&remove, &add]),
+			Err(DeltaError::Patch { index: 0, .. })
+		));
+	}
+
+	#[test]
+	fn invalid() {
+		let base = catalog(vec![track("720p", 3_000_000)]);
+
+		let delta: Delta = serde_json::from_str(r#"[{"op": "replace", "path": "/version", "value": "one"}]"#).unwrap();
+		assert!(matches!(
+			delta.validate(&base),
+			Err(DeltaError::Invalid { index: 0, .. })
+		));
+
+		// Turning off delta updates means no more deltas can be applied.
+		let disable = Root {
+			streaming_delta_updates: false,
+			..catalog(Vec::new())
+		};
+		let delta = base.delta(&disable).unwrap();
+		assert_eq!(base.apply([&delta]).unwrap(), disable);
+		assert!(matches!(base.apply([&delta, &delta]), Err(DeltaError::Unsupported)));
+		assert!(matches!(disable.delta(&base), Err(DeltaError::Unsupported)));
+	}
+
+	#[test]
+	fn update() {
+		let base = catalog(vec![track("720p", 3_000_000)]);
+		let next = catalog(vec![track("720p", 2_500_000)]);
+
+		let full: Update = serde_json::from_str(&serde_json::to_string(&base).unwrap()).unwrap();
+		assert_eq!(full, Update::Full(base.clone()));
+
+		let delta = serde_json::to_string(&base.delta(&next).unwrap()).unwrap();
+		assert!(delta.starts_with('['));
+
+		let delta: Update = serde_json::from_str(&delta).unwrap();
+		assert!(matches!(delta.clone().apply(None), Err(DeltaError::Missing)));
+		assert_eq!(delta.apply(Some(&base)).unwrap(), next);
+	}
+}
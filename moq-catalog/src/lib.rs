@@ -5,7 +5,10 @@
 /// https://www.ietf.org/archive/id/draft-ietf-moq-catalogformat-01.html
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug)]
+mod delta;
+pub use delta::*;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Root {
 	pub version: u16,
 
@@ -24,7 +27,7 @@ pub struct Root {
 	pub tracks: Vec<Track>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
 pub struct Track {
 	#[serde(skip_serializing_if = "Option::is_none")]
 	pub namespace: Option<String>,
@@ -87,7 +90,7 @@ pub enum TrackPackaging {
 	Loc,
 }
 
-#[derive(Serialize, Deserialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
 pub struct SelectionParam {
 	pub codec: Option<String>,
 
@@ -123,7 +126,7 @@ pub struct SelectionParam {
 	pub language: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
 pub struct CommonTrackFields {
 	#[serde(skip_serializing_if = "Option::is_none")]
 	pub namespace: Option<String>,
@@ -2,6 +2,7 @@ use std::{net, sync::Arc, time};
 
 use anyhow::Context;
 use clap::Parser;
+use rustls::pki_types::CertificateDer;
 use url::Url;
 
 use crate::tls;
@@ -82,6 +83,7 @@ impl Endpoint {
 		let server = server_config.is_some().then(|| Server {
 			quic: quic.clone(),
 			accept: Default::default(),
+			refuse: false,
 		});
 
 		let client = Client {
@@ -94,18 +96,37 @@ impl Endpoint {
 	}
 }
 
+/// Information about the remote end of an accepted session.
+#[derive(Clone, Debug)]
+pub struct Peer {
+	/// The address of the peer, at the time of the handshake.
+	pub addr: net::SocketAddr,
+
+	/// The URL requested by the peer, only available for WebTransport sessions.
+	pub url: Option<Url>,
+
+	/// The identity of the peer if it presented a client certificate, which has been verified.
+	pub identity: Option<tls::Identity>,
+}
+
 pub struct Server {
 	quic: quinn::Endpoint,
-	accept: FuturesUnordered<BoxFuture<'static, anyhow::Result<web_transport::Session>>>,
+	accept: FuturesUnordered<BoxFuture<'static, anyhow::Result<(web_transport::Session, Peer)>>>,
+	refuse: bool,
 }
 
 impl Server {
 	pub async fn accept(&mut self) -> Option<web_transport::Session> {
+		self.accept_peer().await.map(|(session, _)| session)
+	}
+
+	/// Accept the next session, also returning information about the peer.
+	pub async fn accept_peer(&mut self) -> Option<(web_transport::Session, Peer)> {
 		loop {
 			tokio::select! {
-				res = self.quic.accept() => {
+				res = Self::next_incoming(&self.quic, self.refuse) => {
 					let conn = res?;
-					self.accept.push(Self::accept_session(conn).boxed());
+					self.accept.push(Self::handshake(conn).boxed());
 				}
 				res = self.accept.next(), if !self.accept.is_empty() => {
 					match res.unwrap() {
@@ -117,7 +138,27 @@ impl Server {
 		}
 	}
 
-	async fn accept_session(conn: quinn::Incoming) -> anyhow::Result<web_transport::Session> {
+	/// Accept the next connection before performing the handshake, so it can be refused based on its address.
+	///
+	/// Use [Self::handshake] to establish the session, which can run concurrently with accepting more connections.
+	pub async fn incoming(&mut self) -> Option<quinn::Incoming> {
+		Self::next_incoming(&self.quic, self.refuse).await
+	}
+
+	// Return the next connection, unless we're refusing them.
+	async fn next_incoming(quic: &quinn::Endpoint, refuse: bool) -> Option<quinn::Incoming> {
+		loop {
+			let conn = quic.accept().await?;
+			if !refuse {
+				return Some(conn);
+			}
+
+			conn.refuse();
+		}
+	}
+
+	/// Perform the handshake for a connection returned by [Self::incoming].
+	pub async fn handshake(conn: quinn::Incoming) -> anyhow::Result<(web_transport::Session, Peer)> {
 		let mut conn = conn.accept()?;
 
 		let handshake = conn
@@ -148,6 +189,27 @@ impl Server {
 			server_name,
 		);
 
+		// The certificate chain is only available if it was verified by `tls::Args::client_root`.
+		let identity = conn
+			.peer_identity()
+			.and_then(|identity| identity.downcast::<Vec<CertificateDer>>().ok())
+			.and_then(|chain| chain.first().map(tls::Identity::new));
+
+		if let Some(identity) = &identity {
+			log::debug!(
+				"authenticated client certificate: id={} subject={} names={:?}",
+				conn.stable_id(),
+				identity.subject,
+				identity.names
+			);
+		}
+
+		let mut peer = Peer {
+			addr: conn.remote_address(),
+			url: None,
+			identity,
+		};
+
 		let session = match alpn.as_bytes() {
 			web_transport_quinn::ALPN => {
 				// Wait for the CONNECT request.
@@ -155,6 +217,8 @@ impl Server {
 					.await
 					.context("failed to receive WebTransport request")?;
 
+				peer.url = Some(request.url().clone());
+
 				// Accept the CONNECT request.
 				request
 					.ok()
@@ -166,7 +230,15 @@ impl Server {
 			_ => anyhow::bail!("unsupported ALPN: {}", alpn),
 		};
 
-		Ok(session.into())
+		Ok((session.into(), peer))
+	}
+
+	/// Refuse any new connections, while existing connections are unaffected.
+	///
+	/// [Self::accept] or [Self::incoming] must still be called so the refusals are sent, although neither will return again.
+	pub fn refuse(&mut self) {
+		self.refuse = true;
+		self.accept.clear();
 	}
 
 	pub fn local_addr(&self) -> anyhow::Result<net::SocketAddr> {
@@ -219,3 +291,39 @@ impl Client {
 		Ok(session.into())
 	}
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[tokio::test]
+	async fn refuse() {
+		let args = Args {
+			bind: "127.0.0.1:0".parse().unwrap(),
+			tls: tls::Args {
+				generate: vec!["localhost".to_string()],
+				disable_verify: true,
+				..Default::default()
+			},
+		};
+
+		let mut endpoint = Endpoint::new(args.load().unwrap()).unwrap();
+		let mut server = endpoint.server.take().unwrap();
+
+		let url = format!("moqt://127.0.0.1:{}", server.local_addr().unwrap().port());
+		let url = Url::parse(&url).unwrap();
+		let connect = tokio::spawn(async move { endpoint.client.connect(&url).await });
+
+		// Refusing a connection before the handshake can't send an application error code.
+		server.incoming().await.unwrap().refuse();
+
+		let err = connect.await.unwrap().err().unwrap();
+		match err.downcast_ref::<quinn::ConnectionError>() {
+			Some(quinn::ConnectionError::ConnectionClosed(close)) => {
+				// CONNECTION_REFUSED is a transport error, not one of the relay's codes.
+				assert_eq!(u64::from(close.error_code), 0x2)
+			}
+			_ => panic!("unexpected error: {:?}", err),
+		}
+	}
+}
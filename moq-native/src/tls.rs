@@ -1,14 +1,16 @@
 use anyhow::Context;
 use clap::Parser;
 use ring::digest::{digest, SHA256};
-use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
-use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier};
 use rustls::sign::CertifiedKey;
 use rustls::RootCertStore;
+use serde::Serialize;
+use std::collections::HashMap;
 use std::fs;
 use std::io::{self, Cursor, Read};
 use std::path;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
 
 #[derive(Parser, Clone, Default)]
 #[group(id = "tls")]
@@ -39,57 +41,85 @@ pub struct Args {
 	/// Fine for local development and between relays, but should be used in caution in production.
 	#[arg(long = "tls-disable-verify")]
 	pub disable_verify: bool,
+
+	/// Generate a short-lived, self-signed certificate for these hostnames on startup.
+	///
+	/// The certificate meets the requirements for WebTransport's `serverCertificateHashes`,
+	/// so browsers can connect using the fingerprint instead of trusting the certificate.
+	/// This is intended for local development and can be combined with `cert`.
+	#[arg(long = "tls-generate")]
+	pub generate: Vec<String>,
+
+	/// Present the certificate at this path when connecting to other servers, encoded as PEM.
+	///
+	/// This is used for mutual TLS between relays and requires `client_key`.
+	#[arg(long = "tls-client-cert", requires = "client_key")]
+	pub client_cert: Option<path::PathBuf>,
+
+	/// Use the private key at this path for `client_cert`, encoded as PEM.
+	#[arg(long = "tls-client-key", requires = "client_cert")]
+	pub client_key: Option<path::PathBuf>,
+
+	/// Request a client certificate, trusting those issued by the TLS root at this path, encoded as PEM.
+	///
+	/// This value can be provided multiple times for multiple roots.
+	/// Clients without a certificate are still accepted, but they are not authenticated.
+	#[arg(long = "tls-client-root")]
+	pub client_root: Vec<path::PathBuf>,
 }
 
 #[derive(Clone)]
 pub struct Config {
 	pub client: rustls::ClientConfig,
 	pub server: Option<rustls::ServerConfig>,
-	pub fingerprints: Vec<String>,
+
+	/// The certificates used by `server`, which can be replaced at runtime.
+	pub certs: Arc<ServeCerts>,
+
+	/// True if `server` authenticates clients that present a certificate.
+	pub client_auth: bool,
+}
+
+impl Config {
+	/// The SHA256 fingerprint of each certificate currently served, which changes after a reload.
+	pub fn fingerprints(&self) -> Vec<String> {
+		self.certs.fingerprints()
+	}
 }
 
 impl Args {
 	pub fn load(&self) -> anyhow::Result<Config> {
 		let provider = Arc::new(rustls::crypto::ring::default_provider());
-		let mut serve = ServeCerts::default();
-
-		// Load the certificate and key files based on their index.
-		anyhow::ensure!(
-			self.cert.len() == self.key.len(),
-			"--tls-cert and --tls-key counts differ"
-		);
-		for (chain, key) in self.cert.iter().zip(self.key.iter()) {
-			serve.load(chain, key)?;
-		}
+		let serve = Arc::new(self.load_certs()?);
 
 		// Create a list of acceptable root certificates.
-		let mut roots = RootCertStore::empty();
-
-		if self.root.is_empty() {
+		let roots = if self.root.is_empty() {
 			// Add the platform's native root certificates.
+			let mut roots = RootCertStore::empty();
 			for cert in rustls_native_certs::load_native_certs().context("could not load platform certs")? {
 				roots.add(cert).context("failed to add root cert")?;
 			}
+			roots
 		} else {
 			// Add the specified root certificates.
-			for root in &self.root {
-				let root = fs::File::open(root).context("failed to open root cert file")?;
-				let mut root = io::BufReader::new(root);
-
-				let root = rustls_pemfile::certs(&mut root)
-					.next()
-					.context("no roots found")?
-					.context("failed to read root cert")?;
-
-				roots.add(root).context("failed to add root cert")?;
-			}
-		}
+			load_roots(&self.root)?
+		};
 
 		// Create the TLS configuration we'll use as a client (relay -> relay)
-		let mut client = rustls::ClientConfig::builder_with_provider(provider.clone())
+		let client = rustls::ClientConfig::builder_with_provider(provider.clone())
 			.with_protocol_versions(&[&rustls::version::TLS13])?
-			.with_root_certificates(roots)
-			.with_no_client_auth();
+			.with_root_certificates(roots);
+
+		// Present a certificate to other servers if configured, used for mutual TLS.
+		let mut client = match (&self.client_cert, &self.client_key) {
+			(Some(cert), Some(key)) => {
+				let (chain, key) = load_pem(cert, key)?;
+				client
+					.with_client_auth_cert(chain, key)
+					.context("invalid client certificate")?
+			}
+			_ => client.with_no_client_auth(),
+		};
 
 		// Allow disabling TLS verification altogether.
 		if self.disable_verify {
@@ -97,16 +127,24 @@ impl Args {
 			client.dangerous().set_certificate_verifier(Arc::new(noop));
 		}
 
-		let fingerprints = serve.fingerprints();
-
 		// Create the TLS configuration we'll use as a server (relay <- browser)
-		let server = if !self.key.is_empty() {
-			Some(
-				rustls::ServerConfig::builder_with_provider(provider)
-					.with_protocol_versions(&[&rustls::version::TLS13])?
-					.with_no_client_auth()
-					.with_cert_resolver(Arc::new(serve)),
-			)
+		let server = if !self.key.is_empty() || !self.generate.is_empty() {
+			let server = rustls::ServerConfig::builder_with_provider(provider.clone())
+				.with_protocol_versions(&[&rustls::version::TLS13])?;
+
+			// Optionally authenticate clients, but still allow clients (ex. browsers) without a certificate.
+			let server = if self.client_root.is_empty() {
+				server.with_no_client_auth()
+			} else {
+				let roots = Arc::new(load_roots(&self.client_root)?);
+				let verifier = WebPkiClientVerifier::builder_with_provider(roots, provider)
+					.allow_unauthenticated()
+					.build()
+					.context("failed to create client verifier")?;
+				server.with_client_cert_verifier(verifier)
+			};
+
+			Some(server.with_cert_resolver(serve.clone()))
 		} else {
 			None
 		};
@@ -114,48 +152,249 @@ impl Args {
 		Ok(Config {
 			server,
 			client,
-			fingerprints,
+			certs: serve,
+			client_auth: !self.client_root.is_empty(),
 		})
 	}
+
+	/// Reload the certificate and key files, replacing the certificates served by an existing [Config].
+	///
+	/// New connections will use the new certificates while existing connections are unaffected.
+	pub fn reload(&self, config: &Config) -> anyhow::Result<()> {
+		config.certs.reload(self)
+	}
+
+	// Returns true if the generated certificate is missing or about to expire, so it should be regenerated.
+	fn expiring(&self) -> bool {
+		if self.generate.is_empty() {
+			return false;
+		}
+
+		let generated = GENERATED.get_or_init(Default::default).lock().unwrap();
+		generated
+			.get(&self.generate)
+			.is_none_or(|generated| generated.expiring())
+	}
+
+	// The modification time of each certificate and key file.
+	fn modified(&self) -> Vec<Option<std::time::SystemTime>> {
+		self.cert
+			.iter()
+			.chain(self.key.iter())
+			.map(|path| fs::metadata(path).and_then(|meta| meta.modified()).ok())
+			.collect()
+	}
+
+	fn load_certs(&self) -> anyhow::Result<ServeCerts> {
+		let mut serve = ServeCerts::default();
+
+		// Load the certificate and key files based on their index.
+		anyhow::ensure!(
+			self.cert.len() == self.key.len(),
+			"--tls-cert and --tls-key counts differ"
+		);
+		for (chain, key) in self.cert.iter().zip(self.key.iter()) {
+			serve.load(chain, key)?;
+		}
+
+		if !self.generate.is_empty() {
+			serve.generate(&self.generate)?;
+		}
+
+		Ok(serve)
+	}
+}
+
+// Load the first certificate from each of the PEM files.
+fn load_roots(paths: &[path::PathBuf]) -> anyhow::Result<RootCertStore> {
+	let mut roots = RootCertStore::empty();
+
+	for root in paths {
+		let root = fs::File::open(root).context("failed to open root cert file")?;
+		let mut root = io::BufReader::new(root);
+
+		let root = rustls_pemfile::certs(&mut root)
+			.next()
+			.context("no roots found")?
+			.context("failed to read root cert")?;
+
+		roots.add(root).context("failed to add root cert")?;
+	}
+
+	Ok(roots)
+}
+
+// Load a certificate chain and cooresponding private key from PEM files.
+fn load_pem(
+	chain: &path::PathBuf,
+	key: &path::PathBuf,
+) -> anyhow::Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+	// Read the PEM certificate chain
+	let chain = fs::File::open(chain).context("failed to open cert file")?;
+	let mut chain = io::BufReader::new(chain);
+
+	let chain: Vec<CertificateDer> = rustls_pemfile::certs(&mut chain)
+		.collect::<Result<_, _>>()
+		.context("failed to read certs")?;
+
+	anyhow::ensure!(!chain.is_empty(), "could not find certificate");
+
+	// Read the PEM private key
+	let mut keys = fs::File::open(key).context("failed to open key file")?;
+
+	// Read the keys into a Vec so we can parse it twice.
+	let mut buf = Vec::new();
+	keys.read_to_end(&mut buf)?;
+
+	let key = rustls_pemfile::private_key(&mut Cursor::new(&buf))?.context("missing private key")?;
+
+	Ok((chain, key))
 }
 
 #[derive(Default, Debug)]
-struct ServeCerts {
-	list: Vec<Arc<CertifiedKey>>,
+pub struct ServeCerts {
+	list: RwLock<Vec<Arc<CertifiedKey>>>,
 }
 
 impl ServeCerts {
 	// Load a certificate and cooresponding key from a file
 	pub fn load(&mut self, chain: &path::PathBuf, key: &path::PathBuf) -> anyhow::Result<()> {
-		// Read the PEM certificate chain
-		let chain = fs::File::open(chain).context("failed to open cert file")?;
-		let mut chain = io::BufReader::new(chain);
+		let (chain, key) = load_pem(chain, key)?;
+		let key = rustls::crypto::ring::sign::any_supported_type(&key)?;
+
+		let certified = Arc::new(CertifiedKey::new(chain, key));
+		self.list.get_mut().unwrap().push(certified);
 
-		let chain: Vec<CertificateDer> = rustls_pemfile::certs(&mut chain)
-			.collect::<Result<_, _>>()
-			.context("failed to read certs")?;
+		Ok(())
+	}
 
-		anyhow::ensure!(!chain.is_empty(), "could not find certificate");
+	// Generate a self-signed certificate that's valid for the provided hostnames.
+	//
+	// The certificate is cached so reloading doesn't change the fingerprint, until it's about to expire.
+	pub fn generate(&mut self, hostnames: &[String]) -> anyhow::Result<()> {
+		let mut generated = GENERATED.get_or_init(Default::default).lock().unwrap();
+
+		let certified = match generated.get(hostnames).filter(|generated| !generated.expiring()) {
+			Some(generated) => generated.certified.clone(),
+			None => {
+				let certified = Arc::new(Self::self_signed(hostnames)?);
+				let expires = CertificateInfo::new(&certified).expires;
+				let expires = time::OffsetDateTime::from_unix_timestamp(expires)?;
+
+				let entry = Generated {
+					certified: certified.clone(),
+					expires,
+				};
+				generated.insert(hostnames.to_vec(), entry);
+
+				certified
+			}
+		};
 
-		// Read the PEM private key
-		let mut keys = fs::File::open(key).context("failed to open key file")?;
+		self.list.get_mut().unwrap().push(certified);
 
-		// Read the keys into a Vec so we can parse it twice.
-		let mut buf = Vec::new();
-		keys.read_to_end(&mut buf)?;
+		Ok(())
+	}
 
-		let key = rustls_pemfile::private_key(&mut Cursor::new(&buf))?.context("missing private key")?;
+	fn self_signed(hostnames: &[String]) -> anyhow::Result<CertifiedKey> {
+		let name = hostnames
+			.first()
+			.context("no hostnames to generate a certificate for")?;
+
+		// WebTransport requires ECDSA P-256 for certificates verified via `serverCertificateHashes`.
+		let key = rcgen::KeyPair::generate_for(&rcgen::PKCS_ECDSA_P256_SHA256)?;
+
+		let mut params = rcgen::CertificateParams::new(hostnames.to_vec())?;
+		params.distinguished_name.push(rcgen::DnType::CommonName, name.as_str());
+
+		// The validity period must not exceed two weeks, and allow a little clock skew.
+		let now = time::OffsetDateTime::now_utc();
+		params.not_before = now - time::Duration::hours(1);
+		params.not_after = params.not_before + GENERATED_VALIDITY;
+
+		let cert = params.self_signed(&key)?;
+		let chain = vec![cert.der().clone()];
+
+		let key = PrivateKeyDer::Pkcs8(key.serialize_der().into());
 		let key = rustls::crypto::ring::sign::any_supported_type(&key)?;
 
-		let certified = Arc::new(CertifiedKey::new(chain, key));
-		self.list.push(certified);
+		Ok(CertifiedKey::new(chain, key))
+	}
+
+	/// Reload the certificate and key files in `args`, replacing our certificates.
+	///
+	/// New connections will use the new certificates while existing connections are unaffected.
+	pub fn reload(&self, args: &Args) -> anyhow::Result<()> {
+		let serve = args.load_certs()?;
+		anyhow::ensure!(
+			self.is_empty() || !serve.is_empty(),
+			"refusing to reload without any certificates"
+		);
+
+		self.replace(serve);
 
 		Ok(())
 	}
 
+	/// Reload the certificate and key files in `args` whenever they're modified, polling every `interval`.
+	///
+	/// A reload only happens once the files have stopped changing for an interval, so the certificate and key are updated together.
+	/// If a reload fails, the previous certificates are kept until the files are modified again.
+	/// A generated certificate is also replaced when it's about to expire, changing the fingerprint.
+	/// This runs forever.
+	pub async fn watch(&self, args: &Args, interval: std::time::Duration) {
+		let mut interval = tokio::time::interval(interval);
+		let mut loaded = args.modified();
+		let mut pending = loaded.clone();
+
+		loop {
+			interval.tick().await;
+
+			let latest = args.modified();
+			if latest != pending {
+				// Wait until the files stop changing.
+				pending = latest;
+				continue;
+			}
+
+			let expiring = args.expiring();
+			if latest == loaded && !expiring {
+				continue;
+			}
+
+			if expiring {
+				log::info!(
+					"regenerating TLS certificate before it expires: hostnames={:?}",
+					args.generate
+				);
+			}
+
+			loaded = latest;
+
+			match self.reload(args) {
+				Ok(()) => log::info!(
+					"reloaded modified TLS certificates: fingerprints={:?}",
+					self.fingerprints()
+				),
+				Err(err) => log::warn!("failed to reload modified TLS certificates: {:#}", err),
+			}
+		}
+	}
+
+	// Atomically swap our certificates with the provided ones.
+	pub fn replace(&self, other: ServeCerts) {
+		*self.list.write().unwrap() = other.list.into_inner().unwrap();
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.list.read().unwrap().is_empty()
+	}
+
 	// Return the SHA256 fingerprint of our certificates.
 	pub fn fingerprints(&self) -> Vec<String> {
 		self.list
+			.read()
+			.unwrap()
 			.iter()
 			.map(|ck| {
 				let fingerprint = digest(&SHA256, ck.cert[0].as_ref());
@@ -164,27 +403,173 @@ impl ServeCerts {
 			})
 			.collect()
 	}
+
+	/// Return information about each of our certificates, used to connect via `serverCertificateHashes`.
+	pub fn describe(&self) -> Vec<CertificateInfo> {
+		self.list
+			.read()
+			.unwrap()
+			.iter()
+			.map(|ck| CertificateInfo::new(ck))
+			.collect()
+	}
+}
+
+// A generated certificate, reused until it's about to expire.
+struct Generated {
+	certified: Arc<CertifiedKey>,
+	expires: time::OffsetDateTime,
+}
+
+impl Generated {
+	fn expiring(&self) -> bool {
+		self.expires - time::OffsetDateTime::now_utc() < GENERATED_RENEW
+	}
+}
+
+// Certificates generated for each list of hostnames, cached until they're about to expire.
+static GENERATED: OnceLock<Mutex<HashMap<Vec<String>, Generated>>> = OnceLock::new();
+
+// The validity period of generated certificates, matching dev/cert.
+const GENERATED_VALIDITY: time::Duration = time::Duration::days(10);
+
+// Generated certificates are replaced once they expire within this period, giving clients time to fetch the new fingerprint.
+const GENERATED_RENEW: time::Duration = time::Duration::days(1);
+
+// The maximum validity period allowed by WebTransport's `serverCertificateHashes`.
+const HASHABLE_VALIDITY: time::Duration = time::Duration::days(14);
+
+/// Information about a certificate, used to connect via WebTransport's `serverCertificateHashes`.
+#[derive(Clone, Debug, Serialize)]
+pub struct CertificateInfo {
+	/// The hash algorithm, named like WebTransport's `serverCertificateHashes`.
+	pub algorithm: String,
+
+	/// The hex-encoded hash of the leaf certificate.
+	pub fingerprint: String,
+
+	/// The signature algorithm of the private key, ex. "ECDSA".
+	pub signature: String,
+
+	/// The subject of the leaf certificate.
+	pub subject: String,
+
+	/// When the leaf certificate expires, in seconds since the Unix epoch.
+	pub expires: i64,
+
+	/// If the certificate meets the requirements for `serverCertificateHashes`.
+	pub hashable: bool,
+}
+
+impl CertificateInfo {
+	fn new(ck: &CertifiedKey) -> Self {
+		let leaf = ck.cert[0].as_ref();
+		let fingerprint = hex::encode(digest(&SHA256, leaf).as_ref());
+		let signature = format!("{:?}", ck.key.algorithm());
+
+		let (subject, expires, hashable) = match x509_parser::parse_x509_certificate(leaf) {
+			Ok((_, cert)) => (
+				cert.subject().to_string(),
+				cert.validity().not_after.timestamp(),
+				is_hashable(&cert),
+			),
+			Err(_) => (String::new(), 0, false),
+		};
+
+		Self {
+			algorithm: "sha-256".to_string(),
+			fingerprint,
+			signature,
+			subject,
+			expires,
+			hashable,
+		}
+	}
+}
+
+// Check the rules for `serverCertificateHashes`: X.509v3, ECDSA P-256, currently valid, and valid for at most two weeks.
+fn is_hashable(cert: &x509_parser::certificate::X509Certificate) -> bool {
+	use x509_parser::public_key::PublicKey;
+
+	let validity = cert.validity();
+	let period = validity.not_after.to_datetime() - validity.not_before.to_datetime();
+
+	let p256 = matches!(cert.public_key().parsed(), Ok(PublicKey::EC(point)) if point.key_size() == 256);
+
+	cert.version() == x509_parser::x509::X509Version::V3 && p256 && validity.is_valid() && period <= HASHABLE_VALIDITY
+}
+
+/// The identity of a client, authenticated by its certificate.
+#[derive(Clone, Debug)]
+pub struct Identity {
+	/// The subject of the leaf certificate.
+	pub subject: String,
+
+	/// The common name and any DNS names in the leaf certificate.
+	pub names: Vec<String>,
+
+	/// The hex-encoded SHA256 hash of the leaf certificate.
+	pub fingerprint: String,
+}
+
+impl Identity {
+	pub fn new(leaf: &CertificateDer) -> Self {
+		let fingerprint = hex::encode(digest(&SHA256, leaf.as_ref()).as_ref());
+
+		let mut subject = String::new();
+		let mut names = Vec::new();
+
+		if let Ok((_, cert)) = x509_parser::parse_x509_certificate(leaf.as_ref()) {
+			subject = cert.subject().to_string();
+
+			let common = cert.subject().iter_common_name().filter_map(|cn| cn.as_str().ok());
+			names.extend(common.map(String::from));
+
+			if let Ok(Some(alt)) = cert.subject_alternative_name() {
+				for name in &alt.value.general_names {
+					if let x509_parser::extensions::GeneralName::DNSName(dns) = name {
+						names.push(dns.to_string());
+					}
+				}
+			}
+		}
+
+		Self {
+			subject,
+			names,
+			fingerprint,
+		}
+	}
 }
 
 impl ResolvesServerCert for ServeCerts {
 	fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+		let list = self.list.read().unwrap();
+
+		// Skip any certificates with a signature algorithm that the client doesn't support.
+		let schemes = client_hello.signature_schemes().to_vec();
+		let list: Vec<_> = list
+			.iter()
+			.filter(|ck| ck.key.choose_scheme(&schemes).is_some())
+			.collect();
+
 		if let Some(name) = client_hello.server_name() {
 			if let Ok(dns_name) = webpki::DnsNameRef::try_from_ascii_str(name) {
-				for ck in &self.list {
+				for ck in list.iter() {
 					// TODO I gave up on caching the parsed result because of lifetime hell.
 					// If this shows up on benchmarks, somebody should fix it.
 					let leaf = ck.end_entity_cert().expect("missing certificate");
 					let parsed = webpki::EndEntityCert::try_from(leaf.as_ref()).expect("failed to parse certificate");
 
 					if parsed.verify_is_valid_for_dns_name(dns_name).is_ok() {
-						return Some(ck.clone());
+						return Some((*ck).clone());
 					}
 				}
 			}
 		}
 
 		// Default to the last certificate if we couldn't find one.
-		self.list.last().cloned()
+		list.last().map(|ck| (*ck).clone())
 	}
 }
 
@@ -225,3 +610,124 @@ impl rustls::client::danger::ServerCertVerifier for NoCertificateVerification {
 		self.0.signature_verification_algorithms.supported_schemes()
 	}
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn generated() {
+		let args = Args {
+			generate: vec!["localhost".to_string()],
+			..Default::default()
+		};
+
+		// Reloading uses the same certificate, so the fingerprint doesn't change.
+		let first = args.load_certs().unwrap().fingerprints();
+		let second = args.load_certs().unwrap().fingerprints();
+		assert_eq!(first.len(), 1);
+		assert_eq!(first, second);
+
+		// Other hostnames get their own certificate.
+		let mut other = ServeCerts::default();
+		other.generate(&["example.com".to_string()]).unwrap();
+		assert_ne!(other.fingerprints(), first);
+
+		assert!(other.describe()[0].hashable);
+
+		// A certificate needs at least one hostname.
+		assert!(ServeCerts::default().generate(&[]).is_err());
+	}
+
+	#[tokio::test]
+	async fn renew() {
+		let args = Args {
+			generate: vec!["renew.example.com".to_string()],
+			..Default::default()
+		};
+
+		let certs = Arc::new(args.load_certs().unwrap());
+		let first = certs.fingerprints();
+		assert!(!args.expiring());
+
+		let watch = tokio::spawn({
+			let certs = certs.clone();
+			let args = args.clone();
+			async move { certs.watch(&args, std::time::Duration::from_millis(20)).await }
+		});
+
+		// Pretend the certificate is about to expire, so it's replaced with a new fingerprint.
+		if let Some(generated) = GENERATED.get().unwrap().lock().unwrap().get_mut(&args.generate) {
+			generated.expires = time::OffsetDateTime::now_utc();
+		}
+
+		for _ in 0..100 {
+			if certs.fingerprints() != first {
+				break;
+			}
+
+			tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+		}
+
+		assert_eq!(certs.fingerprints().len(), 1);
+		assert_ne!(certs.fingerprints(), first);
+		assert!(!args.expiring());
+
+		watch.abort();
+	}
+
+	// Write a new self-signed certificate and key to the given paths.
+	fn write_pem(cert: &path::Path, key: &path::Path) {
+		let pair = rcgen::KeyPair::generate().unwrap();
+		let params = rcgen::CertificateParams::new(vec!["localhost".to_string()]).unwrap();
+		let signed = params.self_signed(&pair).unwrap();
+
+		fs::write(cert, signed.pem()).unwrap();
+		fs::write(key, pair.serialize_pem()).unwrap();
+	}
+
+	#[tokio::test]
+	async fn watch() {
+		let dir = std::env::temp_dir().join(format!("moq-tls-{}", std::process::id()));
+		fs::create_dir_all(&dir).unwrap();
+
+		let args = Args {
+			cert: vec![dir.join("cert.pem")],
+			key: vec![dir.join("key.pem")],
+			..Default::default()
+		};
+		write_pem(&args.cert[0], &args.key[0]);
+
+		let certs = Arc::new(args.load_certs().unwrap());
+		let first = certs.fingerprints();
+
+		let watch = tokio::spawn({
+			let certs = certs.clone();
+			let args = args.clone();
+			async move { certs.watch(&args, std::time::Duration::from_millis(20)).await }
+		});
+
+		// Wait for the first poll, so the rewritten files count as a modification.
+		tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+		write_pem(&args.cert[0], &args.key[0]);
+
+		for _ in 0..100 {
+			if certs.fingerprints() != first {
+				break;
+			}
+
+			tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+		}
+
+		assert_eq!(certs.fingerprints().len(), 1);
+		assert_ne!(certs.fingerprints(), first);
+
+		// Reloading without any certificates keeps the current ones.
+		let current = certs.fingerprints();
+		assert!(certs.reload(&Args::default()).is_err());
+		assert_eq!(certs.fingerprints(), current);
+
+		watch.abort();
+		fs::remove_dir_all(&dir).ok();
+	}
+}
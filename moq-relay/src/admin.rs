@@ -0,0 +1,181 @@
+use std::net;
+
+use axum::{
+	extract::State,
+	http::StatusCode,
+	response::IntoResponse,
+	routing::{get, post},
+	Json, Router,
+};
+use serde::Serialize;
+
+use crate::{Drain, Forward, ForwardStatus, Health, Quotas};
+
+pub struct AdminConfig {
+	/// Listen for plain HTTP requests on this address.
+	pub bind: net::SocketAddr,
+
+	/// Used to trigger a graceful drain.
+	pub drain: Drain,
+
+	/// The sessions used to forward announces, reported by `GET /forward`.
+	pub forwards: Vec<Forward>,
+
+	/// The sessions to each peer in the mesh, also reported by `GET /forward`.
+	pub peers: Vec<Forward>,
+
+	/// The bandwidth usage of each quota, reported by `GET /quota`.
+	pub quotas: Quotas,
+
+	/// Used to answer the `GET /healthz` and `GET /readyz` probes.
+	pub health: Health,
+}
+
+#[derive(Clone)]
+struct AdminState {
+	drain: Drain,
+	forwards: Vec<Forward>,
+	peers: Vec<Forward>,
+	quotas: Quotas,
+	health: Health,
+}
+
+// Run an administrative HTTP server using Axum.
+// This is unauthenticated, so it should only be reachable from a trusted network.
+pub struct Admin {
+	app: Router,
+	bind: net::SocketAddr,
+}
+
+impl Admin {
+	pub fn new(config: AdminConfig) -> Self {
+		let state = AdminState {
+			drain: config.drain,
+			forwards: config.forwards,
+			peers: config.peers,
+			quotas: config.quotas,
+			health: config.health,
+		};
+
+		let app = Router::new()
+			.route("/drain", post(drain))
+			.route("/forward", get(forward))
+			.route("/quota", get(quota))
+			.route("/healthz", get(healthz))
+			.route("/readyz", get(readyz))
+			.with_state(state);
+
+		Self { app, bind: config.bind }
+	}
+
+	pub async fn run(self) -> anyhow::Result<()> {
+		log::info!("serving admin requests: bind={}", self.bind);
+
+		let listener = tokio::net::TcpListener::bind(self.bind).await?;
+		axum::serve(listener, self.app.into_make_service()).await?;
+
+		Ok(())
+	}
+}
+
+async fn drain(State(state): State<AdminState>) -> StatusCode {
+	match state.drain.start() {
+		true => StatusCode::ACCEPTED,
+		false => StatusCode::OK,
+	}
+}
+
+#[derive(Serialize)]
+struct ForwardReport {
+	url: String,
+	peer: bool,
+
+	#[serde(flatten)]
+	status: ForwardStatus,
+}
+
+// Report the status of each forward target and mesh peer, which reconnect on their own.
+async fn forward(State(state): State<AdminState>) -> impl IntoResponse {
+	let forwards = state.forwards.iter().map(|forward| (forward, false));
+	let peers = state.peers.iter().map(|peer| (peer, true));
+
+	let report: Vec<_> = forwards
+		.chain(peers)
+		.map(|(forward, peer)| ForwardReport {
+			url: forward.url().to_string(),
+			peer,
+			status: forward.status(),
+		})
+		.collect();
+
+	Json(report)
+}
+
+async fn quota(State(state): State<AdminState>) -> impl IntoResponse {
+	Json(state.quotas.usage())
+}
+
+// Liveness: the relay is running and accepting QUIC sessions.
+async fn healthz(State(state): State<AdminState>) -> StatusCode {
+	match state.health.is_listening() {
+		true => StatusCode::OK,
+		false => StatusCode::SERVICE_UNAVAILABLE,
+	}
+}
+
+// Readiness: the relay should receive new sessions.
+async fn readyz(State(state): State<AdminState>) -> impl IntoResponse {
+	let report = state.health.check().await;
+	let status = match report.ready {
+		true => StatusCode::OK,
+		false => StatusCode::SERVICE_UNAVAILABLE,
+	};
+
+	(status, Json(report))
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::{Announces, DrainConfig, Locals, Mesh, MeshConfig};
+
+	use moq_native::{quic, tls};
+
+	#[tokio::test]
+	async fn forward_redacted() {
+		let tls = tls::Args {
+			disable_verify: true,
+			..Default::default()
+		};
+		let quic = quic::Endpoint::new(quic::Config {
+			bind: "127.0.0.1:0".parse().unwrap(),
+			tls: tls.load().unwrap(),
+		})
+		.unwrap();
+
+		let mesh = Mesh::new(MeshConfig {
+			peers: vec!["https://relay2.example.com".parse().unwrap()],
+			node: None,
+			secret: Some("hunter2".to_string()),
+			quic: quic.client,
+			locals: Locals::new(),
+			announces: Announces::new(),
+		});
+
+		let drain = Drain::new(DrainConfig::default());
+		let state = AdminState {
+			drain: drain.clone(),
+			forwards: Vec::new(),
+			peers: mesh.peers().to_vec(),
+			quotas: Quotas::default(),
+			health: Health::new(drain, None, Vec::new()),
+		};
+
+		let response = forward(State(state)).await.into_response();
+		let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+		let body = String::from_utf8(body.to_vec()).unwrap();
+
+		assert!(body.contains("https://relay2.example.com/.mesh/***"), "{}", body);
+		assert!(!body.contains("hunter2"), "{}", body);
+	}
+}
@@ -1,75 +1,785 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time;
+
+use anyhow::Context;
+use serde::Deserialize;
+use tokio::sync::watch;
 use url::Url;
 
+use crate::{Direction, Drain, Quotas};
+
+// The delay between retrying requests to moq-api, doubling after each failure.
+const MIN_BACKOFF: time::Duration = time::Duration::from_secs(1);
+const MAX_BACKOFF: time::Duration = time::Duration::from_secs(30);
+
+// How often to refresh an origin, well within the 10 minute expiration.
+const REFRESH_INTERVAL: time::Duration = time::Duration::from_secs(300);
+const ORIGIN_TTL: time::Duration = time::Duration::from_secs(600);
+
+// How often to send a heartbeat to moq-api, well within the 30 second expiration.
+const HEARTBEAT_INTERVAL: time::Duration = time::Duration::from_secs(10);
+const NODE_TTL: time::Duration = time::Duration::from_secs(30);
+
+// How long to wait for origins and the node to be removed from moq-api before exiting anyway.
+const REMOVE_TIMEOUT: time::Duration = time::Duration::from_secs(10);
+
+/// How this relay is advertised to moq-api, and how to choose which relay to connect to for other origins.
+#[derive(clap::Args, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiRouting {
+	/// Connect to this relay for every origin instead of the origin itself, for example a regional edge.
+	#[arg(long = "api-edge")]
+	pub edge: Option<Url>,
+
+	/// The region of this relay, advertised to moq-api and used to prefer origins in the same region.
+	/// Origins in this region, or with a hostname containing it, are connected to directly, skipping any rewrites.
+	#[arg(long = "api-region")]
+	pub region: Option<String>,
+
+	/// The maximum number of sessions advertised to moq-api, so other relays prefer origins with spare capacity.
+	#[arg(long = "api-capacity")]
+	pub capacity: Option<u64>,
+
+	/// Rewrite the URL of other origins, applied in order; only configurable via the configuration file.
+	#[arg(skip)]
+	pub rewrite: Vec<ApiRewrite>,
+}
+
+/// Replace each match of a regular expression in an origin URL, configured via `[[api_routing.rewrite]]`.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ApiRewrite {
+	pub pattern: String,
+
+	/// The replacement, which can refer to capture groups such as `$1`.
+	pub replace: String,
+}
+
+impl ApiRouting {
+	/// Override any values that are set in `other`.
+	pub fn merge(&mut self, other: &ApiRouting) {
+		self.edge = other.edge.clone().or(self.edge.take());
+		self.region = other.region.clone().or(self.region.take());
+		self.capacity = other.capacity.or(self.capacity);
+	}
+
+	/// Compile the routing policy used by the moq-api client.
+	pub fn policy(&self) -> anyhow::Result<moq_api::Routing> {
+		let rewrites = self
+			.rewrite
+			.iter()
+			.map(|rewrite| {
+				moq_api::Rewrite::new(&rewrite.pattern, &rewrite.replace)
+					.with_context(|| format!("invalid api rewrite: {}", rewrite.pattern))
+			})
+			.collect::<anyhow::Result<_>>()?;
+
+		Ok(moq_api::Routing {
+			edge: self.edge.clone(),
+			region: self.region.clone(),
+			rewrites,
+		})
+	}
+}
+
 #[derive(Clone)]
 pub struct Api {
 	client: moq_api::Client,
-	origin: moq_api::Origin,
+	drain: Drain,
+
+	// The origin we advertise, whose region and capacity can change when reloading.
+	origin: Arc<Mutex<moq_api::Origin>>,
+
+	// The number of active sessions, reported to moq-api as the load.
+	load: Arc<AtomicU64>,
+
+	// The origin registered for each namespace, shared by backup publishers.
+	registered: Arc<Mutex<HashMap<String, Registration>>>,
+
+	// The candidates for each namespace, cached while we're notified of changes.
+	cache: Arc<Mutex<Cache>>,
+
+	// The last error refreshing each namespace, cleared once it succeeds, reported by the readiness probe.
+	failures: Arc<Mutex<HashMap<String, String>>>,
+
+	// The number of origins and nodes still being removed, so we can wait for them before exiting.
+	removing: Arc<watch::Sender<usize>>,
+
+	// Set once the node is removed, so it's not removed twice.
+	removed: Arc<AtomicBool>,
 }
 
 impl Api {
-	pub fn new(url: Url, node: Url) -> Self {
-		let origin = moq_api::Origin { url: node };
-		let client = moq_api::Client::new(url);
+	pub fn new(
+		url: Url,
+		origin: moq_api::Origin,
+		token: Option<String>,
+		routing: moq_api::Routing,
+		drain: Drain,
+	) -> Self {
+		let mut client = moq_api::Client::new(url).with_routing(routing);
+		if let Some(token) = token {
+			client = client.with_token(token);
+		}
+
+		Self {
+			client,
+			drain,
+			origin: Arc::new(Mutex::new(origin)),
+			load: Default::default(),
+			registered: Default::default(),
+			cache: Default::default(),
+			failures: Default::default(),
+			removing: Arc::new(watch::channel(0).0),
+			removed: Default::default(),
+		}
+	}
+
+	/// Apply a new token and routing policy, used by any new requests.
+	///
+	/// The region and capacity are advertised on the next heartbeat, and for origins on the next registration.
+	pub fn update(&self, token: Option<String>, routing: &ApiRouting) -> anyhow::Result<()> {
+		let policy = routing.policy()?;
+
+		self.client.set_token(token);
+		self.client.set_routing(policy);
+
+		let mut origin = self.origin.lock().unwrap();
+		origin.region.clone_from(&routing.region);
+		origin.capacity = routing.capacity;
+		drop(origin);
+
+		// Cached candidates had the previous routing policy applied.
+		self.cache.lock().unwrap().clear();
+
+		Ok(())
+	}
 
-		Self { client, origin }
+	fn origin(&self) -> moq_api::Origin {
+		self.origin.lock().unwrap().clone()
 	}
 
 	pub async fn set_origin(&self, namespace: String) -> Result<Refresh, moq_api::ApiError> {
-		let refresh = Refresh::new(self.client.clone(), self.origin.clone(), namespace);
+		self.registered
+			.lock()
+			.unwrap()
+			.entry(namespace.clone())
+			.or_default()
+			.count += 1;
+
+		let mut refresh = Refresh::new(self.clone(), namespace);
 		refresh.update().await?;
 		Ok(refresh)
 	}
 
-	pub async fn get_origin(&self, namespace: &str) -> Result<Option<moq_api::Origin>, moq_api::ApiError> {
-		self.client.get_origin(namespace).await
+	/// Return every candidate for the namespace, from most to least preferred.
+	///
+	/// The result is cached until moq-api notifies us that the namespace changed.
+	pub async fn get_origins(&self, namespace: &str) -> Result<Vec<moq_api::Origin>, moq_api::ApiError> {
+		let generation = {
+			let cache = self.cache.lock().unwrap();
+			if let Some(origins) = cache.origins.get(namespace) {
+				return Ok(origins.clone());
+			}
+
+			cache.generation
+		};
+
+		let origins = self.client.get_candidates(namespace).await?;
+		self.cache.lock().unwrap().insert(generation, namespace, &origins);
+
+		Ok(origins)
+	}
+
+	/// Stream changes from moq-api to invalidate the cached candidates, reconnecting forever.
+	///
+	/// Nothing is cached while disconnected, so every lookup makes a request instead.
+	pub async fn run(self) -> anyhow::Result<()> {
+		let mut backoff = MIN_BACKOFF;
+
+		loop {
+			let connected = time::Instant::now();
+
+			let err = match self.watch().await {
+				Ok(()) => anyhow::anyhow!("stream closed"),
+				Err(err) => err.into(),
+			};
+
+			self.cache.lock().unwrap().reset(false);
+
+			// Reset the backoff if we were connected for a while.
+			if connected.elapsed() > MAX_BACKOFF {
+				backoff = MIN_BACKOFF;
+			}
+
+			log::warn!("failed to watch moq-api: retry={:?} error={}", backoff, err);
+
+			tokio::time::sleep(backoff).await;
+			backoff = (backoff * 2).min(MAX_BACKOFF);
+		}
+	}
+
+	/// Register this relay as a node and send a heartbeat until draining, then remove it so it's no longer selected.
+	///
+	/// Bandwidth is only measured for namespaces with a quota, so it's not reported without any.
+	pub async fn heartbeat(self, quotas: Quotas) -> anyhow::Result<()> {
+		let mut interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+
+		loop {
+			tokio::select! {
+				_ = interval.tick() => {},
+				_ = self.drain.wait() => break,
+			}
+
+			let node = self.node(&quotas);
+			if let Err(err) = self.client.set_node(&node).await {
+				log::warn!("failed to send heartbeat: url={} error={}", node.url, err);
+			}
+		}
+
+		self.remove_node();
+
+		Ok(())
+	}
+
+	// Remove the node in the background, unless it was already removed.
+	fn remove_node(&self) {
+		if self.removed.swap(true, Ordering::Relaxed) {
+			return;
+		}
+
+		let client = self.client.clone();
+		let url = self.origin().url;
+		let name = format!("node: url={}", url);
+
+		self.remove(name, NODE_TTL, move || {
+			let client = client.clone();
+			let url = url.clone();
+			async move { client.delete_node(&url).await }
+		});
+	}
+
+	// Remove the origin in the background, unless it was claimed by another relay since.
+	fn remove_origin(&self, namespace: String, lease: moq_api::Lease) {
+		let client = self.client.clone();
+		let name = format!("origin: namespace={}", namespace);
+
+		self.remove(name, ORIGIN_TTL, move || {
+			let client = client.clone();
+			let namespace = namespace.clone();
+			let lease = lease.clone();
+			async move { client.delete_origin(&namespace, &lease).await }
+		});
+	}
+
+	// Retry the request with backoff until it succeeds, tracked so it can finish before the relay exits.
+	// There's no point retrying for longer than `ttl`, since moq-api will have expired it by then.
+	fn remove<F, Fut>(&self, name: String, ttl: time::Duration, mut request: F)
+	where
+		F: FnMut() -> Fut + Send + 'static,
+		Fut: Future<Output = Result<bool, moq_api::ApiError>> + Send,
+	{
+		self.removing.send_modify(|count| *count += 1);
+		let removing = Removing(self.removing.clone());
+
+		log::debug!("removing {}", name);
+
+		tokio::spawn(async move {
+			let _removing = removing;
+
+			let retry = async {
+				let mut backoff = MIN_BACKOFF;
+
+				loop {
+					match request().await {
+						Ok(true) => log::debug!("removed {}", name),
+						Ok(false) => log::info!("already removed or expired: {}", name),
+						Err(moq_api::ApiError::Claimed) => log::info!("claimed by another relay: {}", name),
+						Err(err) => {
+							log::warn!("failed to remove {} retry={:?} error={}", name, backoff, err);
+							tokio::time::sleep(backoff).await;
+							backoff = (backoff * 2).min(MAX_BACKOFF);
+							continue;
+						}
+					}
+
+					return;
+				}
+			};
+
+			if tokio::time::timeout(ttl, retry).await.is_err() {
+				log::warn!("gave up removing, it has expired anyway: {}", name);
+			}
+		});
+	}
+
+	/// Remove the node and wait for it and any origins to be removed from moq-api, giving up after a timeout.
+	///
+	/// This should be called after draining, once every origin has been dropped.
+	pub async fn close(&self) {
+		self.remove_node();
+
+		let mut removing = self.removing.subscribe();
+		let done = tokio::time::timeout(REMOVE_TIMEOUT, removing.wait_for(|count| *count == 0)).await;
+
+		match done {
+			Ok(_) => log::info!("removed from moq-api"),
+			Err(_) => log::warn!("timed out removing from moq-api: remaining={}", *self.removing.borrow()),
+		}
+	}
+
+	/// The last error refreshing an origin, if any are currently failing.
+	pub fn refresh_error(&self) -> Option<String> {
+		let failures = self.failures.lock().unwrap();
+		let (namespace, error) = failures.iter().next()?;
+		Some(format!(
+			"failed to refresh {} origins: namespace={} error={}",
+			failures.len(),
+			namespace,
+			error
+		))
+	}
+
+	// The current status of this relay, sent as a heartbeat.
+	fn node(&self, quotas: &Quotas) -> moq_api::Node {
+		let origin = self.origin();
+
+		moq_api::Node {
+			region: origin.region,
+			capacity: origin.capacity,
+			sessions: self.load(),
+			ingress: quotas.rate(Direction::Ingress).map(|rate| rate as u64),
+			egress: quotas.rate(Direction::Egress).map(|rate| rate as u64),
+			..moq_api::Node::new(origin.url)
+		}
+	}
+
+	async fn watch(&self) -> Result<(), moq_api::ApiError> {
+		let mut watch = self.client.watch(None).await?;
+
+		// We'll be notified of any changes from now on, so it's safe to start caching.
+		self.cache.lock().unwrap().reset(true);
+		log::info!("watching moq-api for changes");
+
+		while let Some(event) = watch.next().await? {
+			log::debug!(
+				"origin changed: namespace={} kind={} url={}",
+				event.namespace,
+				event.kind.as_str(),
+				event.origin.url
+			);
+
+			self.cache.lock().unwrap().invalidate(&event.namespace);
+		}
+
+		Ok(())
+	}
+
+	/// Set the number of active sessions, reported to moq-api on the next refresh.
+	pub fn set_load(&self, sessions: usize) {
+		self.load.store(sessions as u64, Ordering::Relaxed);
+	}
+
+	fn load(&self) -> u64 {
+		self.load.load(Ordering::Relaxed)
+	}
+
+	/// Make a request to moq-api to ensure it's reachable, used for readiness probes.
+	pub async fn check(&self) -> Result<(), moq_api::ApiError> {
+		self.client.get_origin(".healthz").await?;
+		Ok(())
+	}
+}
+
+#[derive(Default)]
+struct Cache {
+	// True while connected to the stream of changes, otherwise nothing is cached.
+	watching: bool,
+
+	// Incremented on every change, so a lookup that raced with a change isn't cached.
+	generation: u64,
+
+	origins: HashMap<String, Vec<moq_api::Origin>>,
+}
+
+impl Cache {
+	// Cache the result of a lookup, unless anything changed since it started.
+	fn insert(&mut self, generation: u64, namespace: &str, origins: &[moq_api::Origin]) {
+		if self.watching && self.generation == generation {
+			self.origins.insert(namespace.to_string(), origins.to_vec());
+		}
+	}
+
+	fn invalidate(&mut self, namespace: &str) {
+		self.generation += 1;
+		self.origins.remove(namespace);
+	}
+
+	fn reset(&mut self, watching: bool) {
+		self.watching = watching;
+		self.clear();
+	}
+
+	fn clear(&mut self) {
+		self.generation += 1;
+		self.origins.clear();
+	}
+}
+
+#[derive(Default)]
+struct Registration {
+	// The number of announces using the origin.
+	count: usize,
+
+	// Returned when registering the origin, and required to refresh or remove it.
+	// Shared so registering again for a backup publisher doesn't fence off the lease used by the other.
+	lease: Option<moq_api::Lease>,
+
+	// Set once another relay claims the origin, so we stop refreshing instead of claiming it back.
+	claimed: bool,
+}
+
+// Decrements the number of pending removals when the request finishes or is cancelled.
+struct Removing(Arc<watch::Sender<usize>>);
+
+impl Drop for Removing {
+	fn drop(&mut self) {
+		self.0.send_modify(|count| *count -= 1);
 	}
 }
 
 pub struct Refresh {
-	client: moq_api::Client,
-	origin: moq_api::Origin,
+	api: Api,
 	namespace: String,
 	refresh: tokio::time::Interval,
 }
 
 impl Refresh {
-	fn new(client: moq_api::Client, origin: moq_api::Origin, namespace: String) -> Self {
-		let duration = tokio::time::Duration::from_secs(300);
-		let mut refresh = tokio::time::interval(tokio::time::Duration::from_secs(300));
-		refresh.reset_after(duration); // skip the first tick
+	fn new(api: Api, namespace: String) -> Self {
+		let mut refresh = tokio::time::interval(REFRESH_INTERVAL);
+		refresh.reset_after(REFRESH_INTERVAL); // skip the first tick
 
 		Self {
-			client,
-			origin,
+			api,
 			namespace,
 			refresh,
 		}
 	}
 
-	async fn update(&self) -> Result<(), moq_api::ApiError> {
+	async fn update(&mut self) -> Result<(), moq_api::ApiError> {
+		if self.registration(|registration| registration.claimed) {
+			return Err(moq_api::ApiError::Claimed);
+		}
+
+		let res = self.register().await;
+		if let Err(moq_api::ApiError::Claimed) = res {
+			// The lease is no longer ours, so there's nothing to remove either.
+			self.registration(|registration| {
+				registration.claimed = true;
+				registration.lease = None;
+			});
+		}
+
+		res
+	}
+
+	// Refresh the origin using our lease, or register it if we don't have one or it expired.
+	async fn register(&mut self) -> Result<(), moq_api::ApiError> {
+		let lease = self.registration(|registration| registration.lease.clone());
+
+		if let Some(lease) = &lease {
+			// Extend the expiration using our lease, reporting our current load.
+			let status = moq_api::OriginStatus {
+				load: Some(self.api.load()),
+			};
+
+			if self.api.client.patch_origin(&self.namespace, lease, &status).await? {
+				return Ok(());
+			}
+
+			// The origin expired, so try to register it again.
+			log::warn!("origin not found, registering again: namespace={}", self.namespace);
+		}
+
 		// Register the origin in moq-api.
-		log::debug!(
-			"registering origin: namespace={} url={}",
-			self.namespace,
-			self.origin.url
-		);
-		self.client.set_origin(&self.namespace, self.origin.clone()).await
+		let origin = moq_api::Origin {
+			load: Some(self.api.load()),
+			..self.api.origin()
+		};
+
+		log::debug!("registering origin: namespace={} url={}", self.namespace, origin.url);
+
+		let lease = self.api.client.set_origin(&self.namespace, origin).await?;
+		self.registration(|registration| registration.lease = Some(lease));
+
+		Ok(())
 	}
 
+	fn registration<T>(&self, f: impl FnOnce(&mut Registration) -> T) -> T {
+		let mut registered = self.api.registered.lock().unwrap();
+		f(registered.entry(self.namespace.clone()).or_default())
+	}
+
+	/// Keep refreshing the origin until the relay starts draining, retrying any failures with backoff.
+	/// Stops early if another relay claimed the origin, since refreshing would fail anyway.
+	/// The origin is removed when this is dropped, unless it was claimed.
 	pub async fn run(&mut self) -> anyhow::Result<()> {
+		let mut backoff = MIN_BACKOFF;
+
 		loop {
-			self.refresh.tick().await;
-			self.update().await?;
+			tokio::select! {
+				_ = self.refresh.tick() => {},
+				_ = self.api.drain.wait() => return Ok(()),
+			}
+
+			match self.update().await {
+				Ok(()) => {
+					if self.api.failures.lock().unwrap().remove(&self.namespace).is_some() {
+						log::info!("refreshed origin: namespace={}", self.namespace);
+					}
+
+					backoff = MIN_BACKOFF;
+				}
+				Err(moq_api::ApiError::Claimed) => {
+					log::warn!(
+						"origin claimed by another relay, no longer refreshing: namespace={}",
+						self.namespace
+					);
+					self.api.failures.lock().unwrap().remove(&self.namespace);
+
+					return Ok(());
+				}
+				Err(err) => {
+					log::warn!(
+						"failed to refresh origin: namespace={} retry={:?} error={}",
+						self.namespace,
+						backoff,
+						err
+					);
+
+					self.api
+						.failures
+						.lock()
+						.unwrap()
+						.insert(self.namespace.clone(), err.to_string());
+
+					self.refresh.reset_after(backoff);
+					backoff = (backoff * 2).min(MAX_BACKOFF);
+				}
+			}
 		}
 	}
 }
 
 impl Drop for Refresh {
 	fn drop(&mut self) {
-		// TODO this is really lazy
-		let namespace = self.namespace.clone();
-		let client = self.client.clone();
-		log::debug!("removing origin: namespace={}", namespace,);
-		tokio::spawn(async move { client.delete_origin(&namespace).await });
+		let mut registered = self.api.registered.lock().unwrap();
+		let registration = match registered.get_mut(&self.namespace) {
+			Some(registration) => registration,
+			None => return,
+		};
+
+		registration.count -= 1;
+		if registration.count > 0 {
+			// Another announce for the same namespace is still using the origin.
+			return;
+		}
+
+		let registration = registered.remove(&self.namespace).unwrap();
+		self.api.failures.lock().unwrap().remove(&self.namespace);
+
+		// We never registered the origin, so there's nothing to remove.
+		if let Some(lease) = registration.lease {
+			self.api.remove_origin(self.namespace.clone(), lease);
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::DrainConfig;
+
+	use axum::{
+		extract::State,
+		http::{Method, StatusCode, Uri},
+		response::{IntoResponse, Response},
+		Json,
+	};
+
+	// A fake moq-api that records every request, failing any with a queued status and otherwise succeeding.
+	#[derive(Clone, Default)]
+	struct Mock {
+		requests: Arc<Mutex<Vec<String>>>,
+		failures: Arc<Mutex<HashMap<String, Vec<StatusCode>>>>,
+	}
+
+	impl Mock {
+		async fn serve(&self) -> Url {
+			let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+			let url = format!("http://{}/", listener.local_addr().unwrap()).parse().unwrap();
+
+			let app = axum::Router::new().fallback(respond).with_state(self.clone());
+			tokio::spawn(async move { axum::serve(listener, app).await });
+
+			url
+		}
+
+		// Fail the next request matching "<method> <path>" with this status.
+		fn fail(&self, request: &str, status: StatusCode) {
+			let mut failures = self.failures.lock().unwrap();
+			failures.entry(request.to_string()).or_default().push(status);
+		}
+
+		fn count(&self, request: &str) -> usize {
+			self.requests.lock().unwrap().iter().filter(|r| *r == request).count()
+		}
+	}
+
+	async fn respond(State(mock): State<Mock>, method: Method, uri: Uri) -> Response {
+		let request = format!("{} {}", method, uri.path());
+		mock.requests.lock().unwrap().push(request.clone());
+
+		let failure = mock
+			.failures
+			.lock()
+			.unwrap()
+			.get_mut(&request)
+			.filter(|failures| !failures.is_empty())
+			.map(|failures| failures.remove(0));
+
+		match failure {
+			Some(status) => status.into_response(),
+			None if method == Method::POST => Json(moq_api::Lease {
+				token: "lease".to_string(),
+			})
+			.into_response(),
+			None => StatusCode::OK.into_response(),
+		}
+	}
+
+	fn api(url: Url, drain: Drain) -> Api {
+		let origin = moq_api::Origin::new("https://relay.example.com".parse().unwrap());
+		Api::new(url, origin, None, moq_api::Routing::default(), drain)
+	}
+
+	#[tokio::test]
+	async fn fenced() {
+		let mock = Mock::default();
+		mock.fail("PATCH /origin/live", StatusCode::CONFLICT);
+		let api = api(mock.serve().await, Drain::new(DrainConfig::default()));
+
+		let mut refresh = api.set_origin("live".to_string()).await.unwrap();
+
+		// Another relay claimed our URL, so stop refreshing instead of registering it again.
+		refresh.refresh.reset_immediately();
+		refresh.run().await.unwrap();
+		assert!(api.refresh_error().is_none());
+
+		// A backup announce for the same namespace doesn't claim it back either.
+		assert!(matches!(
+			api.set_origin("live".to_string()).await,
+			Err(moq_api::ApiError::Claimed)
+		));
+
+		// The lease isn't ours anymore, so there's nothing to remove.
+		drop(refresh);
+		assert_eq!(*api.removing.borrow(), 0);
+
+		assert_eq!(mock.count("POST /origin/live"), 1);
+		assert_eq!(mock.count("PATCH /origin/live"), 1);
+		assert_eq!(mock.count("DELETE /origin/live"), 0);
+	}
+
+	#[tokio::test]
+	async fn retry() {
+		let mock = Mock::default();
+		mock.fail("PATCH /origin/live", StatusCode::SERVICE_UNAVAILABLE);
+		mock.fail("DELETE /origin/live", StatusCode::SERVICE_UNAVAILABLE);
+
+		let drain = Drain::new(DrainConfig::default());
+		let api = api(mock.serve().await, drain.clone());
+
+		let mut refresh = api.set_origin("live".to_string()).await.unwrap();
+		refresh.refresh.reset_immediately();
+
+		let run = tokio::spawn(async move {
+			refresh.run().await.unwrap();
+			refresh
+		});
+
+		// The failed refresh is retried after a backoff.
+		while mock.count("PATCH /origin/live") < 2 {
+			tokio::time::sleep(time::Duration::from_millis(10)).await;
+		}
+
+		drain.start();
+		let refresh = run.await.unwrap();
+		assert!(api.refresh_error().is_none());
+
+		// The failed removal is retried too, and closing waits for it.
+		drop(refresh);
+		api.close().await;
+
+		assert_eq!(*api.removing.borrow(), 0);
+		assert_eq!(mock.count("POST /origin/live"), 1);
+		assert_eq!(mock.count("DELETE /origin/live"), 2);
+		assert_eq!(mock.count("DELETE /node"), 1);
+	}
+
+	#[tokio::test]
+	async fn update() {
+		let api = api(Mock::default().serve().await, Drain::new(DrainConfig::default()));
+
+		let routing = ApiRouting {
+			region: Some("eu".to_string()),
+			capacity: Some(10),
+			..Default::default()
+		};
+		api.update(Some("token".to_string()), &routing).unwrap();
+
+		let node = api.node(&Quotas::default());
+		assert_eq!(node.region.as_deref(), Some("eu"));
+		assert_eq!(node.capacity, Some(10));
+
+		// An invalid rewrite is rejected without changing anything.
+		let invalid = ApiRouting {
+			rewrite: vec![ApiRewrite {
+				pattern: "(".to_string(),
+				replace: String::new(),
+			}],
+			..Default::default()
+		};
+		assert!(api.update(None, &invalid).is_err());
+		assert_eq!(api.node(&Quotas::default()).region.as_deref(), Some("eu"));
+	}
+
+	#[tokio::test]
+	async fn drained() {
+		let mock = Mock::default();
+		let drain = Drain::new(DrainConfig::default());
+		let api = api(mock.serve().await, drain.clone());
+
+		let refresh = api.set_origin("live".to_string()).await.unwrap();
+		let heartbeat = tokio::spawn(api.clone().heartbeat(Quotas::default()));
+
+		while mock.count("PUT /node") < 1 {
+			tokio::time::sleep(time::Duration::from_millis(10)).await;
+		}
+
+		// Draining stops the heartbeat and removes the node.
+		drain.start();
+		heartbeat.await.unwrap().unwrap();
+
+		// Closing waits for the node and every dropped origin to be removed, removing the node only once.
+		drop(refresh);
+		api.close().await;
+
+		assert_eq!(*api.removing.borrow(), 0);
+		assert_eq!(mock.count("DELETE /origin/live"), 1);
+		assert_eq!(mock.count("DELETE /node"), 1);
 	}
 }
@@ -0,0 +1,303 @@
+use std::{path, time};
+
+use anyhow::Context;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufReader, BufWriter};
+
+/// The name written in the header of every archive.
+pub const ARCHIVE_FORMAT: &str = "moq-archive";
+
+/// The version of the archive format.
+pub const ARCHIVE_VERSION: u32 = 1;
+
+/// A record within an archive.
+///
+/// An archive is a sequence of records, each encoded as a single line of JSON.
+/// An object record is followed by `size` bytes of payload, so the file is self-describing but still easy to parse.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Record {
+	/// The first record of every file.
+	Header {
+		format: String,
+		version: u32,
+		namespace: String,
+
+		/// When the file was created, in microseconds since the Unix epoch.
+		created: u64,
+	},
+
+	/// Declares a track before any of its objects.
+	Track { track: String, mode: ArchiveMode },
+
+	/// An object, followed by its payload.
+	Object {
+		track: String,
+		group: u64,
+		object: u64,
+		priority: u64,
+
+		/// When the object was received, in microseconds since the Unix epoch.
+		timestamp: u64,
+
+		size: usize,
+	},
+}
+
+/// How the track was delivered, so it can be served the same way.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ArchiveMode {
+	Stream,
+	Groups,
+	Objects,
+	Datagrams,
+}
+
+/// The current time in microseconds since the Unix epoch, used for timestamps.
+pub fn timestamp() -> u64 {
+	time::SystemTime::now()
+		.duration_since(time::UNIX_EPOCH)
+		.unwrap_or_default()
+		.as_micros() as u64
+}
+
+/// Writes records to an archive file.
+pub struct ArchiveWriter {
+	file: BufWriter<tokio::fs::File>,
+	path: path::PathBuf,
+	size: u64,
+	created: time::Instant,
+}
+
+impl ArchiveWriter {
+	/// Create a new file, writing the header.
+	pub async fn create(path: path::PathBuf, namespace: &str) -> anyhow::Result<Self> {
+		let file = tokio::fs::File::create(&path)
+			.await
+			.with_context(|| format!("failed to create archive: {}", path.display()))?;
+
+		let mut archive = Self {
+			file: BufWriter::new(file),
+			path,
+			size: 0,
+			created: time::Instant::now(),
+		};
+
+		let header = Record::Header {
+			format: ARCHIVE_FORMAT.to_string(),
+			version: ARCHIVE_VERSION,
+			namespace: namespace.to_string(),
+			created: timestamp(),
+		};
+		archive.write(&header, Bytes::new()).await?;
+		archive.flush().await?;
+
+		Ok(archive)
+	}
+
+	/// Write a record followed by its payload, which must be empty unless it's an object.
+	pub async fn write(&mut self, record: &Record, payload: Bytes) -> anyhow::Result<()> {
+		let mut line = serde_json::to_vec(record)?;
+		line.push(b'\n');
+
+		self.file.write_all(&line).await?;
+		self.file.write_all(&payload).await?;
+		self.size += (line.len() + payload.len()) as u64;
+
+		Ok(())
+	}
+
+	pub async fn flush(&mut self) -> anyhow::Result<()> {
+		self.file.flush().await?;
+		Ok(())
+	}
+
+	pub async fn finish(mut self) -> anyhow::Result<()> {
+		self.flush().await
+	}
+
+	pub fn path(&self) -> &path::Path {
+		&self.path
+	}
+
+	/// The number of bytes written so far.
+	pub fn size(&self) -> u64 {
+		self.size
+	}
+
+	/// How long the file has been open.
+	pub fn elapsed(&self) -> time::Duration {
+		self.created.elapsed()
+	}
+}
+
+/// Reads records from an archive file, in the order they were written.
+pub struct ArchiveReader {
+	file: BufReader<tokio::fs::File>,
+	path: path::PathBuf,
+	namespace: String,
+
+	// The number of bytes read so far.
+	offset: u64,
+
+	// The size of the payload after the last record, if it hasn't been read yet.
+	pending: usize,
+}
+
+impl ArchiveReader {
+	/// Open an existing file, validating the header.
+	pub async fn open(path: path::PathBuf) -> anyhow::Result<Self> {
+		let file = tokio::fs::File::open(&path)
+			.await
+			.with_context(|| format!("failed to open archive: {}", path.display()))?;
+
+		let mut archive = Self {
+			file: BufReader::new(file),
+			path,
+			namespace: String::new(),
+			offset: 0,
+			pending: 0,
+		};
+
+		archive.namespace = match archive.next().await? {
+			Some((
+				Record::Header {
+					format,
+					version,
+					namespace,
+					..
+				},
+				_,
+			)) => {
+				anyhow::ensure!(format == ARCHIVE_FORMAT, "unknown archive format: {}", format);
+				anyhow::ensure!(version == ARCHIVE_VERSION, "unsupported archive version: {}", version);
+				namespace
+			}
+			_ => anyhow::bail!("missing archive header: {}", archive.path.display()),
+		};
+
+		Ok(archive)
+	}
+
+	/// Read the next record and its payload, returning None at the end of the file.
+	pub async fn next(&mut self) -> anyhow::Result<Option<(Record, Bytes)>> {
+		let record = match self.record().await? {
+			Some(record) => record,
+			None => return Ok(None),
+		};
+
+		let payload = self.payload().await?;
+		Ok(Some((record, payload)))
+	}
+
+	/// Read the next record without its payload, which can be read with [Self::payload] or is skipped otherwise.
+	pub async fn record(&mut self) -> anyhow::Result<Option<Record>> {
+		if self.pending > 0 {
+			self.file.seek(std::io::SeekFrom::Current(self.pending as i64)).await?;
+			self.offset += self.pending as u64;
+			self.pending = 0;
+		}
+
+		let mut line = Vec::new();
+		let size = self.file.read_until(b'\n', &mut line).await?;
+		if size == 0 {
+			return Ok(None);
+		}
+
+		let record: Record = serde_json::from_slice(&line)
+			.with_context(|| format!("invalid record: path={} offset={}", self.path.display(), self.offset))?;
+		self.offset += size as u64;
+
+		if let Record::Object { size, .. } = &record {
+			self.pending = *size;
+		}
+
+		Ok(Some(record))
+	}
+
+	/// Read the payload of the last record, which is empty unless it's an object.
+	pub async fn payload(&mut self) -> anyhow::Result<Bytes> {
+		let mut payload = vec![0; self.pending];
+		self.file.read_exact(&mut payload).await.context("truncated payload")?;
+		self.offset += self.pending as u64;
+		self.pending = 0;
+
+		Ok(payload.into())
+	}
+
+	/// The namespace from the header.
+	pub fn namespace(&self) -> &str {
+		&self.namespace
+	}
+
+	pub fn path(&self) -> &path::Path {
+		&self.path
+	}
+
+	/// The number of bytes read so far, which is the end of the last payload.
+	pub fn offset(&self) -> u64 {
+		self.offset
+	}
+}
+
+/// Read a payload from an archive, given the offset and size found via [ArchiveReader].
+pub async fn read_payload(file: &mut tokio::fs::File, offset: u64, size: usize) -> anyhow::Result<Bytes> {
+	file.seek(std::io::SeekFrom::Start(offset)).await?;
+
+	let mut payload = vec![0; size];
+	file.read_exact(&mut payload).await?;
+
+	Ok(payload.into())
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[tokio::test]
+	async fn round_trip() {
+		let path = std::env::temp_dir().join(format!("moq-archive-{}.moqrec", timestamp()));
+
+		let track = Record::Track {
+			track: "video".to_string(),
+			mode: ArchiveMode::Groups,
+		};
+		let object = Record::Object {
+			track: "video".to_string(),
+			group: 1,
+			object: 2,
+			priority: 3,
+			timestamp: 4,
+			size: 5,
+		};
+
+		let mut writer = ArchiveWriter::create(path.clone(), "live/test").await.unwrap();
+		writer.write(&track, Bytes::new()).await.unwrap();
+		writer.write(&object, Bytes::from_static(b"hello")).await.unwrap();
+		writer.finish().await.unwrap();
+
+		let mut reader = ArchiveReader::open(path.clone()).await.unwrap();
+		assert_eq!(reader.namespace(), "live/test");
+		assert_eq!(reader.next().await.unwrap(), Some((track.clone(), Bytes::new())));
+		assert_eq!(
+			reader.next().await.unwrap(),
+			Some((object.clone(), Bytes::from_static(b"hello")))
+		);
+		assert_eq!(reader.next().await.unwrap(), None);
+
+		let offset = reader.offset() - 5;
+		let mut file = tokio::fs::File::open(&path).await.unwrap();
+		assert_eq!(read_payload(&mut file, offset, 5).await.unwrap(), "hello");
+
+		// Payloads that aren't read are skipped.
+		let mut reader = ArchiveReader::open(path.clone()).await.unwrap();
+		assert_eq!(reader.record().await.unwrap(), Some(track));
+		assert_eq!(reader.record().await.unwrap(), Some(object));
+		assert_eq!(reader.record().await.unwrap(), None);
+		assert_eq!(reader.offset(), offset + 5);
+
+		std::fs::remove_file(path).unwrap();
+	}
+}
@@ -0,0 +1,385 @@
+use std::{fs, net, path, time};
+
+use anyhow::Context;
+use serde::Deserialize;
+use tokio::sync::watch;
+use url::Url;
+
+use crate::{ApiRouting, Cli, DrainConfig, DuplicatePolicy, ForwardTarget, Limits, RecordConfig, ReplayConfig};
+
+/// The contents of the relay's TOML configuration file.
+///
+/// Every field is optional and command line flags take precedence over the file.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigFile {
+	/// Listen on this address
+	pub bind: net::SocketAddr,
+
+	/// The TLS configuration.
+	pub tls: TlsFile,
+
+	/// Forward all announces to the provided server for authentication/routing.
+	pub announce: Option<Url>,
+
+	/// Forward announces to each of these servers, optionally filtered by namespace.
+	pub forward: Vec<ForwardTarget>,
+
+	/// The URL of the moq-api server in order to run a cluster.
+	pub api: Option<Url>,
+
+	/// Authenticate with moq-api using the bearer token in this file.
+	pub api_token: Option<path::PathBuf>,
+
+	/// Decides which relay to connect to for origins returned by moq-api.
+	pub api_routing: ApiRouting,
+
+	/// Load a static routing table from this TOML file, used instead of the moq-api server.
+	pub routes: Option<path::PathBuf>,
+
+	/// Connect to these relays and propagate announces over MoQ, forming a mesh.
+	pub peers: Vec<Url>,
+
+	/// Authenticate with peers in the mesh using the shared secret in this file.
+	pub mesh_secret: Option<path::PathBuf>,
+
+	/// Trust relays authenticated with a client certificate for one of these names.
+	pub trust: Vec<String>,
+
+	/// The hostname that we advertise to other origins.
+	pub node: Option<Url>,
+
+	/// Enable development mode.
+	pub dev: bool,
+
+	/// Limits to protect against misbehaving clients, which can be changed live.
+	pub limits: Limits,
+
+	/// Serve administrative endpoints over plain HTTP on this address.
+	pub admin: Option<net::SocketAddr>,
+
+	/// Configuration used when draining the relay, which can be changed live.
+	pub drain: DrainConfig,
+
+	/// What to do when a namespace is announced that is already registered, which can be changed live.
+	pub duplicate: DuplicatePolicy,
+
+	/// Record broadcasts to disk.
+	pub record: RecordConfig,
+
+	/// Serve recorded broadcasts.
+	pub replay: ReplayConfig,
+}
+
+impl Default for ConfigFile {
+	fn default() -> Self {
+		Self {
+			bind: "[::]:443".parse().unwrap(),
+			tls: Default::default(),
+			announce: None,
+			forward: Vec::new(),
+			api: None,
+			api_token: None,
+			api_routing: Default::default(),
+			routes: None,
+			peers: Vec::new(),
+			mesh_secret: None,
+			trust: Vec::new(),
+			node: None,
+			dev: false,
+			limits: Default::default(),
+			admin: None,
+			drain: Default::default(),
+			duplicate: Default::default(),
+			record: Default::default(),
+			replay: Default::default(),
+		}
+	}
+}
+
+impl ConfigFile {
+	pub fn load(path: &path::Path) -> anyhow::Result<Self> {
+		let contents =
+			fs::read_to_string(path).with_context(|| format!("failed to read config: {}", path.display()))?;
+		let config =
+			toml::from_str(&contents).with_context(|| format!("failed to parse config: {}", path.display()))?;
+		Ok(config)
+	}
+
+	/// Every forward target, including the legacy `announce` URL.
+	pub fn forward(&self) -> Vec<ForwardTarget> {
+		let announce = self.announce.clone().map(ForwardTarget::from);
+		announce.into_iter().chain(self.forward.iter().cloned()).collect()
+	}
+
+	/// Read the bearer token used to authenticate with moq-api, if configured.
+	pub fn api_token(&self) -> anyhow::Result<Option<String>> {
+		self.api_token
+			.as_deref()
+			.map(|path| read_secret(path, "api token"))
+			.transpose()
+	}
+
+	/// Read the secret used to authenticate peers in the mesh, if configured.
+	pub fn mesh_secret(&self) -> anyhow::Result<Option<String>> {
+		self.mesh_secret
+			.as_deref()
+			.map(|path| read_secret(path, "mesh secret"))
+			.transpose()
+	}
+
+	// Log any changes that can't be applied without a restart.
+	fn warn_restart(&self, other: &Self) {
+		if self.bind != other.bind {
+			log::warn!("changing bind requires a restart");
+		}
+
+		if self.announce != other.announce || self.forward != other.forward {
+			log::warn!("changing announce or forward requires a restart");
+		}
+
+		if self.api != other.api || self.node != other.node {
+			log::warn!("changing api or node requires a restart");
+		}
+
+		if self.peers != other.peers {
+			log::warn!("changing peers requires a restart");
+		}
+
+		if self.mesh_secret != other.mesh_secret {
+			log::warn!("changing mesh_secret only applies to sessions from peers until a restart");
+		}
+
+		if self.routes.is_some() != other.routes.is_some() {
+			log::warn!("adding or removing routes requires a restart");
+		}
+
+		if self.admin != other.admin {
+			log::warn!("changing admin requires a restart");
+		}
+
+		if self.record != other.record || self.replay != other.replay {
+			log::warn!("changing record or replay requires a restart");
+		}
+
+		if self.dev != other.dev {
+			log::warn!("changing dev requires a restart");
+		}
+
+		if self.tls.root != other.tls.root || self.tls.disable_verify != other.tls.disable_verify {
+			log::warn!("changing tls.root or tls.disable_verify requires a restart");
+		}
+
+		if self.tls.client_cert != other.tls.client_cert
+			|| self.tls.client_key != other.tls.client_key
+			|| self.tls.client_root != other.tls.client_root
+		{
+			log::warn!("changing tls.client_cert, tls.client_key or tls.client_root requires a restart");
+		}
+	}
+}
+
+// Read a secret from a file, ignoring any surrounding whitespace.
+fn read_secret(path: &path::Path, name: &str) -> anyhow::Result<String> {
+	let secret = fs::read_to_string(path).with_context(|| format!("failed to read {}: {}", name, path.display()))?;
+	let secret = secret.trim();
+	anyhow::ensure!(!secret.is_empty(), "empty {}: {}", name, path.display());
+
+	Ok(secret.to_string())
+}
+
+/// The `[tls]` section of the configuration file, mirroring [moq_native::tls::Args].
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsFile {
+	/// Use the certificates at these paths, encoded as PEM.
+	pub cert: Vec<path::PathBuf>,
+
+	/// Use the private keys at these paths, encoded as PEM.
+	pub key: Vec<path::PathBuf>,
+
+	/// Use the TLS roots at these paths, encoded as PEM.
+	pub root: Vec<path::PathBuf>,
+
+	/// Danger: Disable TLS certificate verification.
+	pub disable_verify: bool,
+
+	/// Generate a short-lived, self-signed certificate for these hostnames on startup.
+	pub generate: Vec<String>,
+
+	/// Present this certificate when connecting to other relays, encoded as PEM.
+	pub client_cert: Option<path::PathBuf>,
+
+	/// The private key for `client_cert`, encoded as PEM.
+	pub client_key: Option<path::PathBuf>,
+
+	/// Authenticate clients with a certificate issued by these TLS roots, encoded as PEM.
+	pub client_root: Vec<path::PathBuf>,
+}
+
+impl TlsFile {
+	pub fn args(&self) -> moq_native::tls::Args {
+		moq_native::tls::Args {
+			cert: self.cert.clone(),
+			key: self.key.clone(),
+			root: self.root.clone(),
+			disable_verify: self.disable_verify,
+			generate: self.generate.clone(),
+			client_cert: self.client_cert.clone(),
+			client_key: self.client_key.clone(),
+			client_root: self.client_root.clone(),
+		}
+	}
+}
+
+impl Cli {
+	/// Load the configuration file (if any) and override it with any command line flags.
+	pub fn config(&self) -> anyhow::Result<ConfigFile> {
+		let mut config = match &self.config {
+			Some(path) => ConfigFile::load(path)?,
+			None => ConfigFile::default(),
+		};
+
+		if let Some(bind) = self.bind {
+			config.bind = bind;
+		}
+
+		if !self.tls.cert.is_empty() || !self.tls.key.is_empty() {
+			config.tls.cert.clone_from(&self.tls.cert);
+			config.tls.key.clone_from(&self.tls.key);
+		}
+
+		if !self.tls.generate.is_empty() {
+			config.tls.generate.clone_from(&self.tls.generate);
+		}
+
+		if !self.tls.root.is_empty() {
+			config.tls.root.clone_from(&self.tls.root);
+		}
+
+		if self.tls.client_cert.is_some() {
+			config.tls.client_cert.clone_from(&self.tls.client_cert);
+			config.tls.client_key.clone_from(&self.tls.client_key);
+		}
+
+		if !self.tls.client_root.is_empty() {
+			config.tls.client_root.clone_from(&self.tls.client_root);
+		}
+
+		config.tls.disable_verify |= self.tls.disable_verify;
+		config.dev |= self.dev;
+
+		if !self.announce.is_empty() {
+			// Command line targets replace any in the file.
+			config.announce = None;
+			config.forward = self.announce.iter().cloned().map(ForwardTarget::from).collect();
+		}
+		config.api = self.api.clone().or(config.api);
+		config.api_token = self.api_token.clone().or(config.api_token);
+		config.api_routing.merge(&self.api_routing);
+		config.node = self.node.clone().or(config.node);
+		config.routes = self.routes.clone().or(config.routes);
+
+		if !self.peers.is_empty() {
+			config.peers.clone_from(&self.peers);
+		}
+
+		config.mesh_secret = self.mesh_secret.clone().or(config.mesh_secret);
+
+		if !self.trust.is_empty() {
+			config.trust.clone_from(&self.trust);
+		}
+
+		// Otherwise peers can't tell our sessions apart from any client using the mesh path.
+		anyhow::ensure!(
+			config.peers.is_empty() || config.mesh_secret.is_some() || config.tls.client_cert.is_some(),
+			"peers require --mesh-secret-file or --tls-client-cert to authenticate with each other"
+		);
+
+		anyhow::ensure!(
+			config.api.is_none() || config.routes.is_none(),
+			"api and routes can't be used together"
+		);
+
+		config.admin = self.admin.or(config.admin);
+		config.limits.merge(&self.limits);
+		config.drain.merge(&self.drain);
+		config.duplicate = self.duplicate.unwrap_or(config.duplicate);
+		config.record.merge(&self.record);
+		config.replay.merge(&self.replay);
+
+		Ok(config)
+	}
+}
+
+/// Reloads the configuration on SIGHUP or when the configuration file is modified on disk.
+///
+/// Each new configuration is published to a [watch::Receiver] so components can apply any settings that can change live.
+pub struct Reloader {
+	cli: Cli,
+	config: watch::Sender<ConfigFile>,
+}
+
+impl Reloader {
+	pub fn new(cli: Cli, config: ConfigFile) -> (Self, watch::Receiver<ConfigFile>) {
+		let (send, recv) = watch::channel(config);
+		(Self { cli, config: send }, recv)
+	}
+
+	pub async fn run(self) -> anyhow::Result<()> {
+		let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+			.context("failed to register SIGHUP handler")?;
+
+		// Poll the modification time, which avoids pulling in a file watching library.
+		let mut interval = tokio::time::interval(time::Duration::from_secs(5));
+		let mut modified = self.modified();
+
+		loop {
+			tokio::select! {
+				Some(_) = hangup.recv() => {
+					log::info!("received SIGHUP, reloading config");
+
+					// Always notify on SIGHUP, even if the file is unchanged, so certificates are reloaded from disk.
+					self.reload(true);
+				}
+				_ = interval.tick() => {
+					let latest = self.modified();
+					if latest == modified {
+						continue;
+					}
+
+					modified = latest;
+					log::info!("config file modified, reloading");
+
+					self.reload(false);
+				}
+			}
+		}
+	}
+
+	fn reload(&self, force: bool) {
+		let config = match self.cli.config() {
+			Ok(config) => config,
+			Err(err) => {
+				log::warn!("failed to reload config: {:#}", err);
+				return;
+			}
+		};
+
+		self.config.send_if_modified(|current| {
+			if *current == config {
+				return force;
+			}
+
+			current.warn_restart(&config);
+			*current = config;
+
+			true
+		});
+	}
+
+	fn modified(&self) -> Option<time::SystemTime> {
+		let path = self.cli.config.as_ref()?;
+		fs::metadata(path).and_then(|meta| meta.modified()).ok()
+	}
+}
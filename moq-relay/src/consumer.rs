@@ -1,39 +1,63 @@
 use anyhow::Context;
 use futures::{stream::FuturesUnordered, FutureExt, StreamExt};
 use moq_transport::{
-	serve::Tracks,
+	serve::{ServeError, Track, Tracks},
 	session::{Announced, SessionError, Subscriber},
 };
 
-use crate::{Api, Locals, Producer};
+use crate::{Announces, Api, Locals, SessionLimits};
 
 #[derive(Clone)]
 pub struct Consumer {
 	remote: Subscriber,
 	locals: Locals,
 	api: Option<Api>,
-	forward: Option<Producer>, // Forward all announcements to this subscriber
+	forward: Option<Announces>, // Forward all announcements upstream and to any peers
+	limits: Option<SessionLimits>,
 }
 
 impl Consumer {
-	pub fn new(remote: Subscriber, locals: Locals, api: Option<Api>, forward: Option<Producer>) -> Self {
+	pub fn new(
+		remote: Subscriber,
+		locals: Locals,
+		api: Option<Api>,
+		forward: Option<Announces>,
+		limits: Option<SessionLimits>,
+	) -> Self {
 		Self {
 			remote,
 			locals,
 			api,
 			forward,
+			limits,
 		}
 	}
 
+	/// Ask the client to reconnect to the provided URL.
+	pub fn go_away(&mut self, url: String) {
+		self.remote.go_away(url)
+	}
+
 	pub async fn run(mut self) -> Result<(), SessionError> {
 		let mut tasks = FuturesUnordered::new();
 
 		loop {
 			tokio::select! {
 				Some(announce) = self.remote.announced() => {
+					// Reserve the announce until it's done being served.
+					let permit = match self.limits.as_ref().map(|limits| limits.announce(&announce.namespace)).transpose() {
+						Ok(permit) => permit,
+						Err(err) => {
+							log::warn!("rejecting announce: {:?}, error: {}", announce.info, err);
+							announce.close(ServeError::Closed(err.code().into())).ok();
+							continue;
+						}
+					};
+
 					let this = self.clone();
 
 					tasks.push(async move {
+						let _permit = permit;
 						let info = announce.clone();
 						log::info!("serving announce: {:?}", info);
 
@@ -59,34 +83,50 @@ impl Consumer {
 		}
 
 		// Register the local tracks, unregister on drop
-		let _register = self.locals.register(reader.clone()).await?;
+		let register = self.locals.register(reader.clone()).await?;
 
 		announce.ok()?;
 
-		if let Some(mut forward) = self.forward {
-			tasks.push(
-				async move {
-					log::info!("forwarding announce: {:?}", reader.info);
-					forward.announce(reader).await.context("failed forwarding announce")
-				}
-				.boxed(),
-			);
-		}
+		// Forward the announce, unannouncing on drop
+		let forward = self.forward.as_ref().map(|forward| forward.announce(reader.clone()));
 
 		loop {
 			tokio::select! {
 				// If the announce is closed, return the error
 				Err(err) = announce.closed() => return Err(err.into()),
 
+				// If another publisher replaced this announce, close it.
+				_ = register.evicted() => {
+					announce.close(ServeError::Cancel).ok();
+					anyhow::bail!("replaced by a newer announce");
+				},
+
+				// If an upstream rejected this announce, close it with the same error.
+				Some(err) = async { Some(forward.as_ref()?.rejected().await) } => {
+					announce.close(err.clone()).ok();
+					anyhow::bail!("rejected upstream: {}", err);
+				},
+
 				// Wait for the next subscriber and serve the track.
 				Some(track) = request.next() => {
 					let mut remote = self.remote.clone();
+					let meter = self.limits.as_ref().and_then(|limits| limits.ingress(&track.namespace));
 
 					tasks.push(async move {
 						let info = track.clone();
 						log::info!("forwarding subscribe: {:?}", info);
 
-						if let Err(err) = remote.subscribe(track).await {
+						let res = match meter {
+							// Subscribe to a separate track and copy it, counting every byte against the ingress quota.
+							Some(meter) => {
+								let (writer, reader) = Track::new(track.namespace.clone(), track.name.clone()).produce();
+								let (res, _) = tokio::join!(remote.subscribe(writer), meter.copy(reader, track));
+								res
+							}
+							None => remote.subscribe(track).await,
+						};
+
+						if let Err(err) = res {
 							log::warn!("failed forwarding subscribe: {:?}, error: {}", info, err)
 						}
 
@@ -0,0 +1,132 @@
+use std::sync::{Arc, Mutex};
+use std::time;
+
+use serde::Deserialize;
+use tokio::sync::watch;
+use url::Url;
+
+/// Configuration used when draining the relay before shutdown.
+#[derive(clap::Args, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DrainConfig {
+	/// Send this URL in a GOAWAY when draining, asking clients to reconnect to a replacement relay.
+	/// If not provided, clients are asked to reconnect to the same URL.
+	#[arg(long = "drain-url")]
+	pub url: Option<Url>,
+
+	/// The maximum number of seconds to wait for sessions to finish when draining [default: 30]
+	#[arg(long = "drain-timeout")]
+	pub timeout: Option<u64>,
+}
+
+impl DrainConfig {
+	/// Override any values that are set in `other`.
+	pub fn merge(&mut self, other: &DrainConfig) {
+		self.url = other.url.clone().or(self.url.take());
+		self.timeout = other.timeout.or(self.timeout);
+	}
+
+	pub fn timeout(&self) -> time::Duration {
+		time::Duration::from_secs(self.timeout.unwrap_or(30))
+	}
+}
+
+/// Used to trigger and observe a graceful drain of the relay.
+///
+/// When draining, the relay refuses new sessions, sends a GOAWAY to existing sessions,
+/// deregisters origins from moq-api, and exits once every session is closed or the timeout expires.
+#[derive(Clone)]
+pub struct Drain {
+	state: Arc<watch::Sender<bool>>,
+	config: Arc<Mutex<DrainConfig>>,
+}
+
+impl Drain {
+	pub fn new(config: DrainConfig) -> Self {
+		let (state, _) = watch::channel(false);
+
+		Self {
+			state: Arc::new(state),
+			config: Arc::new(Mutex::new(config)),
+		}
+	}
+
+	/// Start draining, returning false if we were already draining.
+	pub fn start(&self) -> bool {
+		let started = !self.state.send_replace(true);
+		if started {
+			log::info!("draining: {:?}", self.config());
+		}
+
+		started
+	}
+
+	pub fn is_draining(&self) -> bool {
+		*self.state.borrow()
+	}
+
+	/// Wait until we start draining.
+	pub async fn wait(&self) {
+		let mut state = self.state.subscribe();
+
+		// The sender can't be dropped while we hold a reference.
+		state.wait_for(|draining| *draining).await.ok();
+	}
+
+	/// Replace the configuration used by any future drain.
+	pub fn update(&self, config: DrainConfig) {
+		*self.config.lock().unwrap() = config;
+	}
+
+	pub fn config(&self) -> DrainConfig {
+		self.config.lock().unwrap().clone()
+	}
+
+	/// The URL to send in a GOAWAY message, empty if clients should reconnect to the same URL.
+	pub fn url(&self) -> String {
+		self.config().url.map(|url| url.to_string()).unwrap_or_default()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[tokio::test]
+	async fn start() {
+		let drain = Drain::new(DrainConfig::default());
+		assert!(!drain.is_draining());
+
+		let wait = tokio::spawn({
+			let drain = drain.clone();
+			async move { drain.wait().await }
+		});
+
+		// Only the first call starts draining.
+		assert!(drain.start());
+		assert!(!drain.start());
+		assert!(drain.is_draining());
+
+		wait.await.unwrap();
+
+		// Waiting after draining started returns immediately.
+		drain.wait().await;
+	}
+
+	#[test]
+	fn update() {
+		let drain = Drain::new(DrainConfig::default());
+		assert_eq!(drain.url(), "");
+		assert_eq!(drain.config().timeout(), time::Duration::from_secs(30));
+
+		drain.update(DrainConfig {
+			url: Some("https://backup.example.com/".parse().unwrap()),
+			timeout: Some(5),
+		});
+
+		// Clones share the configuration, so a reload applies to any future drain.
+		let clone = drain.clone();
+		assert_eq!(clone.url(), "https://backup.example.com/");
+		assert_eq!(clone.config().timeout(), time::Duration::from_secs(5));
+	}
+}
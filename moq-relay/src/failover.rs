@@ -0,0 +1,244 @@
+use std::time;
+
+use futures::{stream::FuturesUnordered, StreamExt};
+use moq_transport::serve::{
+	Datagram, DatagramsReader, DatagramsWriter, Group, GroupReader, GroupWriter, GroupsReader, GroupsWriter, Object,
+	ObjectReader, ObjectWriter, ObjectsReader, ObjectsWriter, ServeError, StreamGroupReader, StreamGroupWriter,
+	StreamReader, StreamWriter, TrackReader, TrackReaderMode, TrackWriter, TrackWriterMode,
+};
+use tokio::sync::watch;
+
+use crate::Locals;
+
+// How long to wait for a backup publisher after the active publisher's track ends.
+// This covers the gap between the track closing and the announce being unregistered.
+const FAILOVER_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+
+/// Copies a track from the active publisher of a namespace, switching to the next publisher when it disappears.
+///
+/// Group IDs are shifted when switching so they keep increasing, otherwise the new publisher's groups would be dropped.
+pub struct Failover {
+	pub locals: Locals,
+	pub namespace: String,
+	pub name: String,
+
+	// Notified when the active publisher changes.
+	pub changed: watch::Receiver<Option<u64>>,
+}
+
+impl Failover {
+	pub async fn run(mut self, writer: TrackWriter, mut id: u64, mut track: TrackReader) -> Result<(), ServeError> {
+		// Pick the output mode based on the first publisher.
+		let mut output: TrackWriterMode = match track.mode().await {
+			Ok(TrackReaderMode::Stream(stream)) => writer.stream(stream.priority)?.into(),
+			Ok(TrackReaderMode::Groups(_)) => writer.groups()?.into(),
+			Ok(TrackReaderMode::Objects(_)) => writer.objects()?.into(),
+			Ok(TrackReaderMode::Datagrams(_)) => writer.datagrams()?.into(),
+			Err(err) => return writer.close(err.clone()).and(Err(err)),
+		};
+
+		let mut groups = GroupIds::default();
+
+		loop {
+			let res = Self::copy(&mut output, track, &mut groups).await;
+
+			// The track ended, which happens when the publisher goes away, so wait for another publisher.
+			let changed = self.changed.wait_for(|active| *active != Some(id));
+			let changed = matches!(tokio::time::timeout(FAILOVER_TIMEOUT, changed).await, Ok(Ok(_)));
+
+			let next = match self.locals.active(&self.namespace) {
+				Some((next, tracks, _)) if changed => (next, tracks),
+				_ => {
+					let err = res.err().unwrap_or(ServeError::Done);
+					return output.close(err.clone()).and(Err(err));
+				}
+			};
+
+			id = next.0;
+			track = match next.1.clone().subscribe(&self.name) {
+				Some(track) => track,
+				None => return output.close(ServeError::NotFound).and(Err(ServeError::NotFound)),
+			};
+
+			log::info!("failing over track: {:?}", track.info);
+			groups.switch();
+		}
+	}
+
+	async fn copy(output: &mut TrackWriterMode, track: TrackReader, groups: &mut GroupIds) -> Result<(), ServeError> {
+		match (track.mode().await?, output) {
+			(TrackReaderMode::Stream(reader), TrackWriterMode::Stream(writer)) => {
+				Self::copy_stream(reader, writer, groups).await
+			}
+			(TrackReaderMode::Groups(reader), TrackWriterMode::Groups(writer)) => {
+				Self::copy_groups(reader, writer, groups).await
+			}
+			(TrackReaderMode::Objects(reader), TrackWriterMode::Objects(writer)) => {
+				Self::copy_objects(reader, writer, groups).await
+			}
+			(TrackReaderMode::Datagrams(reader), TrackWriterMode::Datagrams(writer)) => {
+				Self::copy_datagrams(reader, writer, groups).await
+			}
+			_ => Err(ServeError::Mode),
+		}
+	}
+
+	async fn copy_stream(
+		mut reader: StreamReader,
+		writer: &mut StreamWriter,
+		groups: &mut GroupIds,
+	) -> Result<(), ServeError> {
+		let mut tasks = FuturesUnordered::new();
+
+		loop {
+			tokio::select! {
+				res = reader.next() => match res? {
+					Some(group) => {
+						let output = writer.create(groups.map(group.group_id))?;
+						tasks.push(Self::copy_stream_group(group, output));
+					}
+					None => break,
+				},
+				Some(res) = tasks.next() => res?,
+			}
+		}
+
+		// Finish copying any groups that are still in progress.
+		while let Some(res) = tasks.next().await {
+			res?;
+		}
+
+		Ok(())
+	}
+
+	async fn copy_stream_group(mut group: StreamGroupReader, mut output: StreamGroupWriter) -> Result<(), ServeError> {
+		while let Some(mut object) = group.next().await? {
+			let mut output = output.create(object.size)?;
+			while let Some(chunk) = object.read().await? {
+				output.write(chunk)?;
+			}
+		}
+
+		Ok(())
+	}
+
+	async fn copy_groups(
+		mut reader: GroupsReader,
+		writer: &mut GroupsWriter,
+		groups: &mut GroupIds,
+	) -> Result<(), ServeError> {
+		let mut tasks = FuturesUnordered::new();
+
+		loop {
+			tokio::select! {
+				res = reader.next() => match res? {
+					Some(group) => {
+						let output = writer.create(Group {
+							group_id: groups.map(group.group_id),
+							priority: group.priority,
+						})?;
+						tasks.push(Self::copy_group(group, output));
+					}
+					None => break,
+				},
+				Some(res) = tasks.next() => res?,
+			}
+		}
+
+		// Finish copying any groups that are still in progress.
+		while let Some(res) = tasks.next().await {
+			res?;
+		}
+
+		Ok(())
+	}
+
+	async fn copy_group(mut group: GroupReader, mut output: GroupWriter) -> Result<(), ServeError> {
+		while let Some(mut object) = group.next().await? {
+			let mut output = output.create(object.size)?;
+			while let Some(chunk) = object.read().await? {
+				output.write(chunk)?;
+			}
+		}
+
+		Ok(())
+	}
+
+	async fn copy_objects(
+		mut reader: ObjectsReader,
+		writer: &mut ObjectsWriter,
+		groups: &mut GroupIds,
+	) -> Result<(), ServeError> {
+		let mut tasks = FuturesUnordered::new();
+
+		loop {
+			tokio::select! {
+				res = reader.next() => match res? {
+					Some(object) => {
+						let output = writer.create(Object {
+							group_id: groups.map(object.group_id),
+							object_id: object.object_id,
+							priority: object.priority,
+						})?;
+						tasks.push(Self::copy_object(object, output));
+					}
+					None => break,
+				},
+				Some(res) = tasks.next() => res?,
+			}
+		}
+
+		// Finish copying any groups that are still in progress.
+		while let Some(res) = tasks.next().await {
+			res?;
+		}
+
+		Ok(())
+	}
+
+	async fn copy_object(mut object: ObjectReader, mut output: ObjectWriter) -> Result<(), ServeError> {
+		while let Some(chunk) = object.read().await? {
+			output.write(chunk)?;
+		}
+
+		Ok(())
+	}
+
+	async fn copy_datagrams(
+		mut reader: DatagramsReader,
+		writer: &mut DatagramsWriter,
+		groups: &mut GroupIds,
+	) -> Result<(), ServeError> {
+		while let Some(datagram) = reader.read().await? {
+			writer.write(Datagram {
+				group_id: groups.map(datagram.group_id),
+				..datagram
+			})?;
+		}
+
+		Ok(())
+	}
+}
+
+// Shifts group IDs so they keep increasing across publishers.
+#[derive(Default)]
+struct GroupIds {
+	// The smallest group ID that won't be dropped as a duplicate.
+	next: u64,
+
+	// The amount to add to each group ID, computed from the first group of each publisher.
+	shift: Option<u64>,
+}
+
+impl GroupIds {
+	fn map(&mut self, group_id: u64) -> u64 {
+		let shift = *self.shift.get_or_insert(self.next.saturating_sub(group_id));
+		let group_id = group_id.saturating_add(shift);
+		self.next = self.next.max(group_id.saturating_add(1));
+		group_id
+	}
+
+	fn switch(&mut self) {
+		self.shift = None;
+	}
+}
@@ -0,0 +1,400 @@
+use std::collections::{hash_map, HashMap};
+use std::sync::atomic::{self, AtomicU64};
+use std::sync::{Arc, Mutex};
+use std::time;
+
+use anyhow::Context;
+use futures::future::{AbortHandle, Abortable};
+use futures::{stream::FuturesUnordered, FutureExt, StreamExt};
+use moq_native::quic;
+use moq_transport::serve::{ServeError, TracksReader};
+use moq_transport::session::SessionError;
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use url::Url;
+
+use crate::{glob, Consumer, Locals, Mesh, Producer, RemotesConsumer, Session};
+
+// The delay between reconnects, doubling after each failure.
+const MIN_BACKOFF: time::Duration = time::Duration::from_secs(1);
+const MAX_BACKOFF: time::Duration = time::Duration::from_secs(30);
+
+/// A server that receives our announces, configured via `[[forward]]` in the configuration file.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ForwardTarget {
+	/// Connect to this URL.
+	pub url: Url,
+
+	/// Only forward namespaces matching one of these patterns, or every namespace if empty.
+	#[serde(default)]
+	pub namespaces: Vec<String>,
+}
+
+impl From<Url> for ForwardTarget {
+	fn from(url: Url) -> Self {
+		Self {
+			url,
+			namespaces: Vec::new(),
+		}
+	}
+}
+
+impl ForwardTarget {
+	fn matches(&self, namespace: &str) -> bool {
+		self.namespaces.is_empty() || self.namespaces.iter().any(|pattern| glob(pattern, namespace))
+	}
+}
+
+/// The namespaces announced by our clients, which are announced again to every [Forward].
+#[derive(Clone)]
+pub struct Announces {
+	state: Arc<watch::Sender<HashMap<u64, TracksReader>>>,
+	next: Arc<AtomicU64>,
+
+	// Used to tell each announcer that an upstream rejected it.
+	rejected: Arc<Mutex<HashMap<u64, watch::Sender<Option<ServeError>>>>>,
+}
+
+impl Default for Announces {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Announces {
+	pub fn new() -> Self {
+		let (state, _) = watch::channel(HashMap::new());
+
+		Self {
+			state: Arc::new(state),
+			next: Default::default(),
+			rejected: Default::default(),
+		}
+	}
+
+	/// Forward the tracks until the returned handle is dropped.
+	pub fn announce(&self, tracks: TracksReader) -> ForwardAnnounce {
+		let id = self.next.fetch_add(1, atomic::Ordering::Relaxed);
+		let (rejected, rejected_recv) = watch::channel(None);
+
+		self.rejected.lock().unwrap().insert(id, rejected);
+		self.state.send_modify(|state| {
+			state.insert(id, tracks);
+		});
+
+		ForwardAnnounce {
+			announces: self.clone(),
+			id,
+			rejected: rejected_recv,
+		}
+	}
+
+	/// Returns a channel that is notified whenever a namespace is announced or unannounced.
+	pub fn subscribe(&self) -> watch::Receiver<HashMap<u64, TracksReader>> {
+		self.state.subscribe()
+	}
+
+	/// Tell the announcer that an upstream rejected the announce.
+	pub fn reject(&self, id: u64, err: ServeError) {
+		if let Some(rejected) = self.rejected.lock().unwrap().get(&id) {
+			rejected.send_replace(Some(err));
+		}
+	}
+
+	/// Return the oldest announce of each namespace, so a namespace with a backup publisher is only used once.
+	pub fn unique(announces: &HashMap<u64, TracksReader>) -> HashMap<u64, TracksReader> {
+		let mut oldest: HashMap<&str, (u64, &TracksReader)> = HashMap::new();
+
+		for (id, tracks) in announces {
+			match oldest.entry(tracks.namespace.as_str()) {
+				hash_map::Entry::Occupied(mut entry) => {
+					if entry.get().0 > *id {
+						entry.insert((*id, tracks));
+					}
+				}
+				hash_map::Entry::Vacant(entry) => {
+					entry.insert((*id, tracks));
+				}
+			}
+		}
+
+		oldest.into_values().map(|(id, tracks)| (id, tracks.clone())).collect()
+	}
+}
+
+/// Keeps a namespace forwarded, sending an UNANNOUNCE when dropped.
+pub struct ForwardAnnounce {
+	announces: Announces,
+	id: u64,
+	rejected: watch::Receiver<Option<ServeError>>,
+}
+
+impl ForwardAnnounce {
+	/// Wait until an upstream rejects the announce, returning its error.
+	pub async fn rejected(&self) -> ServeError {
+		let mut rejected = self.rejected.clone();
+		let err = match rejected.wait_for(Option::is_some).await {
+			Ok(err) => err.clone(),
+			Err(_) => None,
+		};
+
+		match err {
+			Some(err) => err,
+			// Never rejected, so block forever.
+			None => std::future::pending().await,
+		}
+	}
+}
+
+impl Drop for ForwardAnnounce {
+	fn drop(&mut self) {
+		self.announces.rejected.lock().unwrap().remove(&self.id);
+		self.announces.state.send_modify(|state| {
+			state.remove(&self.id);
+		});
+	}
+}
+
+/// The health of a [Forward] session.
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct ForwardStatus {
+	/// True if the session is currently established.
+	pub connected: bool,
+
+	/// The number of consecutive failed attempts, reset once connected.
+	pub failures: u32,
+
+	/// The most recent error, if any.
+	pub error: Option<String>,
+
+	/// The number of namespaces currently announced.
+	pub announced: usize,
+}
+
+pub struct ForwardConfig {
+	pub target: ForwardTarget,
+
+	/// The namespaces to announce.
+	pub announces: Announces,
+
+	/// Used to serve subscriptions from the upstream, and to register any announces it sends us.
+	pub locals: Locals,
+	pub remotes: Option<RemotesConsumer>,
+
+	pub quic: quic::Client,
+}
+
+/// A supervised session to an upstream server that receives our announces.
+///
+/// The session is reconnected with exponential backoff and every matching namespace is announced again.
+#[derive(Clone)]
+pub struct Forward {
+	config: Arc<ForwardConfig>,
+	status: Arc<Mutex<ForwardStatus>>,
+
+	// The URL without any mesh secret, used for logs and status.
+	url: Url,
+}
+
+impl Forward {
+	pub fn new(config: ForwardConfig) -> Self {
+		Self {
+			url: Mesh::redact(&config.target.url),
+			config: Arc::new(config),
+			status: Default::default(),
+		}
+	}
+
+	/// The URL of the upstream with any mesh secret redacted, so it's safe to log or report.
+	pub fn url(&self) -> &Url {
+		&self.url
+	}
+
+	pub fn status(&self) -> ForwardStatus {
+		self.status.lock().unwrap().clone()
+	}
+
+	/// Keep the session connected, forever.
+	pub async fn run(self) -> anyhow::Result<()> {
+		let mut backoff = MIN_BACKOFF;
+
+		loop {
+			let connected = time::Instant::now();
+
+			let err = match self.connect().await {
+				Ok(()) => anyhow::anyhow!("session closed"),
+				Err(err) => err,
+			};
+
+			// Reset the backoff if we were connected for a while.
+			if connected.elapsed() > MAX_BACKOFF {
+				backoff = MIN_BACKOFF;
+			}
+
+			let failures = {
+				let mut status = self.status.lock().unwrap();
+				status.connected = false;
+				status.announced = 0;
+				status.failures += 1;
+				status.error = Some(format!("{:#}", err));
+				status.failures
+			};
+
+			log::warn!(
+				"forward failed: url={} failures={} retry={:?} error={:#}",
+				self.url(),
+				failures,
+				backoff,
+				err
+			);
+
+			tokio::time::sleep(backoff).await;
+			backoff = (backoff * 2).min(MAX_BACKOFF);
+		}
+	}
+
+	async fn connect(&self) -> anyhow::Result<()> {
+		let config = &self.config;
+		let url = self.url();
+
+		let session = config
+			.quic
+			.connect(&config.target.url)
+			.await
+			.context("failed to connect")?;
+		let (session, publisher, subscriber) = moq_transport::session::Session::connect(session)
+			.await
+			.context("failed to establish session")?;
+
+		log::info!("forward connected: url={}", url);
+
+		{
+			let mut status = self.status.lock().unwrap();
+			status.connected = true;
+			status.failures = 0;
+			status.error = None;
+		}
+
+		// Create a normal looking session, except we never forward or propagate announces.
+		let producer = Producer::new(publisher, config.locals.clone(), config.remotes.clone(), None, None);
+		let session = Session {
+			session,
+			producer: Some(producer.clone()),
+			consumer: Some(Consumer::new(subscriber, config.locals.clone(), None, None, None)),
+			drain: None,
+		};
+
+		let mut session = session.run().boxed();
+
+		let mut announces = config.announces.subscribe();
+		announces.mark_changed();
+
+		// Abort an announce to send an UNANNOUNCE.
+		let mut running: HashMap<u64, AbortHandle> = HashMap::new();
+		let mut tasks = FuturesUnordered::new();
+
+		loop {
+			tokio::select! {
+				res = &mut session => return res.context("session failed"),
+				res = announces.changed() => {
+					res.context("announces closed")?;
+
+					// A namespace is only announced once, even if a backup publisher announced it too.
+					// Once the primary is unannounced, the backup is announced instead.
+					let mut current = Announces::unique(&announces.borrow_and_update());
+					current.retain(|_, tracks| config.target.matches(&tracks.namespace));
+
+					// An aborted announce is polled before any announce pushed after it, so it's unannounced first.
+					running.retain(|id, abort| {
+						let keep = current.contains_key(id);
+						if !keep {
+							abort.abort();
+						}
+						keep
+					});
+
+					for (id, tracks) in current {
+						if running.contains_key(&id) {
+							continue;
+						}
+
+						let (abort, registration) = AbortHandle::new_pair();
+						running.insert(id, abort);
+
+						let mut producer = producer.clone();
+						let announces = config.announces.clone();
+						let url = url.clone();
+
+						tasks.push(Abortable::new(async move {
+							log::info!("forwarding announce: url={} namespace={}", url, tracks.namespace);
+							match producer.announce(tracks.clone()).await {
+								Ok(()) => {},
+								// The upstream replied with an ANNOUNCE_ERROR, so tell the client.
+								Err(SessionError::Serve(err @ ServeError::Closed(_))) => {
+									log::warn!("upstream rejected announce: url={} namespace={} error={}", url, tracks.namespace, err);
+									announces.reject(id, err);
+								},
+								Err(err) => log::warn!("failed forwarding announce: url={} namespace={} error={}", url, tracks.namespace, err),
+							}
+						}, registration));
+					}
+
+					self.status.lock().unwrap().announced = running.len();
+				},
+				_ = tasks.next(), if !tasks.is_empty() => {},
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	use moq_transport::serve::Tracks;
+
+	#[test]
+	fn unique() {
+		let announces = Announces::new();
+
+		let (_primary_writer, _primary_request, primary) = Tracks::new("live/foo".to_string()).produce();
+		let (_backup_writer, _backup_request, backup) = Tracks::new("live/foo".to_string()).produce();
+		let (_other_writer, _other_request, other) = Tracks::new("live/bar".to_string()).produce();
+
+		let first = announces.announce(primary);
+		let _second = announces.announce(backup);
+		let _third = announces.announce(other);
+
+		// The backup isn't used while the primary is announced.
+		let current = Announces::unique(&announces.subscribe().borrow());
+		assert_eq!(current.len(), 2);
+		assert!(current.contains_key(&0));
+		assert!(current.contains_key(&2));
+
+		drop(first);
+		let current = Announces::unique(&announces.subscribe().borrow());
+		assert_eq!(current.len(), 2);
+		assert!(current.contains_key(&1));
+	}
+
+	#[tokio::test]
+	async fn rejected() {
+		let announces = Announces::new();
+		let (_writer, _request, reader) = Tracks::new("live/foo".to_string()).produce();
+
+		let forward = announces.announce(reader);
+		assert!(
+			tokio::time::timeout(time::Duration::from_millis(10), forward.rejected())
+				.await
+				.is_err()
+		);
+
+		announces.reject(0, ServeError::Closed(403));
+		assert_eq!(forward.rejected().await, ServeError::Closed(403));
+
+		// Rejecting an announce that's gone does nothing.
+		drop(forward);
+		announces.reject(0, ServeError::Closed(403));
+	}
+}
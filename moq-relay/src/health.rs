@@ -0,0 +1,137 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time;
+
+use serde::Serialize;
+
+use crate::{Api, Drain, Forward};
+
+// How long to wait for moq-api before reporting it as unhealthy.
+const API_TIMEOUT: time::Duration = time::Duration::from_secs(2);
+
+/// The state used to answer health and readiness probes.
+#[derive(Clone)]
+pub struct Health {
+	listening: Arc<AtomicBool>,
+	drain: Drain,
+	api: Option<Api>,
+	forwards: Vec<Forward>,
+}
+
+impl Health {
+	pub fn new(drain: Drain, api: Option<Api>, forwards: Vec<Forward>) -> Self {
+		Self {
+			listening: Default::default(),
+			drain,
+			api,
+			forwards,
+		}
+	}
+
+	/// Mark whether the QUIC endpoint is accepting sessions.
+	pub fn set_listening(&self, listening: bool) {
+		self.listening.store(listening, Ordering::Relaxed);
+	}
+
+	/// True if the QUIC endpoint is accepting sessions, even if it's draining.
+	pub fn is_listening(&self) -> bool {
+		self.listening.load(Ordering::Relaxed)
+	}
+
+	/// Check each dependency, returning a report of which ones are failing.
+	pub async fn check(&self) -> HealthReport {
+		let mut checks = vec![
+			HealthCheck::new("listening", self.is_listening(), "QUIC endpoint is not listening"),
+			HealthCheck::new("draining", !self.drain.is_draining(), "relay is draining"),
+		];
+
+		if let Some(api) = &self.api {
+			let error = match tokio::time::timeout(API_TIMEOUT, api.check()).await {
+				Ok(Ok(())) => None,
+				Ok(Err(err)) => Some(err.to_string()),
+				Err(_) => Some("timed out".to_string()),
+			};
+
+			checks.push(HealthCheck {
+				name: "api".to_string(),
+				ok: error.is_none(),
+				error,
+			});
+
+			let error = api.refresh_error();
+			checks.push(HealthCheck {
+				name: "api:refresh".to_string(),
+				ok: error.is_none(),
+				error,
+			});
+		}
+
+		// Mesh peers are not checked; any one of them may be down without affecting this relay.
+		for forward in &self.forwards {
+			let status = forward.status();
+			let error = match status.connected {
+				true => None,
+				false => Some(status.error.unwrap_or_else(|| "not connected".to_string())),
+			};
+
+			checks.push(HealthCheck {
+				name: format!("forward:{}", forward.url()),
+				ok: error.is_none(),
+				error,
+			});
+		}
+
+		HealthReport {
+			ready: checks.iter().all(|check| check.ok),
+			checks,
+		}
+	}
+}
+
+/// The result of a readiness check, reported by `GET /readyz`.
+#[derive(Serialize, Debug)]
+pub struct HealthReport {
+	/// True if every check passed.
+	pub ready: bool,
+	pub checks: Vec<HealthCheck>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct HealthCheck {
+	pub name: String,
+	pub ok: bool,
+
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub error: Option<String>,
+}
+
+impl HealthCheck {
+	fn new(name: &str, ok: bool, error: &str) -> Self {
+		Self {
+			name: name.to_string(),
+			ok,
+			error: (!ok).then(|| error.to_string()),
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::DrainConfig;
+
+	#[tokio::test]
+	async fn readiness() {
+		let drain = Drain::new(DrainConfig::default());
+		let health = Health::new(drain.clone(), None, Vec::new());
+		assert!(!health.check().await.ready);
+
+		health.set_listening(true);
+		assert!(health.check().await.ready);
+
+		drain.start();
+		let report = health.check().await;
+		assert!(!report.ready);
+		assert_eq!(report.checks[1].error.as_deref(), Some("relay is draining"));
+	}
+}
@@ -0,0 +1,454 @@
+use std::collections::HashMap;
+use std::net;
+use std::sync::atomic::{self, AtomicU64};
+use std::sync::{Arc, Mutex};
+use std::time;
+
+use serde::Deserialize;
+
+use crate::{Meter, Quota, QuotaAnnounce, Quotas};
+
+/// Optional limits used to protect the relay against misbehaving clients.
+///
+/// These can be changed at runtime by reloading the configuration file.
+#[derive(clap::Args, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+	/// The maximum number of concurrent client sessions across every IP address.
+	#[arg(long = "limit-sessions")]
+	pub sessions: Option<usize>,
+
+	/// The maximum number of concurrent sessions from a single IP address.
+	#[arg(long = "limit-sessions-per-ip")]
+	pub sessions_per_ip: Option<usize>,
+
+	/// The maximum number of new connections per second from a single IP address.
+	#[arg(long = "limit-connection-rate")]
+	pub connection_rate: Option<f64>,
+
+	/// The number of new connections allowed in a burst, above the connection rate [default: connection rate]
+	#[arg(long = "limit-connection-burst")]
+	pub connection_burst: Option<f64>,
+
+	/// The maximum number of concurrent announces within a session.
+	#[arg(long = "limit-announces-per-session")]
+	pub announces_per_session: Option<usize>,
+
+	/// The maximum number of concurrent subscriptions within a session.
+	#[arg(long = "limit-subscriptions-per-session")]
+	pub subscriptions_per_session: Option<usize>,
+
+	/// Bandwidth quotas for matching namespaces, only configurable via the configuration file.
+	#[arg(skip)]
+	pub quota: Vec<Quota>,
+}
+
+impl Limits {
+	/// Override any limits that are set in `other`.
+	pub fn merge(&mut self, other: &Limits) {
+		self.sessions = other.sessions.or(self.sessions);
+		self.sessions_per_ip = other.sessions_per_ip.or(self.sessions_per_ip);
+		self.connection_rate = other.connection_rate.or(self.connection_rate);
+		self.connection_burst = other.connection_burst.or(self.connection_burst);
+		self.announces_per_session = other.announces_per_session.or(self.announces_per_session);
+		self.subscriptions_per_session = other.subscriptions_per_session.or(self.subscriptions_per_session);
+	}
+
+	fn burst(&self) -> Option<f64> {
+		// Always allow at least one connection, otherwise a low rate would reject everything.
+		let rate = self.connection_rate?;
+		Some(self.connection_burst.unwrap_or(rate).max(1.0))
+	}
+}
+
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq)]
+pub enum LimitError {
+	#[error("too many sessions on the relay")]
+	Capacity,
+
+	#[error("too many sessions from this IP")]
+	Sessions,
+
+	#[error("connection rate exceeded")]
+	Rate,
+
+	#[error("too many announces")]
+	Announces,
+
+	#[error("too many subscriptions")]
+	Subscriptions,
+
+	#[error("quota exceeded")]
+	Quota,
+}
+
+impl LimitError {
+	/// The error code used when rejecting the connection, announce or subscription, based on HTTP status codes.
+	///
+	/// Each scope has a different code so clients can tell them apart:
+	/// - 429 Too Many Requests: a per-IP or per-session limit, caused by this client.
+	/// - 503 Service Unavailable: the relay-wide session limit, so another relay should be tried.
+	/// - 509 Bandwidth Limit Exceeded: a per-namespace or per-publisher quota.
+	pub fn code(&self) -> u32 {
+		match self {
+			Self::Sessions | Self::Rate | Self::Announces | Self::Subscriptions => 429,
+			Self::Capacity => 503,
+			Self::Quota => 509,
+		}
+	}
+}
+
+// Prune idle peers once we're tracking this many, so the map doesn't grow forever.
+const PRUNE_PEERS: usize = 4096;
+
+struct PeerState {
+	sessions: usize,
+
+	// A token bucket for the connection rate.
+	tokens: f64,
+	updated: time::Instant,
+}
+
+impl PeerState {
+	fn new(limits: &Limits, now: time::Instant) -> Self {
+		Self {
+			sessions: 0,
+			tokens: limits.burst().unwrap_or_default(),
+			updated: now,
+		}
+	}
+
+	fn refill(&mut self, limits: &Limits, now: time::Instant) {
+		if let (Some(rate), Some(burst)) = (limits.connection_rate, limits.burst()) {
+			let elapsed = now.duration_since(self.updated).as_secs_f64();
+			self.tokens = (self.tokens + elapsed * rate).min(burst);
+		}
+
+		self.updated = now;
+	}
+
+	fn idle(&self, limits: &Limits) -> bool {
+		self.sessions == 0 && limits.burst().is_none_or(|burst| self.tokens >= burst)
+	}
+}
+
+struct LimiterState {
+	limits: Limits,
+	peers: HashMap<net::IpAddr, PeerState>,
+
+	// The number of sessions across every peer.
+	sessions: usize,
+}
+
+/// Enforces [Limits] across every session.
+#[derive(Clone)]
+pub struct Limiter {
+	state: Arc<Mutex<LimiterState>>,
+	quotas: Quotas,
+
+	// Used to identify each session for per-publisher quotas.
+	next: Arc<AtomicU64>,
+}
+
+impl Limiter {
+	pub fn new(limits: Limits) -> Self {
+		let quotas = Quotas::new(limits.quota.clone());
+		let state = LimiterState {
+			limits,
+			peers: HashMap::new(),
+			sessions: 0,
+		};
+
+		Self {
+			state: Arc::new(Mutex::new(state)),
+			quotas,
+			next: Default::default(),
+		}
+	}
+
+	/// Replace the limits, applying to any new connections, announces and subscriptions.
+	pub fn update(&self, limits: Limits) {
+		self.quotas.update(limits.quota.clone());
+		self.state.lock().unwrap().limits = limits;
+	}
+
+	/// The bandwidth usage of each quota.
+	pub fn quotas(&self) -> Quotas {
+		self.quotas.clone()
+	}
+
+	/// Admit a new session from the given address, returning a handle that enforces per-session limits.
+	///
+	/// The session counts against the IP address until every clone of the handle is dropped.
+	pub fn admit(&self, addr: net::SocketAddr) -> Result<SessionLimits, LimitError> {
+		let ip = addr.ip().to_canonical();
+		let now = time::Instant::now();
+
+		let mut state = self.state.lock().unwrap();
+		let state = &mut *state;
+
+		if let Some(max) = state.limits.sessions {
+			if state.sessions >= max {
+				return Err(LimitError::Capacity);
+			}
+		}
+
+		if state.peers.len() >= PRUNE_PEERS {
+			let limits = &state.limits;
+			state.peers.retain(|_, peer| {
+				peer.refill(limits, now);
+				!peer.idle(limits)
+			});
+		}
+
+		let peer = state
+			.peers
+			.entry(ip)
+			.or_insert_with(|| PeerState::new(&state.limits, now));
+		peer.refill(&state.limits, now);
+
+		if let Some(max) = state.limits.sessions_per_ip {
+			if peer.sessions >= max {
+				return Err(LimitError::Sessions);
+			}
+		}
+
+		if state.limits.connection_rate.is_some() {
+			if peer.tokens < 1.0 {
+				return Err(LimitError::Rate);
+			}
+
+			peer.tokens -= 1.0;
+		}
+
+		peer.sessions += 1;
+		state.sessions += 1;
+
+		let session = SessionState {
+			limiter: self.clone(),
+			id: self.next.fetch_add(1, atomic::Ordering::Relaxed),
+			ip,
+			announces: 0,
+			subscriptions: 0,
+		};
+
+		Ok(SessionLimits {
+			state: Arc::new(Mutex::new(session)),
+		})
+	}
+
+	fn release(&self, ip: net::IpAddr) {
+		let mut state = self.state.lock().unwrap();
+		let state = &mut *state;
+
+		state.sessions -= 1;
+
+		if let Some(peer) = state.peers.get_mut(&ip) {
+			peer.sessions -= 1;
+			peer.refill(&state.limits, time::Instant::now());
+
+			if peer.idle(&state.limits) {
+				state.peers.remove(&ip);
+			}
+		}
+	}
+
+	fn limits(&self) -> Limits {
+		self.state.lock().unwrap().limits.clone()
+	}
+}
+
+struct SessionState {
+	limiter: Limiter,
+	id: u64,
+	ip: net::IpAddr,
+	announces: usize,
+	subscriptions: usize,
+}
+
+impl Drop for SessionState {
+	fn drop(&mut self) {
+		self.limiter.release(self.ip);
+	}
+}
+
+/// Enforces the per-session limits, created by [Limiter::admit].
+#[derive(Clone)]
+pub struct SessionLimits {
+	state: Arc<Mutex<SessionState>>,
+}
+
+impl SessionLimits {
+	/// Reserve an announce, released when the returned permit is dropped.
+	///
+	/// The namespace is attributed to this session for any per-publisher quota until then.
+	pub fn announce(&self, namespace: &str) -> Result<Permit, LimitError> {
+		let mut state = self.state.lock().unwrap();
+
+		if let Some(max) = state.limiter.limits().announces_per_session {
+			if state.announces >= max {
+				return Err(LimitError::Announces);
+			}
+		}
+
+		let quota = state.limiter.quotas.announce(namespace, state.id)?;
+		state.announces += 1;
+
+		Ok(Permit {
+			session: self.clone(),
+			kind: PermitKind::Announce,
+			meter: None,
+			_quota: Some(quota),
+		})
+	}
+
+	/// Reserve a subscription, released when the returned permit is dropped.
+	pub fn subscribe(&self, namespace: &str) -> Result<Permit, LimitError> {
+		let mut state = self.state.lock().unwrap();
+
+		if let Some(max) = state.limiter.limits().subscriptions_per_session {
+			if state.subscriptions >= max {
+				return Err(LimitError::Subscriptions);
+			}
+		}
+
+		let meter = state.limiter.quotas.subscribe(namespace)?;
+		state.subscriptions += 1;
+
+		Ok(Permit {
+			session: self.clone(),
+			kind: PermitKind::Subscribe,
+			meter,
+			_quota: None,
+		})
+	}
+
+	/// Return a meter for data received from this session, if the namespace has an ingress quota.
+	pub fn ingress(&self, namespace: &str) -> Option<Meter> {
+		let state = self.state.lock().unwrap();
+		state.limiter.quotas.ingress(namespace, state.id)
+	}
+}
+
+enum PermitKind {
+	Announce,
+	Subscribe,
+}
+
+/// A reserved announce or subscription, released on drop.
+pub struct Permit {
+	session: SessionLimits,
+	kind: PermitKind,
+
+	// Used to meter a subscription if the namespace has an egress quota.
+	meter: Option<Meter>,
+
+	// Attributes an announced namespace to the session until dropped.
+	_quota: Option<QuotaAnnounce>,
+}
+
+impl Permit {
+	/// Used to meter a subscription if the namespace has an egress quota.
+	pub fn meter(&self) -> Option<Meter> {
+		self.meter.clone()
+	}
+}
+
+impl Drop for Permit {
+	fn drop(&mut self) {
+		let mut state = self.session.state.lock().unwrap();
+		match self.kind {
+			PermitKind::Announce => state.announces -= 1,
+			PermitKind::Subscribe => state.subscriptions -= 1,
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn addr(ip: &str) -> net::SocketAddr {
+		net::SocketAddr::new(ip.parse().unwrap(), 1234)
+	}
+
+	#[test]
+	fn sessions_per_ip() {
+		let limiter = Limiter::new(Limits {
+			sessions_per_ip: Some(2),
+			..Default::default()
+		});
+
+		let first = limiter.admit(addr("10.0.0.1")).unwrap();
+		let _second = limiter.admit(addr("10.0.0.1")).unwrap();
+		assert_eq!(limiter.admit(addr("10.0.0.1")).err(), Some(LimitError::Sessions));
+
+		// Other addresses are unaffected.
+		let _other = limiter.admit(addr("10.0.0.2")).unwrap();
+
+		drop(first);
+		limiter.admit(addr("10.0.0.1")).unwrap();
+	}
+
+	#[test]
+	fn sessions() {
+		let limiter = Limiter::new(Limits {
+			sessions: Some(2),
+			sessions_per_ip: Some(2),
+			..Default::default()
+		});
+
+		let first = limiter.admit(addr("10.0.0.1")).unwrap();
+		let _second = limiter.admit(addr("10.0.0.2")).unwrap();
+
+		// The relay-wide limit applies across addresses.
+		assert_eq!(limiter.admit(addr("10.0.0.3")).err(), Some(LimitError::Capacity));
+
+		drop(first);
+		limiter.admit(addr("10.0.0.3")).unwrap();
+	}
+
+	#[test]
+	fn codes() {
+		assert_eq!(LimitError::Sessions.code(), 429);
+		assert_eq!(LimitError::Rate.code(), 429);
+		assert_eq!(LimitError::Subscriptions.code(), 429);
+		assert_eq!(LimitError::Capacity.code(), 503);
+		assert_eq!(LimitError::Quota.code(), 509);
+	}
+
+	#[test]
+	fn connection_rate() {
+		let limiter = Limiter::new(Limits {
+			connection_rate: Some(0.001),
+			connection_burst: Some(2.0),
+			..Default::default()
+		});
+
+		limiter.admit(addr("10.0.0.1")).unwrap();
+		limiter.admit(addr("10.0.0.1")).unwrap();
+		assert_eq!(limiter.admit(addr("10.0.0.1")).err(), Some(LimitError::Rate));
+	}
+
+	#[test]
+	fn per_session() {
+		let limiter = Limiter::new(Limits {
+			subscriptions_per_session: Some(1),
+			..Default::default()
+		});
+
+		let session = limiter.admit(addr("10.0.0.1")).unwrap();
+		let permit = session.subscribe("foo").unwrap();
+		assert_eq!(session.subscribe("foo").err(), Some(LimitError::Subscriptions));
+
+		// Raising the limit applies immediately.
+		limiter.update(Limits {
+			subscriptions_per_session: Some(2),
+			..Default::default()
+		});
+		let _second = session.subscribe("foo").unwrap();
+
+		drop(permit);
+		session.announce("foo").unwrap();
+	}
+}
@@ -1,13 +1,57 @@
 use std::collections::hash_map;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 
 use std::sync::{Arc, Mutex};
 
-use moq_transport::serve::{ServeError, TracksReader};
+use moq_transport::serve::{ServeError, Track, TrackReader, TracksReader};
+use serde::Deserialize;
+use tokio::sync::watch;
+
+use crate::Failover;
+
+/// What to do when a namespace is announced that is already registered.
+#[derive(clap::ValueEnum, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DuplicatePolicy {
+	/// Reject the new announce until the existing one is gone.
+	#[default]
+	Reject,
+
+	/// The new announce replaces the existing one, which is closed.
+	Replace,
+
+	/// The new announce is used as a backup, taking over when the existing one is gone.
+	Backup,
+}
 
 #[derive(Clone)]
 pub struct Locals {
-	lookup: Arc<Mutex<HashMap<String, TracksReader>>>,
+	state: Arc<Mutex<LocalsState>>,
+}
+
+#[derive(Default)]
+struct LocalsState {
+	policy: DuplicatePolicy,
+	lookup: HashMap<String, Local>,
+	next: u64,
+}
+
+// All of the publishers for a namespace.
+struct Local {
+	active: LocalPublisher,
+	backups: VecDeque<LocalPublisher>,
+
+	// The ID of the active publisher, or None when the namespace is gone.
+	changed: watch::Sender<Option<u64>>,
+
+	// The track copied by each running failover task, shared by every subscriber along with an ID for the task.
+	failovers: HashMap<String, (u64, TrackReader)>,
+}
+
+struct LocalPublisher {
+	id: u64,
+	tracks: TracksReader,
+	evicted: watch::Sender<bool>,
 }
 
 impl Default for Locals {
@@ -19,37 +63,263 @@ impl Default for Locals {
 impl Locals {
 	pub fn new() -> Self {
 		Self {
-			lookup: Default::default(),
+			state: Default::default(),
 		}
 	}
 
+	/// Change the policy used for any future duplicate announces.
+	pub fn set_policy(&self, policy: DuplicatePolicy) {
+		self.state.lock().unwrap().policy = policy;
+	}
+
 	pub async fn register(&mut self, tracks: TracksReader) -> anyhow::Result<Registration> {
 		let namespace = tracks.namespace.clone();
-		match self.lookup.lock().unwrap().entry(namespace.clone()) {
-			hash_map::Entry::Vacant(entry) => entry.insert(tracks),
-			hash_map::Entry::Occupied(_) => return Err(ServeError::Duplicate.into()),
+
+		let mut state = self.state.lock().unwrap();
+		let state = &mut *state;
+
+		let id = state.next;
+		state.next += 1;
+
+		let (evicted, evicted_recv) = watch::channel(false);
+		let publisher = LocalPublisher { id, tracks, evicted };
+
+		match state.lookup.entry(namespace.clone()) {
+			hash_map::Entry::Vacant(entry) => {
+				let (changed, _) = watch::channel(Some(id));
+				entry.insert(Local {
+					active: publisher,
+					backups: VecDeque::new(),
+					changed,
+					failovers: HashMap::new(),
+				});
+			}
+			hash_map::Entry::Occupied(mut entry) => {
+				let local = entry.get_mut();
+
+				match state.policy {
+					DuplicatePolicy::Reject => return Err(ServeError::Duplicate.into()),
+					DuplicatePolicy::Replace => {
+						log::info!("replacing publisher: namespace={}", namespace);
+
+						let old = std::mem::replace(&mut local.active, publisher);
+						old.evicted.send_replace(true);
+						local.changed.send_replace(Some(id));
+					}
+					DuplicatePolicy::Backup => {
+						log::info!("adding backup publisher: namespace={}", namespace);
+						local.backups.push_back(publisher);
+					}
+				}
+			}
 		};
 
 		let registration = Registration {
 			locals: self.clone(),
 			namespace,
+			id,
+			evicted: evicted_recv,
 		};
 
 		Ok(registration)
 	}
 
 	pub fn route(&self, namespace: &str) -> Option<TracksReader> {
-		self.lookup.lock().unwrap().get(namespace).cloned()
+		self.active(namespace).map(|(_, tracks, _)| tracks)
+	}
+
+	/// Subscribe to a track from the active publisher.
+	///
+	/// Unless duplicates are rejected, the returned track fails over to the next active publisher.
+	/// Every subscriber of the same track shares a single copy.
+	pub fn subscribe(&self, namespace: &str, name: &str) -> Option<TrackReader> {
+		let mut state = self.state.lock().unwrap();
+		let state = &mut *state;
+
+		let local = state.lookup.get_mut(namespace)?;
+		if state.policy == DuplicatePolicy::Reject {
+			return local.active.tracks.clone().subscribe(name);
+		}
+
+		if let Some((_, reader)) = local.failovers.get(name) {
+			return Some(reader.clone());
+		}
+
+		let track = local.active.tracks.clone().subscribe(name)?;
+		let (writer, reader) = Track::new(namespace.to_string(), name.to_string()).produce();
+
+		let key = state.next;
+		state.next += 1;
+		local.failovers.insert(name.to_string(), (key, reader.clone()));
+
+		let id = local.active.id;
+		let failover = Failover {
+			locals: self.clone(),
+			namespace: namespace.to_string(),
+			name: name.to_string(),
+			changed: local.changed.subscribe(),
+		};
+
+		let locals = self.clone();
+		let namespace = namespace.to_string();
+		let name = name.to_string();
+
+		tokio::spawn(async move {
+			let info = writer.info.clone();
+			if let Err(err) = failover.run(writer, id, track).await {
+				log::debug!("failover track closed: {:?}, error: {}", info, err);
+			}
+
+			locals.remove_failover(&namespace, &name, key);
+		});
+
+		Some(reader)
+	}
+
+	// Stop sharing a failover track once its task finishes, so the next subscriber starts a new one.
+	fn remove_failover(&self, namespace: &str, name: &str, key: u64) {
+		let mut state = self.state.lock().unwrap();
+		if let Some(local) = state.lookup.get_mut(namespace) {
+			if local.failovers.get(name).is_some_and(|(current, _)| *current == key) {
+				local.failovers.remove(name);
+			}
+		}
+	}
+
+	// Returns the active publisher and a channel that is notified when it changes.
+	pub(crate) fn active(&self, namespace: &str) -> Option<(u64, TracksReader, watch::Receiver<Option<u64>>)> {
+		let state = self.state.lock().unwrap();
+		let local = state.lookup.get(namespace)?;
+		Some((local.active.id, local.active.tracks.clone(), local.changed.subscribe()))
 	}
 }
 
 pub struct Registration {
 	locals: Locals,
 	namespace: String,
+	id: u64,
+	evicted: watch::Receiver<bool>,
+}
+
+impl Registration {
+	/// Block until this registration is replaced by a newer announce.
+	pub async fn evicted(&self) {
+		let mut evicted = self.evicted.clone();
+		if evicted.wait_for(|evicted| *evicted).await.is_err() {
+			// Never evicted, so block forever.
+			std::future::pending::<()>().await;
+		}
+	}
 }
 
 impl Drop for Registration {
 	fn drop(&mut self) {
-		self.locals.lookup.lock().unwrap().remove(&self.namespace);
+		let mut state = self.locals.state.lock().unwrap();
+
+		let mut entry = match state.lookup.entry(self.namespace.clone()) {
+			hash_map::Entry::Occupied(entry) => entry,
+			hash_map::Entry::Vacant(_) => return,
+		};
+
+		let local = entry.get_mut();
+		if local.active.id != self.id {
+			// We're a backup, or we were replaced.
+			local.backups.retain(|backup| backup.id != self.id);
+			return;
+		}
+
+		match local.backups.pop_front() {
+			Some(backup) => {
+				log::info!("promoting backup publisher: namespace={}", self.namespace);
+				local.changed.send_replace(Some(backup.id));
+				local.active = backup;
+			}
+			None => {
+				local.changed.send_replace(None);
+				entry.remove();
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use moq_transport::serve::Tracks;
+
+	fn tracks(namespace: &str) -> TracksReader {
+		let (_, _, reader) = Tracks::new(namespace.to_string()).produce();
+		reader
+	}
+
+	#[tokio::test]
+	async fn reject() {
+		let mut locals = Locals::new();
+
+		let _first = locals.register(tracks("foo")).await.unwrap();
+		assert!(locals.register(tracks("foo")).await.is_err());
+	}
+
+	#[tokio::test]
+	async fn replace() {
+		let mut locals = Locals::new();
+		locals.set_policy(DuplicatePolicy::Replace);
+
+		let first = locals.register(tracks("foo")).await.unwrap();
+		let (id, _, _) = locals.active("foo").unwrap();
+
+		let second = locals.register(tracks("foo")).await.unwrap();
+		first.evicted().await;
+		assert_ne!(locals.active("foo").unwrap().0, id);
+
+		// Dropping the replaced publisher doesn't remove the namespace.
+		drop(first);
+		assert!(locals.route("foo").is_some());
+
+		drop(second);
+		assert!(locals.route("foo").is_none());
+	}
+
+	#[tokio::test]
+	async fn backup() {
+		let mut locals = Locals::new();
+		locals.set_policy(DuplicatePolicy::Backup);
+
+		let first = locals.register(tracks("foo")).await.unwrap();
+		let (id, _, mut changed) = locals.active("foo").unwrap();
+
+		let second = locals.register(tracks("foo")).await.unwrap();
+		assert_eq!(locals.active("foo").unwrap().0, id);
+
+		// The backup is promoted when the active publisher goes away.
+		drop(first);
+		changed.changed().await.unwrap();
+		assert_ne!(*changed.borrow(), Some(id));
+		assert!(locals.route("foo").is_some());
+
+		drop(second);
+		assert!(locals.route("foo").is_none());
+	}
+
+	#[tokio::test]
+	async fn shared() {
+		let mut locals = Locals::new();
+		locals.set_policy(DuplicatePolicy::Backup);
+
+		let (_writer, mut request, reader) = Tracks::new("foo".to_string()).produce();
+		let _registration = locals.register(reader).await.unwrap();
+
+		// Both subscribers share the same failover track, requested once from the publisher.
+		let first = locals.subscribe("foo", "bar").unwrap();
+		let _second = locals.subscribe("foo", "bar").unwrap();
+		assert_eq!(locals.state.lock().unwrap().lookup["foo"].failovers.len(), 1);
+
+		// Once the failover ends, the track is no longer shared.
+		let track = request.next().await.unwrap();
+		track.close(ServeError::NotFound).unwrap();
+		assert!(first.mode().await.is_err());
+
+		tokio::task::yield_now().await;
+		assert!(locals.state.lock().unwrap().lookup["foo"].failovers.is_empty());
 	}
 }
@@ -1,46 +1,111 @@
 use clap::Parser;
 
+mod admin;
 mod api;
+mod archive;
+mod config;
 mod consumer;
+mod drain;
+mod failover;
+mod forward;
+mod health;
+mod limits;
 mod local;
+mod mesh;
 mod producer;
+mod quota;
+mod record;
 mod relay;
 mod remote;
+mod replay;
+mod routes;
 mod session;
+mod trust;
 mod web;
 
+pub use admin::*;
 pub use api::*;
+pub use archive::*;
+pub use config::*;
 pub use consumer::*;
+pub use drain::*;
+pub use failover::*;
+pub use forward::*;
+pub use health::*;
+pub use limits::*;
 pub use local::*;
+pub use mesh::*;
 pub use producer::*;
+pub use quota::*;
+pub use record::*;
 pub use relay::*;
 pub use remote::*;
+pub use replay::*;
+pub use routes::*;
 pub use session::*;
+pub use trust::*;
 pub use web::*;
 
-use std::net;
+use std::{net, path};
 use url::Url;
 
 #[derive(Parser, Clone)]
 pub struct Cli {
-	/// Listen on this address
-	#[arg(long, default_value = "[::]:443")]
-	pub bind: net::SocketAddr,
+	/// Load the configuration from this TOML file.
+	///
+	/// The file is reloaded on SIGHUP or when modified, applying any settings that can change live.
+	/// Command line flags take precedence over the file.
+	#[arg(long)]
+	pub config: Option<path::PathBuf>,
+
+	/// Listen on this address [default: [::]:443]
+	#[arg(long)]
+	pub bind: Option<net::SocketAddr>,
 
 	/// The TLS configuration.
 	#[command(flatten)]
 	pub tls: moq_native::tls::Args,
 
 	/// Forward all announces to the provided server for authentication/routing.
+	/// This can be used multiple times to forward to multiple servers.
 	/// If not provided, the relay accepts every unique announce.
 	#[arg(long)]
-	pub announce: Option<Url>,
+	pub announce: Vec<Url>,
 
 	/// The URL of the moq-api server in order to run a cluster.
 	/// Must be used in conjunction with --node to advertise the origin
 	#[arg(long)]
 	pub api: Option<Url>,
 
+	/// Authenticate with moq-api using the bearer token in this file, required if it's configured with --tokens.
+	#[arg(long = "api-token-file")]
+	pub api_token: Option<path::PathBuf>,
+
+	/// Decides which relay to connect to for origins returned by moq-api.
+	#[command(flatten)]
+	pub api_routing: ApiRouting,
+
+	/// Load a static routing table from this TOML file, used instead of --api to find origins.
+	/// The file is reloaded along with the configuration.
+	#[arg(long, conflicts_with = "api")]
+	pub routes: Option<path::PathBuf>,
+
+	/// Connect to these relays and propagate announces over MoQ, forming a mesh without moq-api.
+	/// Every relay in the mesh must list every other relay.
+	#[arg(long = "peer")]
+	pub peers: Vec<Url>,
+
+	/// Authenticate with peers in the mesh using the shared secret in this file, which every relay must share.
+	/// Either this or --tls-client-cert is required with --peer, since any client can use the mesh path.
+	#[arg(long = "mesh-secret-file")]
+	pub mesh_secret: Option<path::PathBuf>,
+
+	/// Trust relays authenticated with a client certificate for this name, via --tls-client-root.
+	/// Trusted sessions skip any limits and their announces are forwarded.
+	/// This can be used multiple times; if not provided, any authenticated client is trusted.
+	#[arg(long = "trust-peer")]
+	pub trust: Vec<String>,
+
 	/// The hostname that we advertise to other origins.
 	/// The provided certificate must be valid for this address.
 	#[arg(long)]
@@ -50,6 +115,31 @@ pub struct Cli {
 	/// This hosts a HTTPS web server via TCP to serve the fingerprint of the certificate.
 	#[arg(long)]
 	pub dev: bool,
+
+	/// Limits to protect against misbehaving clients.
+	#[command(flatten)]
+	pub limits: Limits,
+
+	/// Serve administrative endpoints over plain HTTP on this address.
+	/// This includes `POST /drain` to gracefully drain the relay, and the `/healthz` and `/readyz` probes.
+	#[arg(long = "admin-bind")]
+	pub admin: Option<net::SocketAddr>,
+
+	/// Configuration used when draining the relay, triggered by SIGTERM or the admin API.
+	#[command(flatten)]
+	pub drain: DrainConfig,
+
+	/// What to do when a namespace is announced that is already registered [default: reject]
+	#[arg(long, value_enum)]
+	pub duplicate: Option<DuplicatePolicy>,
+
+	/// Record broadcasts to disk.
+	#[command(flatten)]
+	pub record: RecordConfig,
+
+	/// Serve recorded broadcasts.
+	#[command(flatten)]
+	pub replay: ReplayConfig,
 }
 
 #[tokio::main]
@@ -63,25 +153,79 @@ async fn main() -> anyhow::Result<()> {
 	tracing::subscriber::set_global_default(tracer).unwrap();
 
 	let cli = Cli::parse();
-	let tls = cli.tls.load()?;
+	let config = cli.config()?;
+	let tls = config.tls.args().load()?;
 
 	if tls.server.is_none() {
 		anyhow::bail!("missing TLS certificates");
 	}
 
+	let (reloader, reload) = Reloader::new(cli, config.clone());
+	tokio::spawn(async move {
+		// The relay keeps running with its current config if reloading fails.
+		if let Err(err) = reloader.run().await {
+			log::error!("failed to reload config: {:#}", err);
+		}
+	});
+
+	// Drain gracefully on SIGTERM, used by most orchestrators before killing the process.
+	let drain = Drain::new(config.drain.clone());
+	let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+	tokio::spawn({
+		let drain = drain.clone();
+		async move {
+			while terminate.recv().await.is_some() {
+				if !drain.start() {
+					log::warn!("received SIGTERM while draining, exiting immediately");
+					std::process::exit(1);
+				}
+			}
+		}
+	});
+
 	// Create a QUIC server for media.
+	let api_token = config.api_token()?;
+	let mesh_secret = config.mesh_secret()?;
 	let relay = Relay::new(RelayConfig {
+		forward: config.forward(),
 		tls: tls.clone(),
-		bind: cli.bind,
-		node: cli.node,
-		api: cli.api,
-		announce: cli.announce,
+		bind: config.bind,
+		node: config.node,
+		api: config.api,
+		api_token,
+		api_routing: config.api_routing.policy()?,
+		api_capacity: config.api_routing.capacity,
+		peers: config.peers,
+		mesh_secret: mesh_secret.clone(),
+		routes: config.routes.map(Routes::load).transpose()?,
+		limits: config.limits,
+		duplicate: config.duplicate,
+		record: config.record,
+		replays: Replays::load(&config.replay).await?,
+		trust: Trust::new(tls.client_auth, config.trust, mesh_secret),
+		drain: drain.clone(),
+		reload,
 	})?;
 
-	if cli.dev {
+	if let Some(bind) = config.admin {
+		let admin = Admin::new(AdminConfig {
+			bind,
+			drain,
+			forwards: relay.forwards(),
+			peers: relay.peers(),
+			quotas: relay.quotas(),
+			health: relay.health(),
+		});
+
+		tokio::spawn(async move {
+			admin.run().await.expect("failed to run admin server");
+		});
+	}
+
+	if config.dev {
 		// Create a web server too.
 		// Currently this only contains the certificate fingerprint (for development only).
-		let web = Web::new(WebConfig { bind: cli.bind, tls });
+		let web = Web::new(WebConfig { bind: config.bind, tls });
 
 		tokio::spawn(async move {
 			web.run().await.expect("failed to run web server");
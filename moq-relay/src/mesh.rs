@@ -0,0 +1,147 @@
+use futures::{stream::FuturesUnordered, StreamExt};
+use moq_native::quic;
+use url::Url;
+
+use crate::{Announces, Forward, ForwardConfig, ForwardTarget, Locals};
+
+// The path used when connecting to a peer, so it knows not to propagate our announces any further.
+// The shared secret is appended as another segment, since WebTransport doesn't send the query string.
+const MESH_PATH: &str = "/.mesh";
+
+pub struct MeshConfig {
+	/// The URLs of the other relays in the mesh.
+	pub peers: Vec<Url>,
+
+	/// Our own URL, which is skipped if it's listed as a peer.
+	pub node: Option<Url>,
+
+	/// Authenticate with peers using this shared secret, unless mutual TLS is used instead.
+	pub secret: Option<String>,
+
+	/// A QUIC endpoint used to connect to peers.
+	pub quic: quic::Client,
+
+	/// Namespaces announced by peers are registered here.
+	pub locals: Locals,
+
+	/// The namespaces announced by our clients.
+	pub announces: Announces,
+}
+
+/// Propagates announces to other relays over MoQ, without a central store like moq-api.
+///
+/// Every relay connects to every peer and announces the namespaces of its own clients.
+/// Namespaces learned from a peer are never announced again, which avoids loops but requires a full mesh.
+pub struct Mesh {
+	peers: Vec<Forward>,
+}
+
+impl Mesh {
+	pub fn new(config: MeshConfig) -> Self {
+		let peers = config
+			.peers
+			.into_iter()
+			.filter(|peer| Some(peer) != config.node.as_ref())
+			.map(|url| {
+				let url = Self::url(url, config.secret.as_deref());
+
+				// Never serve a peer from another origin, since they only want our namespaces.
+				Forward::new(ForwardConfig {
+					target: ForwardTarget {
+						url,
+						namespaces: Vec::new(),
+					},
+					announces: config.announces.clone(),
+					locals: config.locals.clone(),
+					remotes: None,
+					quic: config.quic.clone(),
+				})
+			})
+			.collect();
+
+		Self { peers }
+	}
+
+	/// The URL used to connect to a peer, including the shared secret if any.
+	pub fn url(mut url: Url, secret: Option<&str>) -> Url {
+		match secret {
+			Some(secret) => url.set_path(&format!("{}/{}", MESH_PATH, secret)),
+			None => url.set_path(MESH_PATH),
+		}
+
+		url
+	}
+
+	/// The URL with any shared secret replaced, so it's safe to log or report.
+	pub fn redact(url: &Url) -> Url {
+		let mut url = url.clone();
+		if Self::is_peer(&url) && url.path() != MESH_PATH {
+			url.set_path(&format!("{}/***", MESH_PATH));
+		}
+
+		url
+	}
+
+	/// Returns true if the session URL was used by another relay in the mesh.
+	///
+	/// The URL alone isn't proof, since any client can use it; see [crate::Trust::is_peer].
+	pub fn is_peer(url: &Url) -> bool {
+		let path = url.path();
+		path == MESH_PATH || path.strip_prefix(MESH_PATH).is_some_and(|rest| rest.starts_with('/'))
+	}
+
+	/// Returns true if the session URL contains the shared secret.
+	pub fn has_secret(url: &Url, secret: &str) -> bool {
+		let expected = Self::url(url.clone(), Some(secret));
+		constant_eq(url.path().as_bytes(), expected.path().as_bytes())
+	}
+
+	/// The sessions to each peer.
+	pub fn peers(&self) -> &[Forward] {
+		&self.peers
+	}
+
+	pub async fn run(self) -> anyhow::Result<()> {
+		let mut tasks: FuturesUnordered<_> = self.peers.into_iter().map(|peer| peer.run()).collect();
+
+		// Each peer reconnects forever, so this only returns if there are no peers.
+		while let Some(res) = tasks.next().await {
+			res?;
+		}
+
+		Ok(())
+	}
+}
+
+// Compare without exiting early, so the time taken doesn't reveal how much of the secret matched.
+fn constant_eq(a: &[u8], b: &[u8]) -> bool {
+	a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn secret() {
+		let peer: Url = "https://relay2.example.com:4443/ignored".parse().unwrap();
+
+		let url = Mesh::url(peer.clone(), Some("hunter2"));
+		assert_eq!(url.as_str(), "https://relay2.example.com:4443/.mesh/hunter2");
+		assert!(Mesh::is_peer(&url));
+		assert!(Mesh::has_secret(&url, "hunter2"));
+		assert!(!Mesh::has_secret(&url, "hunter"));
+		assert!(!Mesh::has_secret(&url, "hunter22"));
+		assert_eq!(Mesh::redact(&url).as_str(), "https://relay2.example.com:4443/.mesh/***");
+
+		let url = Mesh::url(peer.clone(), None);
+		assert_eq!(url.path(), "/.mesh");
+		assert!(Mesh::is_peer(&url));
+		assert!(!Mesh::has_secret(&url, "hunter2"));
+		assert_eq!(Mesh::redact(&url), url);
+
+		// Paths that only look similar are regular clients.
+		assert!(!Mesh::is_peer(&peer));
+		assert!(!Mesh::is_peer(&"https://relay2.example.com/.meshy".parse().unwrap()));
+	}
+}
@@ -4,21 +4,31 @@ use moq_transport::{
 	session::{Publisher, SessionError, Subscribed},
 };
 
-use crate::{Locals, RemotesConsumer};
+use crate::{Locals, Meter, RemotesConsumer, ReplayRange, Replays, SessionLimits};
 
 #[derive(Clone)]
 pub struct Producer {
 	remote: Publisher,
 	locals: Locals,
 	remotes: Option<RemotesConsumer>,
+	replays: Option<Replays>,
+	limits: Option<SessionLimits>,
 }
 
 impl Producer {
-	pub fn new(remote: Publisher, locals: Locals, remotes: Option<RemotesConsumer>) -> Self {
+	pub fn new(
+		remote: Publisher,
+		locals: Locals,
+		remotes: Option<RemotesConsumer>,
+		replays: Option<Replays>,
+		limits: Option<SessionLimits>,
+	) -> Self {
 		Self {
 			remote,
 			locals,
 			remotes,
+			replays,
+			limits,
 		}
 	}
 
@@ -26,19 +36,36 @@ impl Producer {
 		self.remote.announce(tracks).await
 	}
 
+	/// Ask the client to reconnect to the provided URL.
+	pub fn go_away(&mut self, url: String) {
+		self.remote.go_away(url)
+	}
+
 	pub async fn run(mut self) -> Result<(), SessionError> {
 		let mut tasks = FuturesUnordered::new();
 
 		loop {
 			tokio::select! {
 				Some(subscribe) = self.remote.subscribed() => {
+					// Reserve the subscription until it's done being served.
+					let permit = match self.limits.as_ref().map(|limits| limits.subscribe(&subscribe.namespace)).transpose() {
+						Ok(permit) => permit,
+						Err(err) => {
+							log::warn!("rejecting subscribe: {:?}, error: {}", subscribe.info, err);
+							subscribe.close(ServeError::Closed(err.code().into())).ok();
+							continue;
+						}
+					};
+
 					let this = self.clone();
 
 					tasks.push(async move {
+						let meter = permit.as_ref().and_then(|permit| permit.meter());
+						let _permit = permit;
 						let info = subscribe.clone();
 						log::info!("serving subscribe: {:?}", info);
 
-						if let Err(err) = this.serve(subscribe).await {
+						if let Err(err) = this.serve(subscribe, meter).await {
 							log::warn!("failed serving subscribe: {:?}, error: {}", info, err)
 						}
 					})
@@ -49,21 +76,39 @@ impl Producer {
 		}
 	}
 
-	async fn serve(self, subscribe: Subscribed) -> Result<(), anyhow::Error> {
-		if let Some(mut local) = self.locals.route(&subscribe.namespace) {
-			if let Some(track) = local.subscribe(&subscribe.name) {
-				log::info!("serving from local: {:?}", track.info);
-				return Ok(subscribe.serve(track).await?);
+	async fn serve(self, subscribe: Subscribed, meter: Option<Meter>) -> Result<(), anyhow::Error> {
+		// Count every byte against the egress quota, if any.
+		let metered = |track| match &meter {
+			Some(meter) => meter.clone().track(track),
+			None => track,
+		};
+
+		// A recording can be served from any position, so it gets a dedicated track.
+		if let (Some(replays), Some(start)) = (&self.replays, subscribe.start()) {
+			let range = ReplayRange::new(Some(start), subscribe.end());
+			if let Some(track) = replays.subscribe(&subscribe.namespace, &subscribe.name, range) {
+				log::info!(
+					"serving from replay: {:?} start={:?} end={:?}",
+					track.info,
+					start,
+					subscribe.end()
+				);
+				return Ok(subscribe.serve(metered(track)).await?);
 			}
 		}
 
+		if let Some(track) = self.locals.subscribe(&subscribe.namespace, &subscribe.name) {
+			log::info!("serving from local: {:?}", track.info);
+			return Ok(subscribe.serve(metered(track)).await?);
+		}
+
 		if let Some(remotes) = &self.remotes {
 			if let Some(remote) = remotes.route(&subscribe.namespace).await? {
 				if let Some(track) = remote.subscribe(subscribe.namespace.clone(), subscribe.name.clone())? {
 					log::info!("serving from remote: {:?} {:?}", remote.info, track.info);
 
 					// NOTE: Depends on drop(track) being called afterwards
-					return Ok(subscribe.serve(track.reader).await?);
+					return Ok(subscribe.serve(metered(track.reader.clone())).await?);
 				}
 			}
 		}
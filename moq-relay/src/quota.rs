@@ -0,0 +1,895 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time;
+
+use futures::{stream::FuturesUnordered, StreamExt};
+use moq_transport::serve::{
+	DatagramsReader, DatagramsWriter, Group, GroupReader, GroupWriter, GroupsReader, GroupsWriter, Object,
+	ObjectReader, ObjectWriter, ObjectsReader, ObjectsWriter, ServeError, StreamGroupReader, StreamGroupWriter,
+	StreamReader, StreamWriter, Track, TrackReader, TrackReaderMode, TrackWriter, TrackWriterMode,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{glob, LimitError};
+
+/// A bandwidth quota for matching namespaces, configured via `[[limits.quota]]`.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Quota {
+	/// Namespaces matching this pattern are subject to the quota; the first matching quota is used.
+	pub namespace: String,
+
+	/// Whether usage is tracked for each namespace or for each publisher [default: namespace]
+	#[serde(default)]
+	pub per: QuotaScope,
+
+	/// The maximum number of bytes per second received from publishers.
+	pub ingress: Option<u64>,
+
+	/// The maximum number of bytes per second sent to subscribers.
+	pub egress: Option<u64>,
+
+	/// What to do when over the quota [default: reject]
+	#[serde(default)]
+	pub policy: QuotaPolicy,
+
+	/// With the `drop` policy, groups with at least this priority are still delivered when over the quota.
+	pub keep_priority: Option<u64>,
+}
+
+/// What usage is tracked against a [Quota].
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum QuotaScope {
+	/// Every matching namespace has its own budget.
+	#[default]
+	Namespace,
+
+	/// Every publishing session has its own budget, shared by the namespaces it announces.
+	Publisher,
+}
+
+/// What to do when a [Quota] is exceeded.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum QuotaPolicy {
+	/// Reject new subscriptions when over the egress quota, and new announces when over the ingress quota.
+	#[default]
+	Reject,
+
+	/// Drop new groups below `keep_priority` until usage is back within the quota.
+	Drop,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+	Ingress,
+	Egress,
+}
+
+// Who the usage is tracked against.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Subject {
+	Namespace(String),
+	Publisher(u64),
+}
+
+impl Subject {
+	fn scope(&self) -> QuotaScope {
+		match self {
+			Self::Namespace(_) => QuotaScope::Namespace,
+			Self::Publisher(_) => QuotaScope::Publisher,
+		}
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct BucketKey {
+	pattern: String,
+	direction: Direction,
+	subject: Subject,
+}
+
+// Measures the total bytes and the rate over the last window of at least a second.
+struct Rate {
+	total: u64,
+
+	// The bytes since the start of the window, used to measure the rate.
+	window: u64,
+	window_start: time::Instant,
+	rate: f64,
+}
+
+impl Rate {
+	fn new(now: time::Instant) -> Self {
+		Self {
+			total: 0,
+			window: 0,
+			window_start: now,
+			rate: 0.0,
+		}
+	}
+
+	fn update(&mut self, now: time::Instant) {
+		let window = now.duration_since(self.window_start).as_secs_f64();
+		if window >= 1.0 {
+			self.rate = self.window as f64 / window;
+			self.window = 0;
+			self.window_start = now;
+		}
+	}
+
+	fn add(&mut self, bytes: u64, now: time::Instant) {
+		self.update(now);
+		self.total += bytes;
+		self.window += bytes;
+	}
+}
+
+// A token bucket allowing one second of burst, along with usage statistics.
+struct Bucket {
+	// Identifies this bucket, since it may be replaced by another with the same key.
+	id: u64,
+
+	// The number of meters using the bucket.
+	meters: usize,
+
+	limit: u64,
+	tokens: f64,
+	updated: time::Instant,
+
+	usage: Rate,
+	dropped: u64,
+	rejected: u64,
+}
+
+impl Bucket {
+	fn new(id: u64, limit: u64, now: time::Instant) -> Self {
+		Self {
+			id,
+			meters: 0,
+			limit,
+			tokens: limit as f64,
+			updated: now,
+			usage: Rate::new(now),
+			dropped: 0,
+			rejected: 0,
+		}
+	}
+
+	fn refill(&mut self, now: time::Instant) {
+		let elapsed = now.duration_since(self.updated).as_secs_f64();
+		self.tokens = (self.tokens + elapsed * self.limit as f64).min(self.limit as f64);
+		self.updated = now;
+		self.usage.update(now);
+	}
+
+	fn consume(&mut self, bytes: u64, now: time::Instant) {
+		self.refill(now);
+
+		// The bucket can go negative, so a large object pays for itself over time.
+		self.tokens -= bytes as f64;
+		self.usage.add(bytes, now);
+	}
+
+	fn exceeded(&mut self, now: time::Instant) -> bool {
+		self.refill(now);
+		self.tokens <= 0.0
+	}
+
+	// A bucket can be removed once nothing uses it and it's full again, since a new one would be identical.
+	fn idle(&mut self, now: time::Instant) -> bool {
+		if self.meters > 0 {
+			return false;
+		}
+
+		self.refill(now);
+		self.tokens >= self.limit as f64
+	}
+}
+
+/// The usage of a quota, reported by `GET /quota` on the admin server.
+#[derive(Serialize, Clone, Debug)]
+pub struct QuotaUsage {
+	/// The namespace pattern of the quota.
+	pub quota: String,
+	pub direction: Direction,
+
+	/// Either the namespace or the publisher's session ID, depending on the scope.
+	pub namespace: Option<String>,
+	pub publisher: Option<u64>,
+
+	/// The limit and the measured rate, in bytes per second.
+	pub limit: u64,
+	pub rate: f64,
+
+	/// The total number of bytes transferred.
+	pub total: u64,
+
+	/// The number of groups or objects dropped.
+	pub dropped: u64,
+
+	/// The number of announces or subscriptions rejected.
+	pub rejected: u64,
+}
+
+#[derive(Default)]
+struct QuotasState {
+	quotas: Vec<Quota>,
+	buckets: HashMap<BucketKey, Bucket>,
+
+	// The publishing session for each announced namespace, used for publisher quotas.
+	publishers: HashMap<String, u64>,
+
+	// The ID of the next bucket.
+	next: u64,
+}
+
+impl QuotasState {
+	// Return the bucket for the first quota matching the namespace, creating it if needed.
+	fn bucket(&mut self, namespace: &str, direction: Direction, publisher: Option<u64>) -> Option<(BucketKey, Quota)> {
+		let quota = self
+			.quotas
+			.iter()
+			.find(|quota| glob(&quota.namespace, namespace))?
+			.clone();
+
+		let limit = match direction {
+			Direction::Ingress => quota.ingress,
+			Direction::Egress => quota.egress,
+		}?;
+
+		let subject = match quota.per {
+			QuotaScope::Namespace => Subject::Namespace(namespace.to_string()),
+			QuotaScope::Publisher => Subject::Publisher(publisher.or_else(|| self.publishers.get(namespace).copied())?),
+		};
+
+		let key = BucketKey {
+			pattern: quota.namespace.clone(),
+			direction,
+			subject,
+		};
+
+		if !self.buckets.contains_key(&key) {
+			let now = time::Instant::now();
+
+			// Remove any unused buckets first, so they don't grow without bound.
+			self.prune(now);
+
+			self.buckets.insert(key.clone(), Bucket::new(self.next, limit, now));
+			self.next += 1;
+		}
+
+		Some((key, quota))
+	}
+
+	fn prune(&mut self, now: time::Instant) {
+		self.buckets.retain(|_, bucket| !bucket.idle(now));
+	}
+}
+
+// The bytes metered in one direction, counted without locking the quotas.
+struct Total {
+	bytes: AtomicU64,
+
+	// The time and bytes when the rate was last measured.
+	sample: Mutex<(time::Instant, u64)>,
+}
+
+impl Default for Total {
+	fn default() -> Self {
+		Self {
+			bytes: Default::default(),
+			sample: Mutex::new((time::Instant::now(), 0)),
+		}
+	}
+}
+
+impl Total {
+	// The rate since the last call.
+	fn rate(&self) -> f64 {
+		let now = time::Instant::now();
+		let bytes = self.bytes.load(Ordering::Relaxed);
+
+		let mut sample = self.sample.lock().unwrap();
+		let (then, previous) = std::mem::replace(&mut *sample, (now, bytes));
+
+		let elapsed = now.duration_since(then).as_secs_f64();
+		match elapsed > 0.0 {
+			true => bytes.saturating_sub(previous) as f64 / elapsed,
+			false => 0.0,
+		}
+	}
+}
+
+#[derive(Default)]
+struct Totals {
+	ingress: Total,
+	egress: Total,
+}
+
+impl Totals {
+	fn get(&self, direction: Direction) -> &Total {
+		match direction {
+			Direction::Ingress => &self.ingress,
+			Direction::Egress => &self.egress,
+		}
+	}
+}
+
+/// Tracks bandwidth usage against each [Quota], and the total of every metered track.
+#[derive(Clone, Default)]
+pub struct Quotas {
+	state: Arc<Mutex<QuotasState>>,
+	totals: Arc<Totals>,
+}
+
+impl Quotas {
+	pub fn new(quotas: Vec<Quota>) -> Self {
+		let state = QuotasState {
+			quotas,
+			..Default::default()
+		};
+
+		Self {
+			state: Arc::new(Mutex::new(state)),
+			totals: Default::default(),
+		}
+	}
+
+	/// Replace the quotas, keeping the usage of any that still exist with the same scope.
+	///
+	/// Existing meters for a removed bucket are no longer counted against a quota; new ones use the new scope.
+	pub fn update(&self, quotas: Vec<Quota>) {
+		let mut state = self.state.lock().unwrap();
+
+		state.buckets.retain(|key, bucket| {
+			let quota = quotas
+				.iter()
+				.find(|quota| quota.namespace == key.pattern && quota.per == key.subject.scope());
+			let limit = quota.and_then(|quota| match key.direction {
+				Direction::Ingress => quota.ingress,
+				Direction::Egress => quota.egress,
+			});
+
+			match limit {
+				Some(limit) => {
+					bucket.limit = limit;
+					true
+				}
+				None => false,
+			}
+		});
+
+		state.quotas = quotas;
+	}
+
+	/// Register an announce from a publisher, failing if it's over the ingress quota with the reject policy.
+	///
+	/// The namespace is attributed to the publisher until the returned handle is dropped.
+	pub fn announce(&self, namespace: &str, publisher: u64) -> Result<QuotaAnnounce, LimitError> {
+		let mut state = self.state.lock().unwrap();
+
+		if let Some((key, quota)) = state.bucket(namespace, Direction::Ingress, Some(publisher)) {
+			let bucket = state.buckets.get_mut(&key).unwrap();
+			if quota.policy == QuotaPolicy::Reject && bucket.exceeded(time::Instant::now()) {
+				bucket.rejected += 1;
+				return Err(LimitError::Quota);
+			}
+		}
+
+		state.publishers.insert(namespace.to_string(), publisher);
+
+		Ok(QuotaAnnounce {
+			quotas: self.clone(),
+			namespace: namespace.to_string(),
+			publisher,
+		})
+	}
+
+	/// Return a meter for a new subscription if the namespace has an egress quota.
+	///
+	/// Fails if it's over the egress quota with the reject policy.
+	pub fn subscribe(&self, namespace: &str) -> Result<Option<Meter>, LimitError> {
+		let mut state = self.state.lock().unwrap();
+
+		let (key, quota) = match state.bucket(namespace, Direction::Egress, None) {
+			Some(bucket) => bucket,
+			None => return Ok(None),
+		};
+
+		let bucket = state.buckets.get_mut(&key).unwrap();
+		if quota.policy == QuotaPolicy::Reject && bucket.exceeded(time::Instant::now()) {
+			bucket.rejected += 1;
+			return Err(LimitError::Quota);
+		}
+
+		Ok(Some(self.meter(&mut state, Direction::Egress, key, quota)))
+	}
+
+	/// Return a meter for data received from a publisher if the namespace has an ingress quota.
+	pub fn ingress(&self, namespace: &str, publisher: u64) -> Option<Meter> {
+		let mut state = self.state.lock().unwrap();
+		let (key, quota) = state.bucket(namespace, Direction::Ingress, Some(publisher))?;
+
+		Some(self.meter(&mut state, Direction::Ingress, key, quota))
+	}
+
+	fn meter(&self, state: &mut QuotasState, direction: Direction, key: BucketKey, quota: Quota) -> Meter {
+		// The bucket is kept until every clone of the meter is dropped.
+		let bucket = state.buckets.get_mut(&key).unwrap();
+		bucket.meters += 1;
+
+		let bucket = Arc::new(MeterBucket {
+			quotas: self.clone(),
+			id: bucket.id,
+			key,
+		});
+
+		Meter {
+			quotas: self.clone(),
+			direction,
+			bucket,
+			policy: quota.policy,
+			keep_priority: quota.keep_priority,
+		}
+	}
+
+	/// The rate of every metered track in bytes per second since the last call, or None without a quota in that direction.
+	///
+	/// Only namespaces with a quota are metered, so this doesn't include any other traffic.
+	pub fn rate(&self, direction: Direction) -> Option<f64> {
+		let metered = self.state.lock().unwrap().quotas.iter().any(|quota| match direction {
+			Direction::Ingress => quota.ingress.is_some(),
+			Direction::Egress => quota.egress.is_some(),
+		});
+
+		let rate = self.totals.get(direction).rate();
+		metered.then_some(rate)
+	}
+
+	/// The current usage of every quota.
+	pub fn usage(&self) -> Vec<QuotaUsage> {
+		let mut state = self.state.lock().unwrap();
+		let now = time::Instant::now();
+
+		state.prune(now);
+
+		state
+			.buckets
+			.iter_mut()
+			.map(|(key, bucket)| {
+				bucket.refill(now);
+
+				let (namespace, publisher) = match &key.subject {
+					Subject::Namespace(namespace) => (Some(namespace.clone()), None),
+					Subject::Publisher(publisher) => (None, Some(*publisher)),
+				};
+
+				QuotaUsage {
+					quota: key.pattern.clone(),
+					direction: key.direction,
+					namespace,
+					publisher,
+					limit: bucket.limit,
+					rate: bucket.usage.rate,
+					total: bucket.usage.total,
+					dropped: bucket.dropped,
+					rejected: bucket.rejected,
+				}
+			})
+			.collect()
+	}
+}
+
+/// Attributes a namespace to a publisher until dropped.
+pub struct QuotaAnnounce {
+	quotas: Quotas,
+	namespace: String,
+	publisher: u64,
+}
+
+impl Drop for QuotaAnnounce {
+	fn drop(&mut self) {
+		let mut state = self.quotas.state.lock().unwrap();
+
+		// Don't remove a newer publisher of the same namespace.
+		if state.publishers.get(&self.namespace) == Some(&self.publisher) {
+			state.publishers.remove(&self.namespace);
+		}
+	}
+}
+
+// A reference to a bucket, which is kept until the last one is dropped.
+struct MeterBucket {
+	quotas: Quotas,
+	key: BucketKey,
+	id: u64,
+}
+
+impl MeterBucket {
+	// Returns the bucket unless it was removed by an update.
+	fn get<'a>(&self, state: &'a mut QuotasState) -> Option<&'a mut Bucket> {
+		state.buckets.get_mut(&self.key).filter(|bucket| bucket.id == self.id)
+	}
+}
+
+impl Drop for MeterBucket {
+	fn drop(&mut self) {
+		let mut state = self.quotas.state.lock().unwrap();
+		if let Some(bucket) = self.get(&mut state) {
+			bucket.meters -= 1;
+		}
+	}
+}
+
+/// Counts the bytes of a track against its quota, dropping groups when over the quota with the drop policy.
+#[derive(Clone)]
+pub struct Meter {
+	quotas: Quotas,
+	direction: Direction,
+
+	// The quota's bucket.
+	bucket: Arc<MeterBucket>,
+	policy: QuotaPolicy,
+	keep_priority: Option<u64>,
+}
+
+impl Meter {
+	fn consume(&self, bytes: usize) {
+		let total = self.quotas.totals.get(self.direction);
+		total.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+
+		let mut state = self.quotas.state.lock().unwrap();
+		if let Some(bucket) = self.bucket.get(&mut state) {
+			bucket.consume(bytes as u64, time::Instant::now());
+		}
+	}
+
+	// Returns false if a new group or object with this priority should be dropped.
+	fn admit(&self, priority: u64) -> bool {
+		if self.policy != QuotaPolicy::Drop || self.keep_priority.is_some_and(|keep| priority >= keep) {
+			return true;
+		}
+
+		let mut state = self.quotas.state.lock().unwrap();
+		let bucket = match self.bucket.get(&mut state) {
+			Some(bucket) => bucket,
+			None => return true,
+		};
+
+		if bucket.exceeded(time::Instant::now()) {
+			bucket.dropped += 1;
+			return false;
+		}
+
+		true
+	}
+
+	/// Return a copy of the track that is metered.
+	pub fn track(self, track: TrackReader) -> TrackReader {
+		let (writer, reader) = Track::new(track.namespace.clone(), track.name.clone()).produce();
+
+		tokio::spawn(async move {
+			let info = writer.info.clone();
+			if let Err(err) = self.copy(track, writer).await {
+				log::debug!("metered track closed: {:?}, error: {}", info, err);
+			}
+		});
+
+		reader
+	}
+
+	/// Copy the track to the writer, counting every byte.
+	pub async fn copy(self, track: TrackReader, writer: TrackWriter) -> Result<(), ServeError> {
+		let mut output: TrackWriterMode = match track.mode().await {
+			Ok(TrackReaderMode::Stream(stream)) => writer.stream(stream.priority)?.into(),
+			Ok(TrackReaderMode::Groups(_)) => writer.groups()?.into(),
+			Ok(TrackReaderMode::Objects(_)) => writer.objects()?.into(),
+			Ok(TrackReaderMode::Datagrams(_)) => writer.datagrams()?.into(),
+			Err(err) => return writer.close(err.clone()).and(Err(err)),
+		};
+
+		let res = match (track.mode().await?, &mut output) {
+			(TrackReaderMode::Stream(reader), TrackWriterMode::Stream(writer)) => {
+				self.copy_stream(reader, writer).await
+			}
+			(TrackReaderMode::Groups(reader), TrackWriterMode::Groups(writer)) => {
+				self.copy_groups(reader, writer).await
+			}
+			(TrackReaderMode::Objects(reader), TrackWriterMode::Objects(writer)) => {
+				self.copy_objects(reader, writer).await
+			}
+			(TrackReaderMode::Datagrams(reader), TrackWriterMode::Datagrams(writer)) => {
+				self.copy_datagrams(reader, writer).await
+			}
+			_ => Err(ServeError::Mode),
+		};
+
+		match res {
+			Ok(()) => output.close(ServeError::Done),
+			Err(err) => output.close(err.clone()).and(Err(err)),
+		}
+	}
+
+	async fn copy_stream(&self, mut reader: StreamReader, writer: &mut StreamWriter) -> Result<(), ServeError> {
+		let mut tasks = FuturesUnordered::new();
+
+		loop {
+			tokio::select! {
+				res = reader.next() => match res? {
+					Some(group) => {
+						if !self.admit(reader.priority) {
+							continue;
+						}
+
+						let output = writer.create(group.group_id)?;
+						tasks.push(self.copy_stream_group(group, output));
+					}
+					None => break,
+				},
+				Some(res) = tasks.next() => res?,
+			}
+		}
+
+		// Finish copying any groups that are still in progress.
+		while let Some(res) = tasks.next().await {
+			res?;
+		}
+
+		Ok(())
+	}
+
+	async fn copy_stream_group(
+		&self,
+		mut group: StreamGroupReader,
+		mut output: StreamGroupWriter,
+	) -> Result<(), ServeError> {
+		while let Some(mut object) = group.next().await? {
+			let mut output = output.create(object.size)?;
+			while let Some(chunk) = object.read().await? {
+				self.consume(chunk.len());
+				output.write(chunk)?;
+			}
+		}
+
+		Ok(())
+	}
+
+	async fn copy_groups(&self, mut reader: GroupsReader, writer: &mut GroupsWriter) -> Result<(), ServeError> {
+		let mut tasks = FuturesUnordered::new();
+
+		loop {
+			tokio::select! {
+				res = reader.next() => match res? {
+					Some(group) => {
+						if !self.admit(group.priority) {
+							continue;
+						}
+
+						let output = writer.create(Group {
+							group_id: group.group_id,
+							priority: group.priority,
+						})?;
+						tasks.push(self.copy_group(group, output));
+					}
+					None => break,
+				},
+				Some(res) = tasks.next() => res?,
+			}
+		}
+
+		// Finish copying any groups that are still in progress.
+		while let Some(res) = tasks.next().await {
+			res?;
+		}
+
+		Ok(())
+	}
+
+	async fn copy_group(&self, mut group: GroupReader, mut output: GroupWriter) -> Result<(), ServeError> {
+		while let Some(mut object) = group.next().await? {
+			let mut output = output.create(object.size)?;
+			while let Some(chunk) = object.read().await? {
+				self.consume(chunk.len());
+				output.write(chunk)?;
+			}
+		}
+
+		Ok(())
+	}
+
+	async fn copy_objects(&self, mut reader: ObjectsReader, writer: &mut ObjectsWriter) -> Result<(), ServeError> {
+		let mut tasks = FuturesUnordered::new();
+
+		loop {
+			tokio::select! {
+				res = reader.next() => match res? {
+					Some(object) => {
+						if !self.admit(object.priority) {
+							continue;
+						}
+
+						let output = writer.create(Object {
+							group_id: object.group_id,
+							object_id: object.object_id,
+							priority: object.priority,
+						})?;
+						tasks.push(self.copy_object(object, output));
+					}
+					None => break,
+				},
+				Some(res) = tasks.next() => res?,
+			}
+		}
+
+		// Finish copying any groups that are still in progress.
+		while let Some(res) = tasks.next().await {
+			res?;
+		}
+
+		Ok(())
+	}
+
+	async fn copy_object(&self, mut object: ObjectReader, mut output: ObjectWriter) -> Result<(), ServeError> {
+		while let Some(chunk) = object.read().await? {
+			self.consume(chunk.len());
+			output.write(chunk)?;
+		}
+
+		Ok(())
+	}
+
+	async fn copy_datagrams(
+		&self,
+		mut reader: DatagramsReader,
+		writer: &mut DatagramsWriter,
+	) -> Result<(), ServeError> {
+		while let Some(datagram) = reader.read().await? {
+			if !self.admit(datagram.priority) {
+				continue;
+			}
+
+			self.consume(datagram.payload.len());
+			writer.write(datagram)?;
+		}
+
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn quota(policy: QuotaPolicy) -> Quota {
+		Quota {
+			namespace: "live/*".to_string(),
+			per: QuotaScope::Namespace,
+			ingress: Some(1000),
+			egress: Some(1000),
+			policy,
+			keep_priority: None,
+		}
+	}
+
+	#[tokio::test]
+	async fn reject() {
+		let quotas = Quotas::new(vec![quota(QuotaPolicy::Reject)]);
+
+		// Namespaces that don't match aren't metered.
+		assert!(quotas.subscribe("vod/foo").unwrap().is_none());
+
+		let meter = quotas.subscribe("live/foo").unwrap().unwrap();
+		meter.consume(2000);
+
+		assert_eq!(quotas.subscribe("live/foo").err(), Some(LimitError::Quota));
+
+		// Each namespace has its own budget.
+		assert!(quotas.subscribe("live/bar").unwrap().is_some());
+
+		let usage = quotas.usage();
+		let usage = usage
+			.iter()
+			.find(|usage| usage.namespace.as_deref() == Some("live/foo"))
+			.unwrap();
+		assert_eq!(usage.total, 2000);
+		assert_eq!(usage.rejected, 1);
+		assert!(quotas.usage().iter().all(|usage| usage.quota == "live/*"));
+
+		assert_eq!(quotas.totals.egress.bytes.load(Ordering::Relaxed), 2000);
+		assert!(quotas.rate(Direction::Egress).unwrap() > 0.0);
+
+		// Without an ingress quota, ingress isn't measured at all.
+		let egress = Quotas::new(vec![Quota {
+			ingress: None,
+			..quota(QuotaPolicy::Reject)
+		}]);
+		assert!(egress.rate(Direction::Ingress).is_none());
+		assert!(egress.ingress("live/foo", 1).is_none());
+	}
+
+	#[tokio::test]
+	async fn drop_groups() {
+		let quotas = Quotas::new(vec![Quota {
+			keep_priority: Some(10),
+			..quota(QuotaPolicy::Drop)
+		}]);
+
+		let meter = quotas.subscribe("live/foo").unwrap().unwrap();
+		assert!(meter.admit(0));
+
+		meter.consume(2000);
+
+		// New subscriptions are still allowed, but low priority groups are dropped.
+		assert!(quotas.subscribe("live/foo").unwrap().is_some());
+		assert!(!meter.admit(0));
+		assert!(meter.admit(10));
+	}
+
+	#[test]
+	fn publisher() {
+		let quotas = Quotas::new(vec![Quota {
+			per: QuotaScope::Publisher,
+			..quota(QuotaPolicy::Reject)
+		}]);
+
+		let announce = quotas.announce("live/foo", 1).unwrap();
+		let _other = quotas.announce("live/bar", 1).unwrap();
+
+		// Both namespaces share the publisher's budget.
+		quotas.ingress("live/foo", 1).unwrap().consume(2000);
+		assert_eq!(quotas.announce("live/baz", 1).err(), Some(LimitError::Quota));
+		assert!(quotas.announce("live/baz", 2).is_ok());
+
+		// Egress is attributed to the publisher of the namespace.
+		quotas.subscribe("live/foo").unwrap().unwrap().consume(2000);
+		assert_eq!(quotas.subscribe("live/bar").err(), Some(LimitError::Quota));
+
+		std::mem::drop(announce);
+		assert!(quotas.subscribe("live/foo").unwrap().is_none());
+	}
+
+	#[test]
+	fn idle() {
+		let quotas = Quotas::new(vec![quota(QuotaPolicy::Reject)]);
+
+		// A bucket is kept while it has a meter, or until it's no longer over the quota.
+		let meter = quotas.subscribe("live/foo").unwrap();
+		quotas.subscribe("live/bar").unwrap().unwrap().consume(2000);
+		assert_eq!(quotas.usage().len(), 2);
+
+		drop(meter);
+		let usage = quotas.usage();
+		assert_eq!(usage.len(), 1);
+		assert_eq!(usage[0].namespace.as_deref(), Some("live/bar"));
+		assert_eq!(quotas.subscribe("live/bar").err(), Some(LimitError::Quota));
+	}
+
+	#[test]
+	fn scope() {
+		let quotas = Quotas::new(vec![quota(QuotaPolicy::Reject)]);
+		let _announce = quotas.announce("live/foo", 1).unwrap();
+
+		let meter = quotas.subscribe("live/foo").unwrap().unwrap();
+		meter.consume(2000);
+		assert_eq!(quotas.subscribe("live/foo").err(), Some(LimitError::Quota));
+
+		// Changing the scope replaces the buckets, so the publisher starts with a new budget.
+		quotas.update(vec![Quota {
+			per: QuotaScope::Publisher,
+			..quota(QuotaPolicy::Reject)
+		}]);
+		let _new = quotas.subscribe("live/foo").unwrap();
+
+		let usage = quotas.usage();
+		assert_eq!(usage.len(), 1);
+		assert_eq!(usage[0].publisher, Some(1));
+
+		// The old meter no longer counts against a quota.
+		meter.consume(2000);
+		assert!(quotas.subscribe("live/foo").is_ok());
+	}
+}
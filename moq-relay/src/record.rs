@@ -0,0 +1,642 @@
+use std::collections::{HashMap, HashSet};
+use std::{path, time};
+
+use anyhow::Context;
+use bytes::Bytes;
+use futures::{stream::FuturesUnordered, FutureExt, StreamExt};
+use moq_transport::serve::{
+	DatagramsReader, GroupReader, GroupsReader, ObjectsReader, StreamGroupReader, StreamReader, TrackReader,
+	TrackReaderMode,
+};
+use serde::Deserialize;
+use tokio::sync::mpsc;
+
+use crate::{glob, timestamp, Announces, ArchiveMode, ArchiveWriter, Locals, Record};
+
+/// The track containing the catalog, which lists every other track in the broadcast.
+const CATALOG_TRACK: &str = ".catalog";
+
+/// Configuration used to record broadcasts to disk.
+#[derive(clap::Args, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RecordConfig {
+	/// Record broadcasts announced by our clients to this directory.
+	#[arg(long = "record-dir")]
+	pub dir: Option<path::PathBuf>,
+
+	/// Only record namespaces matching one of these patterns [default: every namespace]
+	#[arg(long = "record-namespace")]
+	pub namespaces: Vec<String>,
+
+	/// Record these tracks in addition to those listed in the catalog, for broadcasts without a catalog.
+	#[arg(long = "record-track")]
+	pub tracks: Vec<String>,
+
+	/// Start a new file once the current file reaches this many bytes.
+	#[arg(long = "record-max-size")]
+	pub max_size: Option<u64>,
+
+	/// Start a new file once the current file has been open for this many seconds.
+	#[arg(long = "record-max-duration")]
+	pub max_duration: Option<u64>,
+}
+
+impl RecordConfig {
+	/// Override any values that are set in `other`.
+	pub fn merge(&mut self, other: &RecordConfig) {
+		self.dir = other.dir.clone().or(self.dir.take());
+		self.max_size = other.max_size.or(self.max_size);
+		self.max_duration = other.max_duration.or(self.max_duration);
+
+		if !other.namespaces.is_empty() {
+			self.namespaces.clone_from(&other.namespaces);
+		}
+
+		if !other.tracks.is_empty() {
+			self.tracks.clone_from(&other.tracks);
+		}
+	}
+
+	fn matches(&self, namespace: &str) -> bool {
+		self.namespaces.is_empty() || self.namespaces.iter().any(|pattern| glob(pattern, namespace))
+	}
+}
+
+/// Records every matching broadcast announced by our clients to disk.
+///
+/// Tracks are discovered via the catalog and subscribed via [Locals], so recording continues across failover.
+/// Each broadcast is written to `<dir>/<namespace>/<timestamp>.moqrec`, starting a new file when a limit is reached.
+/// The namespace is percent-encoded, so every namespace has a distinct directory.
+pub struct Recorder {
+	config: RecordConfig,
+	dir: path::PathBuf,
+	announces: Announces,
+	locals: Locals,
+}
+
+impl Recorder {
+	pub fn new(config: RecordConfig, announces: Announces, locals: Locals) -> Option<Self> {
+		let dir = config.dir.clone()?;
+
+		Some(Self {
+			config,
+			dir,
+			announces,
+			locals,
+		})
+	}
+
+	pub fn config(&self) -> &RecordConfig {
+		&self.config
+	}
+
+	pub async fn run(self) -> anyhow::Result<()> {
+		let mut announces = self.announces.subscribe();
+		announces.mark_changed();
+
+		// Each namespace is only recorded once, even with a backup publisher, since tracks are subscribed via [Locals].
+		let mut recording = HashSet::new();
+		let mut tasks = FuturesUnordered::new();
+
+		// Namespaces that failed to record, skipped until they're unannounced.
+		let mut failed = HashSet::new();
+
+		loop {
+			tokio::select! {
+				res = announces.changed() => {
+					res.context("announces closed")?;
+
+					let current: HashSet<String> = announces
+						.borrow_and_update()
+						.values()
+						.filter(|tracks| self.config.matches(&tracks.namespace))
+						.map(|tracks| tracks.namespace.clone())
+						.collect();
+
+					failed.retain(|namespace| current.contains(namespace));
+
+					for namespace in current {
+						if failed.contains(&namespace) || !recording.insert(namespace.clone()) {
+							continue;
+						}
+
+						let recording = Recording {
+							config: self.config.clone(),
+							dir: self.dir.join(encode(&namespace)),
+							namespace,
+							locals: self.locals.clone(),
+						};
+
+						tasks.push(async move {
+							let namespace = recording.namespace.clone();
+							log::info!("recording broadcast: namespace={}", namespace);
+
+							let res = recording.run().await;
+							match &res {
+								Ok(()) => log::info!("finished recording: namespace={}", namespace),
+								Err(err) => log::warn!("failed recording: namespace={} error={:#}", namespace, err),
+							}
+
+							(namespace, res.is_ok())
+						}.boxed());
+					}
+				},
+				Some((namespace, finished)) = tasks.next() => {
+					recording.remove(&namespace);
+
+					// The namespace may have been announced again before its tracks ended, so check again.
+					// Failed recordings are only retried once announced again, so they don't retry in a loop.
+					if finished {
+						announces.mark_changed();
+					} else {
+						failed.insert(namespace);
+					}
+				},
+			}
+		}
+	}
+}
+
+// The recording of a single broadcast, which may be split over multiple files.
+struct Recording {
+	config: RecordConfig,
+	dir: path::PathBuf,
+	namespace: String,
+	locals: Locals,
+}
+
+impl Recording {
+	async fn run(self) -> anyhow::Result<()> {
+		tokio::fs::create_dir_all(&self.dir)
+			.await
+			.with_context(|| format!("failed to create directory: {}", self.dir.display()))?;
+
+		let mut archive = Archive::create(&self).await?;
+
+		let (sender, mut receiver) = mpsc::channel(1024);
+		let mut subscribed = HashSet::new();
+		let mut tracks = FuturesUnordered::new();
+
+		let names = std::iter::once(CATALOG_TRACK.to_string()).chain(self.config.tracks.iter().cloned());
+		for name in names {
+			self.subscribe(name, &sender, &mut subscribed, &mut tracks);
+		}
+
+		while !tracks.is_empty() {
+			tokio::select! {
+				Some((record, payload)) = receiver.recv() => {
+					if let Record::Object { track, .. } = &record {
+						if track == CATALOG_TRACK {
+							for name in catalog_tracks(&payload) {
+								self.subscribe(name, &sender, &mut subscribed, &mut tracks);
+							}
+						}
+					}
+
+					archive.write(&self, record, payload).await?;
+
+					// Flush once we've caught up, so a crash loses as little as possible.
+					if receiver.is_empty() {
+						archive.writer.flush().await?;
+					}
+				},
+				Some(res) = tracks.next() => {
+					let (name, res): (String, anyhow::Result<()>) = res;
+					if let Err(err) = res {
+						log::debug!("recorded track closed: namespace={} track={} error={:#}", self.namespace, name, err);
+					}
+				},
+			}
+		}
+
+		// Write anything sent before the final track finished.
+		while let Ok((record, payload)) = receiver.try_recv() {
+			archive.write(&self, record, payload).await?;
+		}
+
+		if archive.objects > 0 {
+			return archive.finish().await;
+		}
+
+		// Nothing could be recorded, so remove the empty file and fail instead of recording again in a loop.
+		let path = archive.writer.path().to_path_buf();
+		archive.finish().await?;
+		tokio::fs::remove_file(&path)
+			.await
+			.with_context(|| format!("failed to remove empty recording: {}", path.display()))?;
+
+		// Only removed if there are no other recordings.
+		tokio::fs::remove_dir(&self.dir).await.ok();
+
+		anyhow::bail!("no objects recorded")
+	}
+
+	fn subscribe(
+		&self,
+		name: String,
+		sender: &mpsc::Sender<(Record, Bytes)>,
+		subscribed: &mut HashSet<String>,
+		tracks: &mut FuturesUnordered<futures::future::BoxFuture<'static, (String, anyhow::Result<()>)>>,
+	) {
+		if !subscribed.insert(name.clone()) {
+			return;
+		}
+
+		let track = match self.locals.subscribe(&self.namespace, &name) {
+			Some(track) => track,
+			None => {
+				log::debug!("failed to subscribe: namespace={} track={}", self.namespace, name);
+				return;
+			}
+		};
+
+		let sender = sender.clone();
+		tracks.push(async move { (name.clone(), record_track(name, track, sender).await) }.boxed());
+	}
+}
+
+// The current file, along with the state needed to start a new one.
+struct Archive {
+	writer: ArchiveWriter,
+
+	// Every track declared so far, repeated at the start of each file.
+	tracks: HashMap<String, ArchiveMode>,
+
+	// The most recent catalog object, repeated at the start of each file so it can be played alone.
+	catalog: Option<(Record, Bytes)>,
+
+	// The number of objects written across every file.
+	objects: u64,
+}
+
+impl Archive {
+	async fn create(recording: &Recording) -> anyhow::Result<Self> {
+		Ok(Self {
+			writer: Self::open(recording).await?,
+			tracks: HashMap::new(),
+			catalog: None,
+			objects: 0,
+		})
+	}
+
+	async fn open(recording: &Recording) -> anyhow::Result<ArchiveWriter> {
+		let path = recording.dir.join(format!("{}.moqrec", timestamp()));
+		let writer = ArchiveWriter::create(path, &recording.namespace).await?;
+		log::info!("recording to file: path={}", writer.path().display());
+
+		Ok(writer)
+	}
+
+	async fn write(&mut self, recording: &Recording, record: Record, payload: Bytes) -> anyhow::Result<()> {
+		match &record {
+			Record::Track { track, mode } => {
+				self.tracks.insert(track.clone(), *mode);
+			}
+			Record::Object { track, object, .. } => {
+				// Only start a new file at the start of a group, so playback can start cleanly.
+				if *object == 0 && self.full(&recording.config) {
+					self.rotate(recording).await?;
+				}
+
+				if track == CATALOG_TRACK {
+					self.catalog = Some((record.clone(), payload.clone()));
+				}
+
+				self.objects += 1;
+			}
+			Record::Header { .. } => anyhow::bail!("unexpected header"),
+		}
+
+		self.writer.write(&record, payload).await
+	}
+
+	fn full(&self, config: &RecordConfig) -> bool {
+		let size = config.max_size.is_some_and(|max| self.writer.size() >= max);
+		let duration = config
+			.max_duration
+			.is_some_and(|max| self.writer.elapsed() >= time::Duration::from_secs(max));
+
+		size || duration
+	}
+
+	async fn rotate(&mut self, recording: &Recording) -> anyhow::Result<()> {
+		let writer = Self::open(recording).await?;
+		std::mem::replace(&mut self.writer, writer).finish().await?;
+
+		for (track, mode) in &self.tracks {
+			let record = Record::Track {
+				track: track.clone(),
+				mode: *mode,
+			};
+			self.writer.write(&record, Bytes::new()).await?;
+		}
+
+		if let Some((record, payload)) = &self.catalog {
+			self.writer.write(record, payload.clone()).await?;
+		}
+
+		Ok(())
+	}
+
+	async fn finish(self) -> anyhow::Result<()> {
+		self.writer.finish().await
+	}
+}
+
+// Copy every object in the track to the archive until it ends.
+async fn record_track(name: String, track: TrackReader, sender: mpsc::Sender<(Record, Bytes)>) -> anyhow::Result<()> {
+	let mode = track.mode().await?;
+
+	let archive_mode = match &mode {
+		TrackReaderMode::Stream(_) => ArchiveMode::Stream,
+		TrackReaderMode::Groups(_) => ArchiveMode::Groups,
+		TrackReaderMode::Objects(_) => ArchiveMode::Objects,
+		TrackReaderMode::Datagrams(_) => ArchiveMode::Datagrams,
+	};
+
+	let record = Record::Track {
+		track: name.clone(),
+		mode: archive_mode,
+	};
+	sender.send((record, Bytes::new())).await?;
+
+	let output = TrackOutput { name, sender };
+
+	match mode {
+		TrackReaderMode::Stream(reader) => output.stream(reader).await,
+		TrackReaderMode::Groups(reader) => output.groups(reader).await,
+		TrackReaderMode::Objects(reader) => output.objects(reader).await,
+		TrackReaderMode::Datagrams(reader) => output.datagrams(reader).await,
+	}
+}
+
+#[derive(Clone)]
+struct TrackOutput {
+	name: String,
+	sender: mpsc::Sender<(Record, Bytes)>,
+}
+
+impl TrackOutput {
+	async fn send(&self, group: u64, object: u64, priority: u64, payload: Bytes) -> anyhow::Result<()> {
+		let record = Record::Object {
+			track: self.name.clone(),
+			group,
+			object,
+			priority,
+			timestamp: timestamp(),
+			size: payload.len(),
+		};
+
+		self.sender.send((record, payload)).await?;
+		Ok(())
+	}
+
+	async fn stream(self, mut reader: StreamReader) -> anyhow::Result<()> {
+		let mut tasks = FuturesUnordered::new();
+
+		loop {
+			tokio::select! {
+				res = reader.next() => match res? {
+					Some(group) => tasks.push(self.clone().stream_group(group)),
+					None => break,
+				},
+				Some(res) = tasks.next() => res?,
+			}
+		}
+
+		// Finish recording any groups that are still in progress.
+		while let Some(res) = tasks.next().await {
+			res?;
+		}
+
+		Ok(())
+	}
+
+	async fn stream_group(self, mut group: StreamGroupReader) -> anyhow::Result<()> {
+		while let Some(mut object) = group.next().await? {
+			let payload = object.read_all().await?;
+			let info = &object.info.group;
+			self.send(info.group_id, object.info.object_id, info.stream.priority, payload)
+				.await?;
+		}
+
+		Ok(())
+	}
+
+	async fn groups(self, mut reader: GroupsReader) -> anyhow::Result<()> {
+		let mut tasks = FuturesUnordered::new();
+
+		loop {
+			tokio::select! {
+				res = reader.next() => match res? {
+					Some(group) => tasks.push(self.clone().group(group)),
+					None => break,
+				},
+				Some(res) = tasks.next() => res?,
+			}
+		}
+
+		// Finish recording any groups that are still in progress.
+		while let Some(res) = tasks.next().await {
+			res?;
+		}
+
+		Ok(())
+	}
+
+	async fn group(self, mut group: GroupReader) -> anyhow::Result<()> {
+		while let Some(mut object) = group.next().await? {
+			let payload = object.read_all().await?;
+			self.send(group.group_id, object.info.object_id, group.priority, payload)
+				.await?;
+		}
+
+		Ok(())
+	}
+
+	async fn objects(self, mut reader: ObjectsReader) -> anyhow::Result<()> {
+		let mut tasks = FuturesUnordered::new();
+
+		loop {
+			tokio::select! {
+				res = reader.next() => match res? {
+					Some(mut object) => {
+						let output = self.clone();
+						tasks.push(async move {
+							let payload = object.read_all().await?;
+							output.send(object.group_id, object.object_id, object.priority, payload).await
+						});
+					}
+					None => break,
+				},
+				Some(res) = tasks.next() => res?,
+			}
+		}
+
+		// Finish recording any groups that are still in progress.
+		while let Some(res) = tasks.next().await {
+			res?;
+		}
+
+		Ok(())
+	}
+
+	async fn datagrams(self, mut reader: DatagramsReader) -> anyhow::Result<()> {
+		while let Some(datagram) = reader.read().await? {
+			self.send(
+				datagram.group_id,
+				datagram.object_id,
+				datagram.priority,
+				datagram.payload,
+			)
+			.await?;
+		}
+
+		Ok(())
+	}
+}
+
+// Return the track names listed in a catalog, including any init tracks.
+fn catalog_tracks(payload: &[u8]) -> Vec<String> {
+	let catalog: moq_catalog::Root = match serde_json::from_slice(payload) {
+		Ok(catalog) => catalog,
+		Err(err) => {
+			log::warn!("failed to parse catalog: {}", err);
+			return Vec::new();
+		}
+	};
+
+	catalog
+		.tracks
+		.into_iter()
+		.flat_map(|track| std::iter::once(track.name).chain(track.init_track))
+		.collect()
+}
+
+// Make the namespace safe to use as a directory name, percent-encoding any other bytes so it's reversible.
+fn encode(namespace: &str) -> String {
+	let mut encoded = String::with_capacity(namespace.len());
+
+	for byte in namespace.bytes() {
+		match byte {
+			b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' => encoded.push(byte as char),
+			_ => encoded.push_str(&format!("%{:02X}", byte)),
+		}
+	}
+
+	encoded
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	use moq_transport::serve::Tracks;
+
+	use crate::DuplicatePolicy;
+
+	#[test]
+	fn encoded() {
+		assert_eq!(encode("live_a"), "live_a");
+		assert_eq!(encode("live/a"), "live%2Fa");
+		assert_eq!(encode("../%"), "%2E%2E%2F%25");
+		assert_eq!(encode("caf\u{e9}"), "caf%C3%A9");
+	}
+
+	#[test]
+	fn catalog() {
+		let catalog = moq_catalog::Root {
+			version: 1,
+			streaming_format: 1,
+			streaming_format_version: "0.2".to_string(),
+			streaming_delta_updates: false,
+			common_track_fields: Default::default(),
+			tracks: vec![
+				moq_catalog::Track {
+					name: "video".to_string(),
+					init_track: Some("video.init".to_string()),
+					..Default::default()
+				},
+				moq_catalog::Track {
+					name: "audio".to_string(),
+					..Default::default()
+				},
+			],
+		};
+
+		let payload = serde_json::to_vec(&catalog).unwrap();
+		assert_eq!(catalog_tracks(&payload), ["video", "video.init", "audio"]);
+		assert!(catalog_tracks(b"not json").is_empty());
+	}
+
+	#[tokio::test]
+	async fn backup() {
+		let dir = std::env::temp_dir().join(format!("moq-record-{}", timestamp()));
+
+		let announces = Announces::new();
+		let mut locals = Locals::new();
+		locals.set_policy(DuplicatePolicy::Backup);
+
+		let config = RecordConfig {
+			dir: Some(dir.clone()),
+			..Default::default()
+		};
+		let recorder = Recorder::new(config, announces.clone(), locals.clone()).unwrap();
+		let task = tokio::spawn(recorder.run());
+
+		// A primary and a backup publisher of the same namespace.
+		let (_primary_writer, _primary_request, primary) = Tracks::new("live/a".to_string()).produce();
+		let (_backup_writer, _backup_request, backup) = Tracks::new("live/a".to_string()).produce();
+
+		let _primary = locals.register(primary.clone()).await.unwrap();
+		let _backup = locals.register(backup.clone()).await.unwrap();
+		let _first = announces.announce(primary);
+		let _second = announces.announce(backup);
+
+		tokio::time::sleep(time::Duration::from_millis(100)).await;
+		task.abort();
+
+		// Only one recording was started.
+		let files = std::fs::read_dir(dir.join("live%2Fa")).unwrap().count();
+		assert_eq!(files, 1);
+
+		std::fs::remove_dir_all(dir).unwrap();
+	}
+
+	#[tokio::test]
+	async fn empty() {
+		let dir = std::env::temp_dir().join(format!("moq-record-empty-{}", timestamp()));
+
+		let announces = Announces::new();
+		let mut locals = Locals::new();
+
+		let config = RecordConfig {
+			dir: Some(dir.clone()),
+			..Default::default()
+		};
+		let recorder = Recorder::new(config, announces.clone(), locals.clone()).unwrap();
+		let task = tokio::spawn(recorder.run());
+
+		// The publisher doesn't serve any tracks, so every subscription fails.
+		let (_writer, request, reader) = Tracks::new("live/a".to_string()).produce();
+		drop(request);
+
+		let _registered = locals.register(reader.clone()).await.unwrap();
+		let _announce = announces.announce(reader);
+
+		tokio::time::sleep(time::Duration::from_millis(100)).await;
+
+		// Other announces don't start it again either.
+		let (_other_writer, _other_request, other) = Tracks::new("live/b".to_string()).produce();
+		let _other = announces.announce(other);
+
+		tokio::time::sleep(time::Duration::from_millis(100)).await;
+		assert!(!task.is_finished());
+		task.abort();
+
+		// The empty recording was removed and not started again.
+		assert!(!dir.join("live%2Fa").exists());
+
+		std::fs::remove_dir_all(dir).ok();
+	}
+}
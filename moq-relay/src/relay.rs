@@ -1,12 +1,20 @@
-use std::net;
+use std::{net, time};
 
 use anyhow::Context;
 
 use futures::{stream::FuturesUnordered, FutureExt, StreamExt};
 use moq_native::quic;
+use tokio::sync::watch;
 use url::Url;
 
-use crate::{Api, Consumer, Locals, Producer, Remotes, RemotesConsumer, RemotesProducer, Session};
+use crate::{
+	Announces, Api, ConfigFile, Consumer, Drain, DuplicatePolicy, Forward, ForwardConfig, ForwardTarget, Health,
+	Limiter, Limits, Locals, Mesh, MeshConfig, Origins, Producer, Quotas, RecordConfig, Recorder, Remotes,
+	RemotesConsumer, RemotesProducer, Replays, Routes, Session, Trust,
+};
+
+// How often to check if the TLS certificate files were modified.
+const CERT_INTERVAL: time::Duration = time::Duration::from_secs(10);
 
 pub struct RelayConfig {
 	/// Listen on this address
@@ -15,23 +23,75 @@ pub struct RelayConfig {
 	/// The TLS configuration.
 	pub tls: moq_native::tls::Config,
 
-	/// Forward all announcements to the (optional) URL.
-	pub announce: Option<Url>,
+	/// Forward announcements to each of these targets.
+	pub forward: Vec<ForwardTarget>,
 
 	/// Connect to the HTTP moq-api at this URL.
 	pub api: Option<Url>,
 
+	/// Authenticate with moq-api using this bearer token.
+	pub api_token: Option<String>,
+
+	/// Decides which relay to connect to for origins returned by moq-api.
+	pub api_routing: moq_api::Routing,
+
+	/// The maximum number of sessions advertised to moq-api.
+	pub api_capacity: Option<u64>,
+
+	/// Connect to these relays and propagate announces to them.
+	pub peers: Vec<Url>,
+
+	/// Authenticate with peers using this shared secret.
+	pub mesh_secret: Option<String>,
+
+	/// Use a static routing table instead of moq-api to find origins.
+	pub routes: Option<Routes>,
+
 	/// Our hostname which we advertise to other origins.
 	/// We use QUIC, so the certificate must be valid for this address.
 	pub node: Option<Url>,
+
+	/// Limits to protect against misbehaving clients.
+	pub limits: Limits,
+
+	/// What to do when a namespace is announced that is already registered.
+	pub duplicate: DuplicatePolicy,
+
+	/// Record matching broadcasts to disk.
+	pub record: RecordConfig,
+
+	/// Announce and serve these recorded broadcasts.
+	pub replays: Replays,
+
+	/// Decides which sessions are from other relays, authenticated via mutual TLS.
+	pub trust: Trust,
+
+	/// Used to gracefully drain the relay before shutdown.
+	pub drain: Drain,
+
+	/// Receives any reloaded configuration, applying the settings that can change live.
+	pub reload: watch::Receiver<ConfigFile>,
 }
 
 pub struct Relay {
 	quic: quic::Endpoint,
-	announce: Option<Url>,
+	tls: moq_native::tls::Config,
+	limiter: Limiter,
+	drain: Drain,
+	reload: watch::Receiver<ConfigFile>,
 	locals: Locals,
 	api: Option<Api>,
+	routes: Option<Routes>,
 	remotes: Option<(RemotesProducer, RemotesConsumer)>,
+
+	// Any announces from our clients are forwarded upstream and to any peers.
+	announces: Option<Announces>,
+	forwards: Vec<Forward>,
+	mesh: Option<Mesh>,
+	recorder: Option<Recorder>,
+	replays: Replays,
+	health: Health,
+	trust: Trust,
 }
 
 impl Relay {
@@ -39,108 +99,392 @@ impl Relay {
 	pub fn new(config: RelayConfig) -> anyhow::Result<Self> {
 		let quic = quic::Endpoint::new(quic::Config {
 			bind: config.bind,
-			tls: config.tls,
+			tls: config.tls.clone(),
 		})?;
 
-		let api = if let (Some(url), Some(node)) = (config.api, config.node) {
+		let api = if let (Some(url), Some(node)) = (config.api, config.node.clone()) {
 			log::info!("using moq-api: url={} node={}", url, node);
-			Some(Api::new(url, node))
+
+			// Advertise our region and capacity so other relays can choose the best candidate.
+			let origin = moq_api::Origin {
+				region: config.api_routing.region.clone(),
+				capacity: config.api_capacity,
+				..moq_api::Origin::new(node)
+			};
+
+			Some(Api::new(
+				url,
+				origin,
+				config.api_token,
+				config.api_routing,
+				config.drain.clone(),
+			))
 		} else {
 			None
 		};
 
+		let origins = match (&api, &config.routes) {
+			(Some(api), _) => Some(Origins::Api(Box::new(api.clone()))),
+			(None, Some(routes)) => Some(Origins::Routes(routes.clone())),
+			(None, None) => None,
+		};
+
 		let locals = Locals::new();
+		locals.set_policy(config.duplicate);
 
-		let remotes = api.clone().map(|api| {
+		let remotes = origins.map(|origins| {
 			Remotes {
-				api,
+				origins,
+				node: config.node.clone(),
 				quic: quic.client.clone(),
 			}
 			.produce()
 		});
 
+		let announces = (!config.forward.is_empty() || !config.peers.is_empty() || config.record.dir.is_some())
+			.then(Announces::new);
+
+		let forwards: Vec<Forward> = config
+			.forward
+			.into_iter()
+			.map(|target| {
+				log::info!(
+					"forwarding announces: url={} namespaces={:?}",
+					target.url,
+					target.namespaces
+				);
+
+				Forward::new(ForwardConfig {
+					target,
+					announces: announces.clone().unwrap(),
+					locals: locals.clone(),
+					remotes: remotes.as_ref().map(|(_, consumer)| consumer.clone()),
+					quic: quic.client.clone(),
+				})
+			})
+			.collect();
+
+		let mesh = if config.peers.is_empty() {
+			None
+		} else {
+			log::info!("using mesh: peers={:?}", config.peers);
+
+			Some(Mesh::new(MeshConfig {
+				peers: config.peers,
+				node: config.node,
+				secret: config.mesh_secret,
+				quic: quic.client.clone(),
+				locals: locals.clone(),
+				announces: announces.clone().unwrap(),
+			}))
+		};
+
+		let recorder = announces.clone().and_then(|announces| {
+			let recorder = Recorder::new(config.record, announces, locals.clone())?;
+			log::info!("recording broadcasts: {:?}", recorder.config());
+			Some(recorder)
+		});
+
+		let health = Health::new(config.drain.clone(), api.clone(), forwards.clone());
+
 		Ok(Self {
 			quic,
-			announce: config.announce,
+			tls: config.tls,
+			limiter: Limiter::new(config.limits),
+			drain: config.drain,
+			reload: config.reload,
 			api,
+			routes: config.routes,
 			locals,
 			remotes,
+			announces,
+			forwards,
+			mesh,
+			recorder,
+			replays: config.replays,
+			health,
+			trust: config.trust,
 		})
 	}
 
+	/// The supervised sessions used to forward announces upstream.
+	pub fn forwards(&self) -> Vec<Forward> {
+		self.forwards.clone()
+	}
+
+	/// The bandwidth usage of each quota.
+	pub fn quotas(&self) -> Quotas {
+		self.limiter.quotas()
+	}
+
+	/// The supervised sessions to each peer in the mesh.
+	pub fn peers(&self) -> Vec<Forward> {
+		self.mesh.as_ref().map(|mesh| mesh.peers().to_vec()).unwrap_or_default()
+	}
+
+	/// The state used to answer health and readiness probes.
+	pub fn health(&self) -> Health {
+		self.health.clone()
+	}
+
 	pub async fn run(self) -> anyhow::Result<()> {
 		let mut tasks = FuturesUnordered::new();
 
+		tasks.push(
+			Self::run_reload(
+				self.reload,
+				Live {
+					tls: self.tls,
+					limiter: self.limiter.clone(),
+					drain: self.drain.clone(),
+					locals: self.locals.clone(),
+					routes: self.routes.clone(),
+					trust: self.trust.clone(),
+					api: self.api.clone(),
+				},
+			)
+			.boxed(),
+		);
+
 		let remotes = self.remotes.map(|(producer, consumer)| {
 			tasks.push(producer.run().boxed());
 			consumer
 		});
 
-		let forward = if let Some(url) = &self.announce {
-			log::info!("forwarding announces to {}", url);
-			let session = self
-				.quic
-				.client
-				.connect(url)
-				.await
-				.context("failed to establish forward connection")?;
-			let (session, publisher, subscriber) = moq_transport::session::Session::connect(session)
-				.await
-				.context("failed to establish forward session")?;
-
-			// Create a normal looking session, except we never forward or register announces.
-			let session = Session {
-				session,
-				producer: Some(Producer::new(publisher, self.locals.clone(), remotes.clone())),
-				consumer: Some(Consumer::new(subscriber, self.locals.clone(), None, None)),
-			};
+		// Each forward reconnects independently, so a failure doesn't take down the relay.
+		for forward in self.forwards {
+			tasks.push(forward.run().boxed());
+		}
 
-			let forward = session.producer.clone();
+		if let Some(mesh) = self.mesh {
+			tasks.push(mesh.run().boxed());
+		}
 
-			tasks.push(async move { session.run().await.context("forwarding failed") }.boxed());
+		if let Some(recorder) = self.recorder {
+			tasks.push(recorder.run().boxed());
+		}
 
-			forward
-		} else {
-			None
-		};
+		// Cache the origins from moq-api, invalidated as they change, and register as a node.
+		if let Some(api) = self.api.clone() {
+			tasks.push(api.clone().run().boxed());
+			tasks.push(api.heartbeat(self.limiter.quotas()).boxed());
+		}
+
+		if !self.replays.is_empty() {
+			let replays = self.replays.clone();
+			tasks.push(
+				replays
+					.run(self.locals.clone(), self.api.clone(), self.announces.clone())
+					.boxed(),
+			);
+		}
+
+		let forward = self.announces;
 
 		let mut server = self.quic.server.context("missing TLS certificate")?;
 		log::info!("listening on {}", server.local_addr()?);
+		self.health.set_listening(true);
 
-		loop {
+		// Sessions are tracked separately so we know when they've all finished draining.
+		let mut sessions = FuturesUnordered::new();
+		let mut deadline = None;
+
+		// Every exit breaks out of the loop instead of returning, so we always deregister from moq-api below.
+		let res = loop {
 			tokio::select! {
-				res = server.accept() => {
-					let conn = res.context("failed to accept QUIC connection")?;
+				res = server.incoming() => {
+					let conn = match res.context("failed to accept QUIC connection") {
+						Ok(conn) => conn,
+						Err(err) => break Err(err),
+					};
+
+					// Enforce per-IP limits before performing the QUIC handshake, refusing the connection if exceeded.
+					// The client only sees CONNECTION_REFUSED, since an application error code requires a handshake.
+					let addr = conn.remote_address();
+					let limits = match self.limiter.admit(addr) {
+						Ok(limits) => limits,
+						Err(err) => {
+							log::warn!("refusing connection: addr={} code={} error={}", addr, err.code(), err);
+							conn.refuse();
+							continue;
+						}
+					};
 
 					let locals = self.locals.clone();
 					let remotes = remotes.clone();
 					let forward = forward.clone();
 					let api = self.api.clone();
+					let drain = self.drain.clone();
+					let trust = self.trust.clone();
+					let replays = (!self.replays.is_empty()).then(|| self.replays.clone());
+
+					sessions.push(async move {
+						let (conn, peer) = match quic::Server::handshake(conn).await {
+							Ok(res) => res,
+							Err(err) => {
+								log::warn!("failed to accept QUIC connection: {}", err);
+								return;
+							}
+						};
+
+						// Relays authenticated via mutual TLS are trusted, so they're released from any limits.
+						let trusted = trust.is_trusted(&peer);
+						let is_peer = trust.is_peer(&peer);
+						let limits = (!trusted).then_some(limits);
+
+						// Sessions from other relays in the mesh don't use per-session limits.
+						// Their announces are registered but never propagated, forwarded or stored in moq-api.
+						if is_peer {
+							log::info!("accepted peer session: addr={} trusted={}", peer.addr, trusted);
+						} else if trusted {
+							let names = peer.identity.as_ref().map(|identity| identity.names.clone()).unwrap_or_default();
+							log::info!("accepted trusted session: addr={} names={:?}", peer.addr, names);
+						}
 
-					tasks.push(async move {
 						let (session, publisher, subscriber) = match moq_transport::session::Session::accept(conn).await {
 							Ok(session) => session,
 							Err(err) => {
 								log::warn!("failed to accept MoQ session: {}", err);
-								return Ok(());
+								return;
 							}
 						};
 
-						let session = Session {
-							session,
-							producer: publisher.map(|publisher| Producer::new(publisher, locals.clone(), remotes)),
-							consumer: subscriber.map(|subscriber| Consumer::new(subscriber, locals, api, forward)),
+						let session = if is_peer {
+							Session {
+								session,
+								producer: publisher.map(|publisher| Producer::new(publisher, locals.clone(), None, replays, None)),
+								consumer: subscriber.map(|subscriber| Consumer::new(subscriber, locals, None, None, None)),
+								drain: Some(drain),
+							}
+						} else {
+							Session {
+								session,
+								producer: publisher.map(|publisher| Producer::new(publisher, locals.clone(), remotes, replays, limits.clone())),
+								consumer: subscriber.map(|subscriber| Consumer::new(subscriber, locals, api, forward, limits)),
+								drain: Some(drain),
+							}
 						};
 
 						if let Err(err) = session.run().await {
 							log::warn!("failed to run MoQ session: {}", err);
 						}
-
-						Ok(())
 					}.boxed());
 				},
-				res = tasks.next(), if !tasks.is_empty() => res.unwrap()?,
+				_ = self.drain.wait(), if deadline.is_none() => {
+					// Refuse new sessions; existing sessions are sent a GOAWAY.
+					server.refuse();
+
+					let timeout = self.drain.config().timeout();
+					log::info!("draining sessions: count={} timeout={:?}", sessions.len(), timeout);
+					deadline = Some(tokio::time::Instant::now() + timeout);
+				},
+				_ = tokio::time::sleep_until(deadline.unwrap_or_else(far_future)), if deadline.is_some() => {
+					log::warn!("drain timeout expired: remaining={}", sessions.len());
+					break Ok(());
+				},
+				_ = sessions.next(), if !sessions.is_empty() => {},
+				res = tasks.next(), if !tasks.is_empty() => if let Err(err) = res.unwrap() {
+					break Err(err);
+				},
+			}
+
+			if let Some(api) = &self.api {
+				api.set_load(sessions.len());
+			}
+
+			if deadline.is_some() && sessions.is_empty() {
+				log::info!("drained all sessions");
+				break Ok(());
+			}
+		};
+
+		// Dropping any remaining sessions and tasks removes their origins, so wait for moq-api before exiting.
+		drop(sessions);
+		drop(tasks);
+
+		if let Some(api) = &self.api {
+			api.close().await;
+		}
+
+		res
+	}
+
+	// Apply any settings that can change without a restart.
+	async fn run_reload(mut reload: watch::Receiver<ConfigFile>, live: Live) -> anyhow::Result<()> {
+		let Live {
+			tls,
+			limiter,
+			drain,
+			locals,
+			routes,
+			trust,
+			api,
+		} = live;
+
+		// The certificate files are also watched, so renewed certificates are used without a SIGHUP.
+		let mut tls_args = reload.borrow().tls.args();
+
+		loop {
+			tokio::select! {
+				res = reload.changed() => if res.is_err() { break },
+				_ = tls.certs.watch(&tls_args, CERT_INTERVAL) => unreachable!(),
+			}
+
+			let config = reload.borrow_and_update().clone();
+
+			// Secrets are read from disk again, keeping the previous rules if they can't be read.
+			match config.mesh_secret() {
+				Ok(secret) => trust.update(config.trust.clone(), secret),
+				Err(err) => log::warn!("failed to reload trust: {:#}", err),
+			}
+
+			if let Some(api) = &api {
+				let res = config
+					.api_token()
+					.and_then(|token| api.update(token, &config.api_routing));
+				if let Err(err) = res {
+					log::warn!("failed to reload api: {:#}", err);
+				}
+			}
+
+			log::info!("applying limits: {:?}", config.limits);
+			limiter.update(config.limits);
+			drain.update(config.drain);
+			locals.set_policy(config.duplicate);
+
+			if let (Some(routes), Some(path)) = (&routes, &config.routes) {
+				if let Err(err) = routes.reload(path) {
+					log::warn!("failed to reload routes: {:#}", err);
+				}
+			}
+
+			tls_args = config.tls.args();
+			match tls_args.reload(&tls) {
+				Ok(()) => log::info!("reloaded TLS certificates: fingerprints={:?}", tls.fingerprints()),
+				Err(err) => log::warn!("failed to reload TLS certificates: {:#}", err),
 			}
 		}
+
+		// The configuration is no longer reloaded, but renewed certificates should still be used.
+		log::warn!("stopped reloading the configuration, still watching the TLS certificate files");
+		tls.certs.watch(&tls_args, CERT_INTERVAL).await;
+
+		unreachable!()
 	}
 }
+
+// The components with settings that can change without a restart.
+struct Live {
+	tls: moq_native::tls::Config,
+	limiter: Limiter,
+	drain: Drain,
+	locals: Locals,
+	routes: Option<Routes>,
+	trust: Trust,
+	api: Option<Api>,
+}
+
+// tokio::time::Instant doesn't have a MAX, so pick something far in the future.
+fn far_future() -> tokio::time::Instant {
+	tokio::time::Instant::now() + time::Duration::from_secs(86400 * 365)
+}
@@ -5,6 +5,7 @@ use std::fmt;
 use std::ops;
 use std::sync::Arc;
 use std::sync::Weak;
+use std::time;
 
 use futures::stream::FuturesUnordered;
 use futures::FutureExt;
@@ -14,11 +15,46 @@ use moq_transport::serve::{Track, TrackReader, TrackWriter};
 use moq_transport::watch::State;
 use url::Url;
 
-use crate::Api;
+use crate::{Api, Routes};
+
+// How long to skip an origin after failing to connect, so we use the next fallback instead.
+const FAILED_BACKOFF: time::Duration = time::Duration::from_secs(10);
+
+/// Where to find the origins for a namespace.
+#[derive(Clone)]
+pub enum Origins {
+	/// Query the moq-api server.
+	Api(Box<Api>),
+
+	/// Use a static routing table, without any HTTP calls.
+	Routes(Routes),
+}
+
+impl Origins {
+	/// Return the origins for a namespace, in order of preference.
+	pub async fn get(&self, namespace: &str) -> anyhow::Result<Vec<Url>> {
+		Ok(match self {
+			Self::Api(api) => {
+				let mut urls: Vec<Url> = Vec::new();
+				for origin in api.get_origins(namespace).await? {
+					// Candidates can share a URL after routing, for example when using an edge.
+					if !urls.contains(&origin.url) {
+						urls.push(origin.url);
+					}
+				}
+				urls
+			}
+			Self::Routes(routes) => routes.get_origins(namespace),
+		})
+	}
+}
 
 pub struct Remotes {
-	/// The client we use to fetch/store origin information.
-	pub api: Api,
+	/// Used to fetch origin information.
+	pub origins: Origins,
+
+	/// Our own URL, which is skipped if it's listed as an origin.
+	pub node: Option<Url>,
 
 	// A QUIC endpoint we'll use to fetch from other origins.
 	pub quic: quic::Client,
@@ -40,6 +76,9 @@ impl Remotes {
 struct RemotesState {
 	lookup: HashMap<Url, RemoteConsumer>,
 	requested: VecDeque<RemoteProducer>,
+
+	// Origins that recently failed, and when they can be tried again.
+	failed: HashMap<Url, time::Instant>,
 }
 
 // Clone for convenience, but there should only be one instance of this
@@ -80,18 +119,26 @@ impl RemotesProducer {
 						let info = remote.info.clone();
 
 						log::warn!("serving remote: {:?}", info);
-						if let Err(err) = remote.run().await {
+						let res = remote.run().await;
+						if let Err(err) = &res {
 							log::warn!("failed serving remote: {:?}, error: {}", info, err);
 						}
 
-						url
+						(url, res.is_ok())
 					});
 				}
 				res = tasks.next(), if !tasks.is_empty() => {
-					let url = res.unwrap();
+					let (url, ok) = res.unwrap();
 
 					if let Some(mut state) = self.state.lock_mut() {
 						state.lookup.remove(&url);
+
+						if !ok {
+							// Use any fallback origins for a while.
+							let now = time::Instant::now();
+							state.failed.retain(|_, until| *until > now);
+							state.failed.insert(url, now + FAILED_BACKOFF);
+						}
 					}
 				},
 				else => return Ok(()),
@@ -120,14 +167,33 @@ impl RemotesConsumer {
 	}
 
 	pub async fn route(&self, namespace: &str) -> anyhow::Result<Option<RemoteConsumer>> {
-		// Always fetch the origin instead of using the (potentially invalid) cache.
-		let origin = match self.api.get_origin(namespace).await? {
+		// Always fetch the origins instead of using the (potentially invalid) cache.
+		let origins = self.origins.get(namespace).await?;
+		let origins: Vec<_> = origins
+			.into_iter()
+			.filter(|url| Some(url) != self.node.as_ref())
+			.collect();
+
+		let state = self.state.lock();
+
+		// Prefer an existing connection, then the first origin that hasn't recently failed.
+		let now = time::Instant::now();
+		let url = origins
+			.iter()
+			.find(|url| state.lookup.contains_key(*url))
+			.or_else(|| {
+				origins
+					.iter()
+					.find(|url| state.failed.get(*url).is_none_or(|until| *until <= now))
+			})
+			.or(origins.first());
+
+		let url = match url {
 			None => return Ok(None),
-			Some(origin) => origin,
+			Some(url) => url.clone(),
 		};
 
-		let state = self.state.lock();
-		if let Some(remote) = state.lookup.get(&origin.url).cloned() {
+		if let Some(remote) = state.lookup.get(&url).cloned() {
 			return Ok(Some(remote));
 		}
 
@@ -137,14 +203,14 @@ impl RemotesConsumer {
 		};
 
 		let remote = Remote {
-			url: origin.url.clone(),
+			url: url.clone(),
 			remotes: self.info.clone(),
 		};
 
 		let (writer, reader) = remote.produce();
 		state.requested.push_back(writer);
 
-		state.lookup.insert(origin.url, reader.clone());
+		state.lookup.insert(url, reader.clone());
 
 		Ok(Some(reader))
 	}
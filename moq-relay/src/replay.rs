@@ -0,0 +1,618 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::{path, time};
+
+use anyhow::Context;
+use futures::{future::BoxFuture, stream::FuturesUnordered, FutureExt, StreamExt};
+use moq_transport::{
+	data::ObjectStatus,
+	message::{SubscribeLocation, SubscribePair},
+	serve::{
+		Datagram, Group, GroupWriter, Object, ServeError, StreamGroupWriter, TrackReader, TrackWriter, TrackWriterMode,
+		Tracks,
+	},
+};
+use serde::Deserialize;
+
+use crate::{Announces, Api, ArchiveMode, ArchiveReader, Locals, Record};
+
+const MIN_BACKOFF: time::Duration = time::Duration::from_secs(1);
+const MAX_BACKOFF: time::Duration = time::Duration::from_secs(30);
+
+/// How quickly objects are served from a recording.
+#[derive(clap::ValueEnum, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Pacing {
+	/// Serve objects at the rate they were recorded.
+	#[default]
+	Realtime,
+
+	/// Serve objects as fast as possible.
+	Fast,
+}
+
+/// Configuration used to serve recorded broadcasts.
+#[derive(clap::Args, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReplayConfig {
+	/// Announce and serve the broadcast recorded at this path, either a file or a directory of files.
+	/// This can be used multiple times to serve multiple broadcasts.
+	#[arg(long = "replay")]
+	pub paths: Vec<path::PathBuf>,
+
+	/// Serve objects at the rate they were recorded or as fast as possible [default: realtime]
+	#[arg(long = "replay-pacing", value_enum)]
+	pub pacing: Option<Pacing>,
+}
+
+impl ReplayConfig {
+	/// Override any values that are set in `other`.
+	pub fn merge(&mut self, other: &ReplayConfig) {
+		if !other.paths.is_empty() {
+			self.paths.clone_from(&other.paths);
+		}
+
+		self.pacing = other.pacing.or(self.pacing);
+	}
+}
+
+/// The range of objects to serve, inclusive.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReplayRange {
+	/// The first group and object, or the start of the recording.
+	pub start: Option<(u64, u64)>,
+
+	/// The last group and optionally the last object, or the end of the recording.
+	pub end: Option<(u64, Option<u64>)>,
+}
+
+impl ReplayRange {
+	/// Use the range requested by a subscription, ignoring any relative locations.
+	pub fn new(start: Option<&SubscribePair>, end: Option<&SubscribePair>) -> Self {
+		fn absolute(location: &SubscribeLocation) -> Option<u64> {
+			match location {
+				SubscribeLocation::Absolute(value) => Some(*value),
+				_ => None,
+			}
+		}
+
+		let start = start.and_then(|start| Some((absolute(&start.group)?, absolute(&start.object).unwrap_or(0))));
+		let end = end.and_then(|end| Some((absolute(&end.group)?, absolute(&end.object))));
+
+		Self { start, end }
+	}
+
+	fn contains(&self, group: u64, object: u64) -> bool {
+		let after = self.start.is_none_or(|start| (group, object) >= start);
+		let before = self.end.is_none_or(|(end, end_object)| {
+			group < end || (group == end && end_object.is_none_or(|end_object| object <= end_object))
+		});
+
+		after && before
+	}
+}
+
+/// Paces the objects of a replayed broadcast, shared by its tracks so they stay in sync.
+#[derive(Clone, Default)]
+pub struct ReplayClock {
+	// The time the first object was served and its timestamp.
+	start: Arc<Mutex<Option<(tokio::time::Instant, u64)>>>,
+}
+
+impl ReplayClock {
+	// The time to serve an object with this timestamp, relative to the first object served by any track.
+	fn deadline(&self, timestamp: u64) -> tokio::time::Instant {
+		let mut start = self.start.lock().unwrap();
+		let (start, base) = *start.get_or_insert((tokio::time::Instant::now(), timestamp));
+
+		start + time::Duration::from_micros(timestamp.saturating_sub(base))
+	}
+}
+
+/// A broadcast loaded from one or more recordings, served as if it were live.
+///
+/// Only the tracks are indexed when loading; objects are read from the files as they're served.
+pub struct Replay {
+	namespace: String,
+	pacing: Pacing,
+	files: Vec<path::PathBuf>,
+	tracks: HashMap<String, ArchiveMode>,
+}
+
+impl Replay {
+	/// Find the tracks in a recording, which is either a single file or a directory of rotated files.
+	pub async fn load(path: &path::Path, pacing: Pacing) -> anyhow::Result<Self> {
+		let mut files = Vec::new();
+
+		if path.is_dir() {
+			let mut entries = tokio::fs::read_dir(path)
+				.await
+				.with_context(|| format!("failed to read directory: {}", path.display()))?;
+
+			while let Some(entry) = entries.next_entry().await? {
+				if entry.path().extension().is_some_and(|ext| ext == "moqrec") {
+					files.push(entry.path());
+				}
+			}
+
+			// Files are named after the time they were created.
+			files.sort();
+		} else {
+			files.push(path.to_path_buf());
+		}
+
+		anyhow::ensure!(!files.is_empty(), "no recordings found: {}", path.display());
+
+		let mut namespace = None;
+		let mut tracks = HashMap::new();
+
+		for file in &files {
+			let mut reader = ArchiveReader::open(file.clone()).await?;
+
+			match &namespace {
+				None => namespace = Some(reader.namespace().to_string()),
+				Some(namespace) => anyhow::ensure!(
+					namespace == reader.namespace(),
+					"recordings have different namespaces: {}",
+					file.display()
+				),
+			}
+
+			// Payloads are skipped, so this only reads the records.
+			while let Some(record) = reader.record().await? {
+				match record {
+					Record::Track { track, mode } => {
+						tracks.entry(track).or_insert(mode);
+					}
+					Record::Object { track, .. } => {
+						anyhow::ensure!(tracks.contains_key(&track), "object before track: {}", file.display())
+					}
+					Record::Header { .. } => anyhow::bail!("unexpected header: {}", file.display()),
+				}
+			}
+		}
+
+		Ok(Self {
+			namespace: namespace.unwrap(),
+			pacing,
+			files,
+			tracks,
+		})
+	}
+
+	pub fn namespace(&self) -> &str {
+		&self.namespace
+	}
+
+	/// Serve the objects within the range to the track, closing it at the end.
+	///
+	/// With realtime pacing, tracks sharing the `clock` are served at the same rate, relative to the first object of any of them.
+	pub async fn serve(&self, writer: TrackWriter, range: ReplayRange, clock: ReplayClock) -> anyhow::Result<()> {
+		let mode = match self.tracks.get(&writer.name) {
+			Some(mode) => *mode,
+			None => {
+				writer.close(ServeError::NotFound)?;
+				return Ok(());
+			}
+		};
+
+		let name = writer.name.clone();
+
+		// The output is created by the first object, since a stream uses its priority.
+		let mut writer = Some(writer);
+		let mut output = None;
+		let mut groups = CurrentGroup::default();
+
+		// Rotated files repeat the latest catalog object, so an object identical to the previous one is skipped.
+		let mut last = None;
+
+		for file in &self.files {
+			let mut reader = ArchiveReader::open(file.clone()).await?;
+
+			while let Some(record) = reader.record().await? {
+				let (group, object, priority, timestamp) = match record {
+					Record::Object {
+						track,
+						group,
+						object,
+						priority,
+						timestamp,
+						..
+					} if track == name => (group, object, priority, timestamp),
+					_ => continue,
+				};
+
+				if last == Some((group, object)) || !range.contains(group, object) {
+					continue;
+				}
+				last = Some((group, object));
+
+				if self.pacing == Pacing::Realtime {
+					tokio::time::sleep_until(clock.deadline(timestamp)).await;
+				}
+
+				let payload = reader.payload().await?;
+
+				let output = match output.as_mut() {
+					Some(output) => output,
+					None => output.insert(Self::output(writer.take().unwrap(), mode, priority)?),
+				};
+
+				match output {
+					TrackWriterMode::Stream(stream) => {
+						if let Some(group) = groups.stream(group, || stream.create(group))? {
+							group.write(payload)?;
+						}
+					}
+					TrackWriterMode::Groups(writer) => {
+						let create = Group {
+							group_id: group,
+							priority,
+						};
+
+						if let Some(group) = groups.group(group, || writer.create(create))? {
+							group.write(payload)?;
+						}
+					}
+					TrackWriterMode::Objects(writer) => {
+						let object = Object {
+							group_id: group,
+							object_id: object,
+							priority,
+						};
+						writer.write(object, payload)?;
+					}
+					TrackWriterMode::Datagrams(writer) => {
+						writer.write(Datagram {
+							group_id: group,
+							object_id: object,
+							priority,
+							status: ObjectStatus::Object,
+							payload,
+						})?;
+					}
+					_ => anyhow::bail!("invalid track mode"),
+				}
+			}
+		}
+
+		// There were no objects in the range, so serve an empty track.
+		if let Some(writer) = writer {
+			Self::output(writer, mode, 0)?;
+		}
+
+		Ok(())
+	}
+
+	fn output(writer: TrackWriter, mode: ArchiveMode, priority: u64) -> Result<TrackWriterMode, ServeError> {
+		Ok(match mode {
+			ArchiveMode::Stream => writer.stream(priority)?.into(),
+			ArchiveMode::Groups => writer.groups()?.into(),
+			ArchiveMode::Objects => writer.objects()?.into(),
+			ArchiveMode::Datagrams => writer.datagrams()?.into(),
+		})
+	}
+}
+
+// Only the latest group is served for stream and group modes, so any objects for older groups are skipped.
+#[derive(Default)]
+struct CurrentGroup {
+	stream: Option<(u64, StreamGroupWriter)>,
+	group: Option<(u64, GroupWriter)>,
+}
+
+impl CurrentGroup {
+	fn stream<F>(&mut self, group_id: u64, create: F) -> Result<Option<&mut StreamGroupWriter>, ServeError>
+	where
+		F: FnOnce() -> Result<StreamGroupWriter, ServeError>,
+	{
+		match &self.stream {
+			Some((current, _)) if *current > group_id => return Ok(None),
+			Some((current, _)) if *current == group_id => {}
+			_ => self.stream = Some((group_id, create()?)),
+		}
+
+		Ok(self.stream.as_mut().map(|(_, writer)| writer))
+	}
+
+	fn group<F>(&mut self, group_id: u64, create: F) -> Result<Option<&mut GroupWriter>, ServeError>
+	where
+		F: FnOnce() -> Result<GroupWriter, ServeError>,
+	{
+		match &self.group {
+			Some((current, _)) if *current > group_id => return Ok(None),
+			Some((current, _)) if *current == group_id => {}
+			_ => self.group = Some((group_id, create()?)),
+		}
+
+		Ok(self.group.as_mut().map(|(_, writer)| writer))
+	}
+}
+
+/// Every recorded broadcast served by the relay.
+#[derive(Clone, Default)]
+pub struct Replays {
+	replays: Arc<HashMap<String, Arc<Replay>>>,
+}
+
+impl Replays {
+	pub async fn load(config: &ReplayConfig) -> anyhow::Result<Self> {
+		let pacing = config.pacing.unwrap_or_default();
+		let mut replays = HashMap::new();
+
+		for path in &config.paths {
+			let replay = Replay::load(path, pacing).await?;
+			log::info!(
+				"loaded recording: path={} namespace={} tracks={}",
+				path.display(),
+				replay.namespace(),
+				replay.tracks.len()
+			);
+
+			anyhow::ensure!(
+				!replays.contains_key(replay.namespace()),
+				"duplicate recording namespace: {}",
+				replay.namespace()
+			);
+			replays.insert(replay.namespace().to_string(), Arc::new(replay));
+		}
+
+		Ok(Self {
+			replays: Arc::new(replays),
+		})
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.replays.is_empty()
+	}
+
+	/// Serve a replayed track from the requested range, independent of any other subscriptions.
+	///
+	/// Returns None if the namespace isn't a recording.
+	pub fn subscribe(&self, namespace: &str, name: &str, range: ReplayRange) -> Option<TrackReader> {
+		let replay = self.replays.get(namespace)?.clone();
+		let (writer, reader) = moq_transport::serve::Track::new(namespace.to_string(), name.to_string()).produce();
+
+		tokio::spawn(async move {
+			if let Err(err) = replay.serve(writer, range, ReplayClock::default()).await {
+				log::warn!("failed serving replay: error={:#}", err);
+			}
+		});
+
+		Some(reader)
+	}
+
+	/// Announce every recording until the relay exits.
+	///
+	/// A recording that fails is announced again after a backoff, without affecting the others.
+	pub async fn run(self, locals: Locals, api: Option<Api>, announces: Option<Announces>) -> anyhow::Result<()> {
+		let mut tasks: FuturesUnordered<_> = self
+			.replays
+			.values()
+			.map(|replay| Self::retry(replay.clone(), locals.clone(), api.clone(), announces.clone()))
+			.collect();
+
+		while tasks.next().await.is_some() {}
+
+		Ok(())
+	}
+
+	// Announce the recording until it's replaced by a newer announce, retrying on any error.
+	async fn retry(replay: Arc<Replay>, locals: Locals, api: Option<Api>, announces: Option<Announces>) {
+		let mut backoff = MIN_BACKOFF;
+
+		loop {
+			let started = time::Instant::now();
+
+			let err = match Self::announce(replay.clone(), locals.clone(), api.clone(), announces.clone()).await {
+				Ok(()) => return,
+				Err(err) => err,
+			};
+
+			// Reset the backoff if we were serving for a while.
+			if started.elapsed() > MAX_BACKOFF {
+				backoff = MIN_BACKOFF;
+			}
+
+			log::warn!(
+				"failed serving recording: namespace={} retry={:?} error={:#}",
+				replay.namespace(),
+				backoff,
+				err
+			);
+
+			tokio::time::sleep(backoff).await;
+			backoff = (backoff * 2).min(MAX_BACKOFF);
+		}
+	}
+
+	// Returns once the recording is replaced by a newer announce, which isn't retried.
+	async fn announce(
+		replay: Arc<Replay>,
+		mut locals: Locals,
+		api: Option<Api>,
+		announces: Option<Announces>,
+	) -> anyhow::Result<()> {
+		// Each task returns the name of a finished track, if any.
+		let mut tasks = FuturesUnordered::<BoxFuture<'static, anyhow::Result<Option<String>>>>::new();
+
+		let (mut writer, mut request, reader) = Tracks::new(replay.namespace().to_string()).produce();
+
+		if let Some(api) = api.as_ref() {
+			let mut refresh = api.set_origin(reader.namespace.clone()).await?;
+			tasks.push(
+				async move {
+					refresh.run().await.context("failed refreshing origin")?;
+					Ok(None)
+				}
+				.boxed(),
+			);
+		}
+
+		let register = locals.register(reader.clone()).await?;
+		let _forward = announces.as_ref().map(|announces| announces.announce(reader.clone()));
+
+		log::info!("serving recording: namespace={}", replay.namespace());
+
+		// The shared tracks are paced by one clock, restarted once none of them are being served.
+		let mut clock = ReplayClock::default();
+		let mut serving = 0;
+
+		loop {
+			tokio::select! {
+				_ = register.evicted() => {
+					log::warn!("recording replaced by a newer announce: namespace={}", replay.namespace());
+					return Ok(());
+				},
+
+				// Subscriptions without a range share a track, played from the start of the recording.
+				Some(track) = request.next() => {
+					let replay = replay.clone();
+					let clock = clock.clone();
+					serving += 1;
+
+					tasks.push(async move {
+						let name = track.name.clone();
+						if let Err(err) = replay.serve(track, ReplayRange::default(), clock).await {
+							log::warn!("failed serving replay: namespace={} track={} error={:#}", replay.namespace(), name, err);
+						}
+
+						Ok(Some(name))
+					}.boxed());
+				},
+				res = tasks.next(), if !tasks.is_empty() => {
+					// Remove a finished track so the next subscriber starts from the beginning again.
+					if let Some(name) = res.unwrap()? {
+						writer.remove(&name);
+
+						serving -= 1;
+						if serving == 0 {
+							clock = ReplayClock::default();
+						}
+					}
+				},
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	use bytes::Bytes;
+	use moq_transport::serve::{Track, TrackReaderMode};
+
+	#[test]
+	fn range() {
+		let all = ReplayRange::default();
+		assert!(all.contains(0, 0));
+		assert!(all.contains(u64::MAX, u64::MAX));
+
+		let start = SubscribePair {
+			group: SubscribeLocation::Absolute(2),
+			object: SubscribeLocation::Absolute(1),
+		};
+		let end = SubscribePair {
+			group: SubscribeLocation::Absolute(4),
+			object: SubscribeLocation::None,
+		};
+
+		let range = ReplayRange::new(Some(&start), None);
+		assert!(!range.contains(1, 5));
+		assert!(!range.contains(2, 0));
+		assert!(range.contains(2, 1));
+		assert!(range.contains(9, 0));
+
+		let range = ReplayRange::new(Some(&start), Some(&end));
+		assert!(range.contains(3, 0));
+		assert!(range.contains(4, 100));
+		assert!(!range.contains(5, 0));
+
+		// Relative locations aren't meaningful for a recording, so they're ignored.
+		let latest = SubscribePair {
+			group: SubscribeLocation::Latest(0),
+			object: SubscribeLocation::Absolute(0),
+		};
+		assert_eq!(ReplayRange::new(Some(&latest), None), ReplayRange::default());
+	}
+
+	#[test]
+	fn clock() {
+		let clock = ReplayClock::default();
+		let first = clock.deadline(5_000_000);
+
+		// Every track is paced from the first object served by any of them.
+		let audio = clock.clone();
+		assert_eq!(audio.deadline(5_500_000) - first, time::Duration::from_millis(500));
+
+		// A track that starts later catches up instead of being delayed.
+		assert_eq!(audio.deadline(4_000_000), first);
+	}
+
+	#[tokio::test]
+	async fn serve() {
+		let dir = std::env::temp_dir().join(format!("moq-replay-{}", crate::timestamp()));
+		std::fs::create_dir(&dir).unwrap();
+
+		let track = Record::Track {
+			track: ".catalog".to_string(),
+			mode: ArchiveMode::Objects,
+		};
+		let object = |object| Record::Object {
+			track: ".catalog".to_string(),
+			group: 0,
+			object,
+			priority: 0,
+			timestamp: object,
+			size: 1,
+		};
+
+		// The second file repeats the track and the latest catalog, like a rotated recording.
+		let mut first = crate::ArchiveWriter::create(dir.join("1.moqrec"), "live/test")
+			.await
+			.unwrap();
+		first.write(&track, Bytes::new()).await.unwrap();
+		first.write(&object(0), Bytes::from_static(b"a")).await.unwrap();
+		first.finish().await.unwrap();
+
+		let mut second = crate::ArchiveWriter::create(dir.join("2.moqrec"), "live/test")
+			.await
+			.unwrap();
+		second.write(&track, Bytes::new()).await.unwrap();
+		second.write(&object(0), Bytes::from_static(b"a")).await.unwrap();
+		second.write(&object(1), Bytes::from_static(b"b")).await.unwrap();
+		second.finish().await.unwrap();
+
+		let replay = Replay::load(&dir, Pacing::Fast).await.unwrap();
+		assert_eq!(replay.namespace(), "live/test");
+
+		let (writer, reader) = Track::new("live/test".to_string(), ".catalog".to_string()).produce();
+		replay
+			.serve(writer, ReplayRange::default(), ReplayClock::default())
+			.await
+			.unwrap();
+
+		let mut objects = match reader.mode().await.unwrap() {
+			TrackReaderMode::Objects(objects) => objects,
+			_ => panic!("wrong mode"),
+		};
+
+		let mut served = Vec::new();
+		while let Some(mut object) = objects.next().await.unwrap() {
+			served.push((object.object_id, object.read_all().await.unwrap()));
+		}
+		served.sort();
+		assert_eq!(served, [(0, Bytes::from_static(b"a")), (1, Bytes::from_static(b"b"))]);
+
+		// Unknown tracks aren't found.
+		let (writer, reader) = Track::new("live/test".to_string(), "video".to_string()).produce();
+		replay
+			.serve(writer, ReplayRange::default(), ReplayClock::default())
+			.await
+			.unwrap();
+		assert!(matches!(reader.mode().await, Err(ServeError::NotFound)));
+
+		std::fs::remove_dir_all(dir).unwrap();
+	}
+}
@@ -0,0 +1,170 @@
+use std::sync::{Arc, RwLock};
+use std::{fs, path};
+
+use anyhow::Context;
+use serde::Deserialize;
+use url::Url;
+
+/// The contents of a static routing file, used instead of moq-api to find the origin for a namespace.
+///
+/// ```toml
+/// [[route]]
+/// namespace = "live/*"
+/// origins = ["https://relay1.example.com", "https://relay2.example.com"]
+/// ```
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RoutesFile {
+	/// The routes in priority order; the first matching namespace is used.
+	pub route: Vec<Route>,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Route {
+	/// A namespace pattern, where `*` matches any number of characters and `?` matches a single character.
+	pub namespace: String,
+
+	/// The origins for matching namespaces, tried in order until one can be reached.
+	pub origins: Vec<Url>,
+}
+
+impl RoutesFile {
+	pub fn load(path: &path::Path) -> anyhow::Result<Self> {
+		let contents =
+			fs::read_to_string(path).with_context(|| format!("failed to read routes: {}", path.display()))?;
+		let routes: Self =
+			toml::from_str(&contents).with_context(|| format!("failed to parse routes: {}", path.display()))?;
+
+		for route in &routes.route {
+			anyhow::ensure!(
+				!route.origins.is_empty(),
+				"route has no origins: namespace={}",
+				route.namespace
+			);
+		}
+
+		Ok(routes)
+	}
+}
+
+/// A static routing table loaded from a file, which can be reloaded at runtime.
+#[derive(Clone)]
+pub struct Routes {
+	file: Arc<RwLock<RoutesFile>>,
+}
+
+impl Routes {
+	pub fn load(path: path::PathBuf) -> anyhow::Result<Self> {
+		let file = RoutesFile::load(&path)?;
+		log::info!("loaded routes: path={} count={}", path.display(), file.route.len());
+
+		Ok(Self {
+			file: Arc::new(RwLock::new(file)),
+		})
+	}
+
+	/// Reload the routing file, which may be at a new path, keeping the existing routes on error.
+	pub fn reload(&self, path: &path::Path) -> anyhow::Result<()> {
+		let file = RoutesFile::load(path)?;
+		log::info!("reloaded routes: path={} count={}", path.display(), file.route.len());
+
+		*self.file.write().unwrap() = file;
+		Ok(())
+	}
+
+	/// Return the origins for the first route matching the namespace, in order of preference.
+	pub fn get_origins(&self, namespace: &str) -> Vec<Url> {
+		let file = self.file.read().unwrap();
+
+		file.route
+			.iter()
+			.find(|route| glob(&route.namespace, namespace))
+			.map(|route| route.origins.clone())
+			.unwrap_or_default()
+	}
+}
+
+// Match a pattern where `*` matches any number of characters and `?` matches a single character.
+pub(crate) fn glob(pattern: &str, text: &str) -> bool {
+	let pattern: Vec<char> = pattern.chars().collect();
+	let text: Vec<char> = text.chars().collect();
+
+	let (mut p, mut t) = (0, 0);
+
+	// The position of the last `*` and the text it matched up to, so we can backtrack.
+	let mut star = None;
+
+	while t < text.len() {
+		match pattern.get(p) {
+			Some('*') => {
+				star = Some((p, t));
+				p += 1;
+			}
+			Some(&c) if c == '?' || c == text[t] => {
+				p += 1;
+				t += 1;
+			}
+			_ => match star {
+				Some((sp, st)) => {
+					// Let the last `*` match one more character.
+					p = sp + 1;
+					t = st + 1;
+					star = Some((sp, st + 1));
+				}
+				None => return false,
+			},
+		}
+	}
+
+	pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn patterns() {
+		assert!(glob("live", "live"));
+		assert!(!glob("live", "live/foo"));
+		assert!(glob("live/*", "live/foo"));
+		assert!(glob("live/*", "live/foo/bar"));
+		assert!(!glob("live/*", "vod/foo"));
+		assert!(glob("*", ""));
+		assert!(glob("*/bar", "live/foo/bar"));
+		assert!(glob("live/?", "live/1"));
+		assert!(!glob("live/?", "live/12"));
+		assert!(glob("a*b*c", "aXbYbZc"));
+		assert!(!glob("a*b*c", "aXbYbZ"));
+	}
+
+	#[test]
+	fn first_match() {
+		let file: RoutesFile = toml::from_str(
+			r#"
+			[[route]]
+			namespace = "live/special"
+			origins = ["https://special.example.com"]
+
+			[[route]]
+			namespace = "live/*"
+			origins = ["https://primary.example.com", "https://backup.example.com"]
+			"#,
+		)
+		.unwrap();
+
+		let routes = Routes {
+			file: Arc::new(RwLock::new(file)),
+		};
+
+		let origins = routes.get_origins("live/special");
+		assert_eq!(origins, vec![Url::parse("https://special.example.com").unwrap()]);
+
+		let origins = routes.get_origins("live/other");
+		assert_eq!(origins.len(), 2);
+		assert_eq!(origins[0].host_str(), Some("primary.example.com"));
+
+		assert!(routes.get_origins("vod/other").is_empty());
+	}
+}
@@ -1,12 +1,15 @@
 use futures::{stream::FuturesUnordered, FutureExt, StreamExt};
 use moq_transport::session::SessionError;
 
-use crate::{Consumer, Producer};
+use crate::{Consumer, Drain, Producer};
 
 pub struct Session {
 	pub session: moq_transport::session::Session,
 	pub producer: Option<Producer>,
 	pub consumer: Option<Consumer>,
+
+	/// Send a GOAWAY when the relay starts draining.
+	pub drain: Option<Drain>,
 }
 
 impl Session {
@@ -14,6 +17,28 @@ impl Session {
 		let mut tasks = FuturesUnordered::new();
 		tasks.push(self.session.run().boxed());
 
+		if let Some(drain) = self.drain {
+			let mut producer = self.producer.clone();
+			let mut consumer = self.consumer.clone();
+
+			tasks.push(
+				async move {
+					drain.wait().await;
+
+					let url = drain.url();
+					if let Some(producer) = producer.as_mut() {
+						producer.go_away(url);
+					} else if let Some(consumer) = consumer.as_mut() {
+						consumer.go_away(url);
+					}
+
+					// Keep running until the client disconnects or the relay exits.
+					futures::future::pending().await
+				}
+				.boxed(),
+			);
+		}
+
 		if let Some(producer) = self.producer {
 			tasks.push(producer.run().boxed());
 		}
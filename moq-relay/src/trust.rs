@@ -0,0 +1,137 @@
+use std::sync::{Arc, RwLock};
+
+use moq_native::quic;
+
+use crate::Mesh;
+
+/// Decides which sessions are from other relays, authenticated using mutual TLS or a shared secret.
+///
+/// Trusted sessions skip the per-session limits and quotas, and their announces are forwarded like any client.
+/// Mesh sessions must be trusted or present the mesh secret, since any client can use the mesh path.
+/// The names and secret can be changed when reloading, shared by every clone.
+#[derive(Clone, Default)]
+pub struct Trust {
+	// True if client certificates are requested and verified.
+	enabled: bool,
+
+	rules: Arc<RwLock<TrustRules>>,
+}
+
+#[derive(Default)]
+struct TrustRules {
+	// Only trust certificates for these names, or any verified certificate if empty.
+	names: Vec<String>,
+
+	// The secret that mesh peers include in their URL, if configured.
+	secret: Option<String>,
+}
+
+impl Trust {
+	pub fn new(enabled: bool, names: Vec<String>, secret: Option<String>) -> Self {
+		Self {
+			enabled,
+			rules: Arc::new(RwLock::new(TrustRules { names, secret })),
+		}
+	}
+
+	/// Replace the trusted names and mesh secret, applying to any new sessions.
+	pub fn update(&self, names: Vec<String>, secret: Option<String>) {
+		*self.rules.write().unwrap() = TrustRules { names, secret };
+	}
+
+	/// Returns true if the peer presented a verified certificate for one of the trusted names.
+	pub fn is_trusted(&self, peer: &quic::Peer) -> bool {
+		let identity = match &peer.identity {
+			Some(identity) if self.enabled => identity,
+			_ => return false,
+		};
+
+		let rules = self.rules.read().unwrap();
+		rules.names.is_empty() || identity.names.iter().any(|name| rules.names.contains(name))
+	}
+
+	/// Returns true if the session is from another relay in the mesh.
+	///
+	/// The session must use the mesh path and either present the mesh secret or be trusted via mutual TLS.
+	/// Without either configured, no session is ever treated as a peer.
+	pub fn is_peer(&self, peer: &quic::Peer) -> bool {
+		let url = match &peer.url {
+			Some(url) if Mesh::is_peer(url) => url,
+			_ => return false,
+		};
+
+		let secret = self
+			.rules
+			.read()
+			.unwrap()
+			.secret
+			.as_ref()
+			.is_some_and(|secret| Mesh::has_secret(url, secret));
+		secret || self.is_trusted(peer)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use moq_native::tls;
+
+	fn peer(path: &str, names: Option<&[&str]>) -> quic::Peer {
+		quic::Peer {
+			addr: "127.0.0.1:443".parse().unwrap(),
+			url: Some(format!("https://localhost{}", path).parse().unwrap()),
+			identity: names.map(|names| tls::Identity {
+				subject: String::new(),
+				names: names.iter().map(|name| name.to_string()).collect(),
+				fingerprint: String::new(),
+			}),
+		}
+	}
+
+	#[test]
+	fn trust() {
+		let disabled = Trust::default();
+		assert!(!disabled.is_trusted(&peer("/", Some(&["relay"]))));
+		assert!(!disabled.is_peer(&peer("/.mesh", None)));
+
+		let any = Trust::new(true, Vec::new(), None);
+		assert!(any.is_trusted(&peer("/", Some(&["relay"]))));
+		assert!(!any.is_trusted(&peer("/", None)));
+		assert!(!any.is_peer(&peer("/.mesh", None)));
+		assert!(any.is_peer(&peer("/.mesh", Some(&["relay"]))));
+
+		let named = Trust::new(true, vec!["relay".to_string()], None);
+		assert!(named.is_trusted(&peer("/", Some(&["other", "relay"]))));
+		assert!(!named.is_trusted(&peer("/", Some(&["other"]))));
+		assert!(!named.is_peer(&peer("/.mesh", Some(&["other"]))));
+	}
+
+	#[test]
+	fn secret() {
+		let secret = Trust::new(false, Vec::new(), Some("hunter2".to_string()));
+		assert!(secret.is_peer(&peer("/.mesh/hunter2", None)));
+		assert!(!secret.is_peer(&peer("/.mesh/wrong", None)));
+		assert!(!secret.is_peer(&peer("/.mesh", None)));
+		assert!(!secret.is_peer(&peer("/hunter2", None)));
+
+		// Either mutual TLS or the secret is enough.
+		let both = Trust::new(true, vec!["relay".to_string()], Some("hunter2".to_string()));
+		assert!(both.is_peer(&peer("/.mesh/hunter2", None)));
+		assert!(both.is_peer(&peer("/.mesh", Some(&["relay"]))));
+		assert!(!both.is_peer(&peer("/.mesh", Some(&["other"]))));
+	}
+
+	#[test]
+	fn update() {
+		let trust = Trust::new(true, vec!["relay".to_string()], Some("hunter2".to_string()));
+		let clone = trust.clone();
+
+		trust.update(vec!["other".to_string()], Some("swordfish".to_string()));
+
+		// Clones share the rules, so a reload applies to every new session.
+		assert!(!clone.is_trusted(&peer("/", Some(&["relay"]))));
+		assert!(clone.is_trusted(&peer("/", Some(&["other"]))));
+		assert!(!clone.is_peer(&peer("/.mesh/hunter2", None)));
+		assert!(clone.is_peer(&peer("/.mesh/swordfish", None)));
+	}
+}
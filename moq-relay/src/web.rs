@@ -1,7 +1,8 @@
 use std::{net, sync::Arc};
 
-use axum::{extract::State, http::Method, response::IntoResponse, routing::get, Router};
+use axum::{extract::State, http::Method, response::IntoResponse, routing::get, Json, Router};
 use hyper_serve::tls_rustls::RustlsAcceptor;
+use moq_native::tls::ServeCerts;
 use tower_http::cors::{Any, CorsLayer};
 
 pub struct WebConfig {
@@ -18,18 +19,16 @@ pub struct Web {
 
 impl Web {
 	pub fn new(config: WebConfig) -> Self {
-		// Get the first certificate's fingerprint.
-		// TODO serve all of them so we can support multiple signature algorithms.
-		let fingerprint = config.tls.fingerprints.first().expect("missing certificate").clone();
-
 		let mut tls = config.tls.server.expect("missing server configuration");
 		tls.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
 		let tls = hyper_serve::tls_rustls::RustlsConfig::from_config(Arc::new(tls));
 
+		// Serve the certificates on demand, so they're up to date after a reload.
 		let app = Router::new()
 			.route("/fingerprint", get(serve_fingerprint))
+			.route("/fingerprints", get(serve_fingerprints))
 			.layer(CorsLayer::new().allow_origin(Any).allow_methods([Method::GET]))
-			.with_state(fingerprint);
+			.with_state(config.tls.certs);
 
 		let server = hyper_serve::bind_rustls(config.bind, tls);
 
@@ -42,6 +41,12 @@ impl Web {
 	}
 }
 
-async fn serve_fingerprint(State(fingerprint): State<String>) -> impl IntoResponse {
-	fingerprint
+// Serve the first certificate's fingerprint as plain text, for backwards compatibility.
+async fn serve_fingerprint(State(certs): State<Arc<ServeCerts>>) -> impl IntoResponse {
+	certs.fingerprints().into_iter().next().unwrap_or_default()
+}
+
+// Serve every certificate so clients can pick one with a supported signature algorithm.
+async fn serve_fingerprints(State(certs): State<Arc<ServeCerts>>) -> impl IntoResponse {
+	Json(certs.describe())
 }
@@ -211,8 +211,11 @@ impl Session {
 				Err(msg) => msg,
 			};
 
-			// TODO GOAWAY
-			unimplemented!("unknown message context: {:?}", msg)
+			match msg {
+				// TODO reconnect to the provided URL
+				message::Message::GoAway(msg) => log::info!("received GOAWAY: url={:?}", msg.url),
+				_ => unimplemented!("unknown message context: {:?}", msg),
+			}
 		}
 	}
 
@@ -153,6 +153,14 @@ impl Publisher {
 		self.unknown.pop().await
 	}
 
+	/// Send a GOAWAY, asking the peer to reconnect to the provided URL (or the same URL if empty).
+	pub fn go_away(&mut self, url: String) {
+		// The queue is only closed once the session is, so there's nobody left to tell.
+		if let Err(msg) = self.outgoing.push(message::GoAway { url }.into()) {
+			log::debug!("failed to send GOAWAY, session closed: {:?}", msg);
+		}
+	}
+
 	pub(crate) fn recv_message(&mut self, msg: message::Subscriber) -> Result<(), SessionError> {
 		let res = match msg {
 			message::Subscriber::AnnounceOk(msg) => self.recv_announce_ok(msg),
@@ -98,6 +98,22 @@ impl Subscribed {
 		}
 	}
 
+	/// The requested start of the subscription, only set for the AbsoluteStart and AbsoluteRange filters.
+	pub fn start(&self) -> Option<&message::SubscribePair> {
+		match self.msg.filter_type {
+			message::FilterType::AbsoluteStart | message::FilterType::AbsoluteRange => self.msg.start.as_ref(),
+			_ => None,
+		}
+	}
+
+	/// The requested end of the subscription, only set for the AbsoluteRange filter.
+	pub fn end(&self) -> Option<&message::SubscribePair> {
+		match self.msg.filter_type {
+			message::FilterType::AbsoluteRange => self.msg.end.as_ref(),
+			_ => None,
+		}
+	}
+
 	pub fn close(self, err: ServeError) -> Result<(), ServeError> {
 		let state = self.state.lock();
 		state.closed.clone()?;
@@ -62,6 +62,14 @@ impl Subscriber {
 		send.closed().await
 	}
 
+	/// Send a GOAWAY, asking the peer to reconnect to the provided URL (or the same URL if empty).
+	pub fn go_away(&mut self, url: String) {
+		// The queue is only closed once the session is, so there's nobody left to tell.
+		if let Err(msg) = self.outgoing.push(message::GoAway { url }.into()) {
+			log::debug!("failed to send GOAWAY, session closed: {:?}", msg);
+		}
+	}
+
 	pub(super) fn send_message<M: Into<message::Subscriber>>(&mut self, msg: M) {
 		let msg = msg.into();
 